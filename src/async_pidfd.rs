@@ -0,0 +1,68 @@
+use crate::sys::{c::EIO, Errno};
+use crate::{ChildStatus, PidFd};
+use tokio::io::{unix::AsyncFd, Interest};
+
+/// A [`PidFd`] registered with the reactor of a tokio runtime.
+#[derive(Debug)]
+pub struct AsyncPidFd(AsyncFd<PidFd>);
+
+impl AsyncPidFd {
+    /// Registers `pidfd` with the reactor of the current tokio runtime.
+    ///
+    /// Panics when called outside of a runtime with IO enabled.
+    pub fn new(pidfd: PidFd) -> Result<Self, Errno> {
+        AsyncFd::with_interest(pidfd, Interest::READABLE)
+            .map(Self)
+            .map_err(|err| Errno(err.raw_os_error().unwrap_or(EIO)))
+    }
+
+    /// Returns the registered pidfd.
+    pub fn get_ref(&self) -> &PidFd {
+        self.0.get_ref()
+    }
+
+    /// Deregisters the pidfd from the reactor.
+    pub fn into_inner(self) -> PidFd {
+        self.0.into_inner()
+    }
+
+    /// Waits for the child to exit without blocking the runtime. The process must be a child of
+    /// the caller.
+    pub async fn wait(&self) -> Result<ChildStatus, Errno> {
+        loop {
+            let mut guard = self
+                .0
+                .readable()
+                .await
+                .map_err(|err| Errno(err.raw_os_error().unwrap_or(EIO)))?;
+            match guard.get_inner().try_wait()? {
+                Some(status) => return Ok(status),
+                None => guard.clear_ready(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c;
+    use crate::Clone3;
+    use std::{thread, time::Duration};
+
+    #[tokio::test]
+    async fn wait() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let pidfd = match unsafe { clone3.spawn() }.unwrap() {
+            None => {
+                thread::sleep(Duration::from_millis(50));
+                unsafe { c::_exit(7) }
+            }
+            Some(mut child) => child.take_pidfd().unwrap(),
+        };
+        let pidfd = AsyncPidFd::new(pidfd).unwrap();
+        assert_eq!(pidfd.wait().await, Ok(ChildStatus::Exited(7)));
+    }
+}
@@ -0,0 +1,320 @@
+use crate::sys::{
+    c::{
+        self, CGROUP2_SUPER_MAGIC, EBUSY, EINVAL, EMEDIUMTYPE, ENOENT, ENOTDIR, EOPNOTSUPP,
+        O_CLOEXEC, O_DIRECTORY, O_PATH, O_RDONLY, O_WRONLY, S_IFDIR, S_IFMT, W_OK,
+    },
+    Errno,
+};
+use crate::{setup::check, sys::AT_EACCESS};
+use std::{
+    ffi::{CStr, CString, OsStr},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    },
+    path::{Path, PathBuf},
+};
+
+/// A cgroup2 created for a child and removed when dropped.
+///
+/// Pass it to [`scoped_cgroup`](crate::Clone3::scoped_cgroup) to create the child in it. The
+/// [`Child`](crate::Child) returned by [`spawn`](crate::Clone3::spawn) then owns the cgroup and
+/// removes it when dropped. Removing fails silently while processes are in the cgroup so the
+/// child should be waited for first.
+#[derive(Debug)]
+pub struct ScopedCgroup {
+    path: PathBuf,
+    dir: OwnedFd,
+}
+
+impl ScopedCgroup {
+    /// Creates the cgroup `name` below the cgroup2 directory `parent`.
+    ///
+    /// The limits can only be set for controllers enabled in `cgroup.subtree_control` of
+    /// `parent`. Fails with `EEXIST` if the cgroup already exists.
+    pub fn create<P: AsRef<Path>, S: AsRef<OsStr>>(parent: P, name: S) -> Result<Self, Errno> {
+        let path = parent.as_ref().join(name.as_ref());
+        let path_c = path_to_cstring(&path)?;
+        check(unsafe { c::mkdir(path_c.as_ptr(), 0o755) })?;
+        let dir = unsafe { c::open(path_c.as_ptr(), O_RDONLY | O_DIRECTORY | O_CLOEXEC) };
+        if dir == -1 {
+            let errno = Errno::default();
+            unsafe { c::rmdir(path_c.as_ptr()) };
+            return Err(errno);
+        }
+        let dir = unsafe { OwnedFd::from_raw_fd(dir) };
+        Ok(Self { path, dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `memory.max`. `None` removes the limit.
+    pub fn set_memory_max(&self, bytes: Option<u64>) -> Result<(), Errno> {
+        self.write(c"memory.max", &limit(bytes))
+    }
+
+    /// Writes `cpu.max` allowing `quota` microseconds of cpu time per `period` microseconds.
+    /// `None` removes the limit.
+    pub fn set_cpu_max(&self, quota: Option<u64>, period: u64) -> Result<(), Errno> {
+        self.write(c"cpu.max", &format!("{} {}", limit(quota), period))
+    }
+
+    /// Writes `pids.max`. `None` removes the limit.
+    pub fn set_pids_max(&self, pids: Option<u64>) -> Result<(), Errno> {
+        self.write(c"pids.max", &limit(pids))
+    }
+
+    fn write(&self, file: &CStr, value: &str) -> Result<(), Errno> {
+        unsafe {
+            let fd = check(c::openat(
+                self.dir.as_raw_fd(),
+                file.as_ptr(),
+                O_WRONLY | O_CLOEXEC,
+            ))?;
+            let fd = OwnedFd::from_raw_fd(fd);
+            let written = c::write(fd.as_raw_fd(), value.as_ptr() as *const _, value.len());
+            if written == -1 {
+                return Err(Errno::default());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsFd for ScopedCgroup {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.dir.as_fd()
+    }
+}
+
+impl Drop for ScopedCgroup {
+    fn drop(&mut self) {
+        if let Ok(path) = path_to_cstring(&self.path) {
+            unsafe { c::rmdir(path.as_ptr()) };
+        }
+    }
+}
+
+/// An fd of a cgroup2 directory that was checked to be usable with
+/// [`flag_into_cgroup`](crate::Clone3::flag_into_cgroup).
+///
+/// clone3 reports an unsuitable fd with a bare `EBADF`, `EOPNOTSUPP` or `EBUSY`. The
+/// constructors check for the common causes first and fail with:
+///
+/// - `ENOTDIR` if the fd is not a directory.
+/// - `EMEDIUMTYPE` if the directory is not on a cgroup2 file system.
+/// - `EOPNOTSUPP` if the cgroup is an invalid domain and cannot contain processes.
+/// - `EBUSY` if the cgroup is not the root and has controllers enabled in
+///   `cgroup.subtree_control`. Processes can only be in the leaves of such a hierarchy.
+/// - `EACCES` if the caller cannot write `cgroup.procs` of the cgroup.
+#[derive(Debug)]
+pub struct CgroupFd(OwnedFd);
+
+impl CgroupFd {
+    pub fn new(fd: OwnedFd) -> Result<Self, Errno> {
+        unsafe {
+            let mut stat: c::stat = mem::zeroed();
+            check(c::fstat(fd.as_raw_fd(), &mut stat))?;
+            if stat.st_mode & S_IFMT != S_IFDIR {
+                return Err(Errno(ENOTDIR));
+            }
+            let mut stat: c::statfs = mem::zeroed();
+            check(c::fstatfs(fd.as_raw_fd(), &mut stat))?;
+            if stat.f_type != CGROUP2_SUPER_MAGIC {
+                return Err(Errno(EMEDIUMTYPE));
+            }
+        }
+        // The root cgroup has no type and may have controllers enabled.
+        match read_small(&fd, c"cgroup.type") {
+            Ok(kind) if kind == b"domain invalid\n" => return Err(Errno(EOPNOTSUPP)),
+            Ok(kind) if kind == b"domain\n" => {
+                let controllers = read_small(&fd, c"cgroup.subtree_control")?;
+                if controllers.iter().any(|byte| !byte.is_ascii_whitespace()) {
+                    return Err(Errno(EBUSY));
+                }
+            }
+            Ok(_) | Err(Errno(ENOENT)) => (),
+            Err(errno) => return Err(errno),
+        }
+        let procs = c"cgroup.procs".as_ptr();
+        check(unsafe { c::faccessat(fd.as_raw_fd(), procs, W_OK, AT_EACCESS) })?;
+        Ok(Self(fd))
+    }
+
+    /// Opens the directory at `path` with `O_PATH | O_DIRECTORY | O_CLOEXEC` and checks it like
+    /// [`new`](Self::new).
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Errno> {
+        let path = path_to_cstring(path.as_ref())?;
+        let fd = check(unsafe { c::open(path.as_ptr(), O_PATH | O_DIRECTORY | O_CLOEXEC) })?;
+        Self::new(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Opens and checks the directory of a cgroup managed by the `cgroups-rs` crate.
+    ///
+    /// Fails with `EMEDIUMTYPE` if the cgroup is not in the cgroup2 hierarchy.
+    #[cfg(feature = "cgroups-rs")]
+    pub fn from_cgroups_rs(cgroup: &cgroups_rs::Cgroup) -> Result<Self, Errno> {
+        use cgroups_rs::Hierarchy;
+        if !cgroup.v2() {
+            return Err(Errno(EMEDIUMTYPE));
+        }
+        let root = cgroups_rs::hierarchies::V2::new().root();
+        Self::open(root.join(cgroup.path()))
+    }
+}
+
+impl AsFd for CgroupFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl From<CgroupFd> for OwnedFd {
+    fn from(cgroup: CgroupFd) -> Self {
+        cgroup.0
+    }
+}
+
+/// Reads a file of at most 64 bytes in the directory `dir`.
+fn read_small(dir: &OwnedFd, file: &CStr) -> Result<Vec<u8>, Errno> {
+    let mut bytes = [0u8; 64];
+    let n = unsafe {
+        let fd = check(c::openat(
+            dir.as_raw_fd(),
+            file.as_ptr(),
+            O_RDONLY | O_CLOEXEC,
+        ))?;
+        let fd = OwnedFd::from_raw_fd(fd);
+        c::read(fd.as_raw_fd(), bytes.as_mut_ptr() as *mut _, bytes.len())
+    };
+    if n == -1 {
+        return Err(Errno::default());
+    }
+    Ok(bytes[..n as usize].to_vec())
+}
+
+fn limit(value: Option<u64>) -> String {
+    value.map_or_else(|| "max".to_owned(), |value| value.to_string())
+}
+
+fn path_to_cstring(path: &Path) -> Result<CString, Errno> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| Errno(EINVAL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::ENOENT;
+    use crate::test_util::assert_child;
+    use crate::{ChildStatus, Clone3, Clone3Error};
+    use std::{fs, os::raw::c_int};
+
+    /// The cgroup2 directory of the current process.
+    fn current_cgroup() -> Option<PathBuf> {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+        let mount_point = mountinfo.lines().find_map(|line| {
+            let (mount, filesystem) = line.split_once(" - ")?;
+            filesystem
+                .starts_with("cgroup2 ")
+                .then(|| mount.split(' ').nth(4))?
+        })?;
+        let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+        let cgroup = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+        Some(Path::new(mount_point).join(cgroup.trim_start_matches('/')))
+    }
+
+    #[test]
+    fn spawn_into_scoped_cgroup() {
+        // Creating cgroups usually requires root.
+        let parent = match current_cgroup() {
+            Some(parent) if unsafe { c::geteuid() } == 0 => parent,
+            _ => return,
+        };
+        let name = format!("clone3-scoped-{}", std::process::id());
+        let cgroup = ScopedCgroup::create(&parent, &name).unwrap();
+        let path = cgroup.path().to_owned();
+        let controllers = fs::read_to_string(path.join("cgroup.controllers")).unwrap();
+        if controllers.split_whitespace().any(|c| c == "pids") {
+            cgroup.set_pids_max(Some(16)).unwrap();
+            assert_eq!(fs::read_to_string(path.join("pids.max")).unwrap(), "16\n");
+        }
+        // The last line is the one of cgroup2.
+        let expected = format!("/{}\n", name);
+        CgroupFd::open(&path).unwrap();
+        let mut clone3 = Clone3::default();
+        clone3.scoped_cgroup(cgroup);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                let fd = c::open(c"/proc/self/cgroup".as_ptr(), O_RDONLY | O_CLOEXEC);
+                let mut cgroups = [0u8; 4096];
+                let n = c::read(fd, cgroups.as_mut_ptr() as *mut _, cgroups.len());
+                let cgroups = &cgroups[..n.max(0) as usize];
+                let found = cgroups.ends_with(expected.as_bytes());
+                c::_exit(found as c_int)
+            },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+        assert!(path.exists());
+        drop(child);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn into_cgroup_path() {
+        let mut clone3 = Clone3::default();
+        clone3.flag_into_cgroup_path("/clone3-does-not-exist");
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::System(Errno(ENOENT))) => (),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+        // Moving a process into a cgroup usually requires root.
+        let cgroup = match current_cgroup() {
+            Some(cgroup) if unsafe { c::geteuid() } == 0 => cgroup,
+            _ => return,
+        };
+        clone3.flag_into_cgroup_path(cgroup);
+        assert_child(&mut clone3, || true);
+    }
+
+    #[test]
+    fn cgroup_fd_validation() {
+        let file = OwnedFd::from(fs::File::open("/dev/null").unwrap());
+        assert_eq!(CgroupFd::new(file).unwrap_err(), Errno(ENOTDIR));
+        let dir = CgroupFd::open("/proc").unwrap_err();
+        assert_eq!(dir, Errno(EMEDIUMTYPE));
+        if let Some(cgroup) = current_cgroup().filter(|_| unsafe { c::geteuid() } == 0) {
+            let cgroup = CgroupFd::open(cgroup).unwrap();
+            let mut clone3 = Clone3::default();
+            clone3.flag_into_cgroup(cgroup.as_fd());
+            assert_child(&mut clone3, || true);
+        }
+    }
+
+    #[cfg(feature = "cgroups-rs")]
+    #[test]
+    fn cgroups_rs_cgroup() {
+        use cgroups_rs::{hierarchies, Cgroup};
+        let v1 = Cgroup::load(Box::new(hierarchies::V1::new()), "");
+        assert_eq!(
+            CgroupFd::from_cgroups_rs(&v1).unwrap_err(),
+            Errno(EMEDIUMTYPE)
+        );
+        // Moving a process into a cgroup usually requires root.
+        if !hierarchies::is_cgroup2_unified_mode() || unsafe { c::geteuid() } != 0 {
+            return;
+        }
+        let cgroups = fs::read_to_string("/proc/self/cgroup").unwrap();
+        let current = cgroups.lines().find_map(|line| line.strip_prefix("0::"));
+        let cgroup = Cgroup::load(
+            hierarchies::auto(),
+            current.unwrap().trim_start_matches('/'),
+        );
+        let mut clone3 = Clone3::default();
+        clone3.flag_into_cgroups_rs(&cgroup);
+        assert_child(&mut clone3, || true);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::sys::{
+    c::{self, pid_t},
+    Errno,
+};
+use crate::{
+    ChildStatus, Namespace, ParentBarrier, Pid, PidFd, PidFdInfo, ResourceUsage, ScopedCgroup,
+    WaitOptions,
+};
+use std::{
+    fs::File,
+    os::{
+        raw::c_int,
+        unix::io::{AsFd, BorrowedFd, OwnedFd},
+    },
+    path::Path,
+};
+
+/// Handle to a child process or thread created by [`Clone3::spawn`](crate::Clone3::spawn).
+///
+/// Dropping the handle neither kills nor waits for the child. It removes the cgroup set with
+/// [`scoped_cgroup`](crate::Clone3::scoped_cgroup).
+#[derive(Debug)]
+pub struct Child {
+    pid: pid_t,
+    pidfd: Option<OwnedFd>,
+    status: Option<ChildStatus>,
+    pub(crate) stdio: [Option<File>; 3],
+    pub(crate) pty_master: Option<File>,
+    pub(crate) barrier: Option<ParentBarrier>,
+    // Removed when the handle is dropped.
+    pub(crate) cgroup: Option<ScopedCgroup>,
+}
+
+impl Child {
+    pub(crate) fn new(pid: pid_t, pidfd: Option<OwnedFd>) -> Self {
+        Self {
+            pid,
+            pidfd,
+            status: None,
+            stdio: Default::default(),
+            pty_master: None,
+            barrier: None,
+            cgroup: None,
+        }
+    }
+
+    /// Creates a handle for the existing process `pid` by opening a pidfd for it.
+    ///
+    /// Use this for processes that were not created through [`spawn`](crate::Clone3::spawn).
+    /// Waiting only works for children of the calling process. Signaling works for all processes
+    /// the caller has permission to signal.
+    pub fn from_pid(pid: pid_t) -> Result<Self, Errno> {
+        let pidfd = crate::pidfd_open(pid, 0)?;
+        Ok(Self::new(pid, Some(pidfd)))
+    }
+
+    /// The pid of the child.
+    pub fn pid(&self) -> Pid {
+        Pid::from_raw_unchecked(self.pid)
+    }
+
+    /// The pidfd of the child if `PIDFD` was set.
+    pub fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+        self.pidfd.as_ref().map(AsFd::as_fd)
+    }
+
+    /// Takes ownership of the pidfd of the child if `PIDFD` was set.
+    pub fn take_pidfd(&mut self) -> Option<PidFd> {
+        self.pidfd.take().map(PidFd::from)
+    }
+
+    /// Takes the parent end of the stdin pipe if stdin is [`Stdio::Piped`](crate::Stdio::Piped).
+    pub fn take_stdin(&mut self) -> Option<File> {
+        self.stdio[0].take()
+    }
+
+    /// Takes the parent end of the stdout pipe if stdout is [`Stdio::Piped`](crate::Stdio::Piped).
+    pub fn take_stdout(&mut self) -> Option<File> {
+        self.stdio[1].take()
+    }
+
+    /// Takes the parent end of the stderr pipe if stderr is [`Stdio::Piped`](crate::Stdio::Piped).
+    pub fn take_stderr(&mut self) -> Option<File> {
+        self.stdio[2].take()
+    }
+
+    /// Takes the master of the pty of the child if [`pty`](crate::Clone3::pty) was set.
+    pub fn take_pty_master(&mut self) -> Option<File> {
+        self.pty_master.take()
+    }
+
+    /// Takes the parent side of the barrier if [`barrier`](crate::Clone3::barrier) was set.
+    pub fn take_barrier(&mut self) -> Option<ParentBarrier> {
+        self.barrier.take()
+    }
+
+    /// Keeps the namespace of type `namespace` of the child alive after it exits by bind mounting
+    /// it onto `path`. See [`pin_namespace`](crate::pin_namespace).
+    pub fn pin_namespace<P: AsRef<Path>>(
+        &self,
+        namespace: Namespace,
+        path: P,
+    ) -> Result<(), Errno> {
+        crate::pin_namespace(self.pid, namespace, path)
+    }
+
+    /// Queries information about the child through its pidfd.
+    ///
+    /// Returns `None` if the pidfd is not available. See
+    /// [`pidfd_get_info`](crate::pidfd_get_info).
+    pub fn info(&self) -> Option<Result<PidFdInfo, Errno>> {
+        self.pidfd
+            .as_ref()
+            .map(|pidfd| crate::pidfd_get_info(pidfd.as_fd()))
+    }
+
+    /// Waits for the child to exit.
+    ///
+    /// The child is waited on through its pidfd if available which cannot be confused with an
+    /// unrelated process reusing the pid. After the child has been reaped the status is cached and
+    /// returned by further calls.
+    pub fn wait(&mut self) -> Result<ChildStatus, Errno> {
+        if let Some(status) = self.status {
+            return Ok(status);
+        }
+        let status = self
+            .waitid(WaitOptions::empty())?
+            .expect("waitid without WNOHANG returned no status");
+        self.status = Some(status);
+        Ok(status)
+    }
+
+    /// Waits for the child to exit and returns the resources it used.
+    ///
+    /// Returns `None` for the usage if the child has already been reaped by an earlier call.
+    pub fn wait_with_rusage(&mut self) -> Result<(ChildStatus, Option<ResourceUsage>), Errno> {
+        if let Some(status) = self.status {
+            return Ok((status, None));
+        }
+        let (status, usage) = match &self.pidfd {
+            Some(pidfd) => crate::wait_pidfd_with_rusage(pidfd.as_fd(), WaitOptions::empty()),
+            None => crate::wait_pid_with_rusage(self.pid, WaitOptions::empty()),
+        }?
+        .expect("waitid without WNOHANG returned no status");
+        self.status = Some(status);
+        Ok((status, Some(usage)))
+    }
+
+    /// Returns the status of the child if it has exited without blocking.
+    pub fn try_wait(&mut self) -> Result<Option<ChildStatus>, Errno> {
+        if self.status.is_none() {
+            self.status = self.waitid(WaitOptions::NOHANG)?;
+        }
+        Ok(self.status)
+    }
+
+    /// Sends `signal` to the child.
+    ///
+    /// Uses [`pidfd_send_signal`](crate::pidfd_send_signal) if the pidfd is available. Does nothing
+    /// if the child has already been reaped because its pid might have been reused.
+    pub fn kill(&mut self, signal: c_int) -> Result<(), Errno> {
+        if self.status.is_some() {
+            return Ok(());
+        }
+        if let Some(pidfd) = &self.pidfd {
+            return crate::pidfd_send_signal(pidfd.as_fd(), signal);
+        }
+        if unsafe { c::kill(self.pid, signal) } == -1 {
+            return Err(Errno::default());
+        }
+        Ok(())
+    }
+
+    fn waitid(&self, options: WaitOptions) -> Result<Option<ChildStatus>, Errno> {
+        match &self.pidfd {
+            Some(pidfd) => crate::wait_pidfd(pidfd.as_fd(), options),
+            None => crate::wait_pid(self.pid, options),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::SIGKILL;
+    use crate::Clone3;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn wait_for_exit_code() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe { c::_exit(3) },
+            Some(child) => child,
+        };
+        assert!(child.pidfd().is_some());
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(3)));
+        assert_eq!(child.try_wait(), Ok(Some(ChildStatus::Exited(3))));
+    }
+
+    #[test]
+    fn kill_with_pidfd() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => loop {
+                unsafe { c::pause() };
+            },
+            Some(child) => child,
+        };
+        child.kill(SIGKILL).unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Signaled(SIGKILL, false)));
+    }
+
+    #[test]
+    fn wait_from_pid() {
+        let pid = match unsafe { Clone3::default().spawn() }.unwrap() {
+            None => unsafe { c::_exit(4) },
+            Some(child) => child.pid(),
+        };
+        let mut child = Child::from_pid(pid.as_raw()).unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(4)));
+    }
+
+    #[test]
+    fn info() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe { c::_exit(5) },
+            Some(child) => child,
+        };
+        let info = match child.info().unwrap() {
+            // PIDFD_GET_INFO requires Linux 6.13.
+            Err(Errno(c::ENOTTY)) => return,
+            info => info.unwrap(),
+        };
+        assert_eq!(info.pid, child.pid().as_raw());
+        assert_eq!(info.ppid, unsafe { c::getpid() });
+        child.wait().unwrap();
+        if let Ok(info) = child.info().unwrap() {
+            assert_eq!(info.exit_status, Some(ChildStatus::Exited(5)));
+        }
+    }
+
+    #[test]
+    fn pidfd_becomes_readable_on_exit() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let pidfd = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe { c::_exit(6) },
+            Some(mut child) => child.take_pidfd().unwrap(),
+        };
+        let mut pollfd = c::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: c::POLLIN,
+            revents: 0,
+        };
+        assert_eq!(unsafe { c::poll(&mut pollfd, 1, -1) }, 1);
+        assert_eq!(pidfd.try_wait(), Ok(Some(ChildStatus::Exited(6))));
+    }
+
+    #[test]
+    fn kill_without_pidfd() {
+        let mut child = match unsafe { Clone3::default().spawn() }.unwrap() {
+            None => loop {
+                unsafe { c::pause() };
+            },
+            Some(child) => child,
+        };
+        assert!(child.pidfd().is_none());
+        child.kill(SIGKILL).unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Signaled(SIGKILL, false)));
+    }
+}
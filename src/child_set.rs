@@ -0,0 +1,194 @@
+use crate::sys::{
+    c::{self, epoll_event, pid_t, EINTR, EPOLLIN, EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL},
+    Errno,
+};
+use crate::{Child, ChildStatus};
+use std::{
+    collections::HashMap,
+    os::{
+        raw::c_int,
+        unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+    time::{Duration, Instant},
+};
+
+/// A set of children that can be waited for together.
+///
+/// The pidfds of the children are registered in one epoll instance so that waiting for any of
+/// them takes a single system call regardless of the number of children.
+#[derive(Debug)]
+pub struct ChildSet {
+    epoll: OwnedFd,
+    children: HashMap<pid_t, Child>,
+}
+
+impl ChildSet {
+    pub fn new() -> Result<Self, Errno> {
+        let epoll = unsafe { c::epoll_create1(EPOLL_CLOEXEC) };
+        if epoll == -1 {
+            return Err(Errno::default());
+        }
+        Ok(Self {
+            epoll: unsafe { OwnedFd::from_raw_fd(epoll) },
+            children: HashMap::new(),
+        })
+    }
+
+    /// Adds `child` to the set.
+    ///
+    /// A pidfd is opened for the child if it was created without `PIDFD`.
+    pub fn insert(&mut self, child: Child) -> Result<(), Errno> {
+        let child = match child.pidfd() {
+            Some(_) => child,
+            None => Child::from_pid(child.pid().as_raw())?,
+        };
+        let pidfd = child.pidfd().expect("child has a pidfd");
+        let mut event = epoll_event {
+            events: EPOLLIN as u32,
+            u64: child.pid().as_raw() as u64,
+        };
+        self.epoll_ctl(EPOLL_CTL_ADD, pidfd.as_raw_fd(), &mut event)?;
+        self.children.insert(child.pid().as_raw(), child);
+        Ok(())
+    }
+
+    /// Removes the child `pid` from the set without waiting for it.
+    pub fn remove(&mut self, pid: pid_t) -> Option<Child> {
+        let child = self.children.remove(&pid)?;
+        if let Some(pidfd) = child.pidfd() {
+            // Closing the pidfd would also remove it but the child might outlive the set.
+            let _ = self.epoll_ctl(
+                EPOLL_CTL_DEL,
+                pidfd.as_raw_fd(),
+                &mut epoll_event { events: 0, u64: 0 },
+            );
+        }
+        Some(child)
+    }
+
+    /// The number of children in the set.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Iterates over the children in the set.
+    pub fn iter(&self) -> impl Iterator<Item = &Child> {
+        self.children.values()
+    }
+
+    /// Waits for any child in the set to exit and removes it from the set.
+    ///
+    /// Returns `None` if the set is empty.
+    pub fn wait_any(&mut self) -> Result<Option<(Child, ChildStatus)>, Errno> {
+        self.wait_until(None)
+    }
+
+    /// Like [`wait_any`](Self::wait_any) but returns `None` if no child exits within `timeout`.
+    pub fn wait_any_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<(Child, ChildStatus)>, Errno> {
+        self.wait_until(Some(Instant::now() + timeout))
+    }
+
+    fn wait_until(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Result<Option<(Child, ChildStatus)>, Errno> {
+        while !self.children.is_empty() {
+            let timeout = match deadline {
+                None => -1,
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    // Round up so that the deadline is not missed by waking up early.
+                    let millis = remaining.as_nanos().div_ceil(1_000_000);
+                    millis.min(c_int::MAX as u128) as c_int
+                }
+            };
+            let mut event = epoll_event { events: 0, u64: 0 };
+            let ready = unsafe { c::epoll_wait(self.epoll.as_raw_fd(), &mut event, 1, timeout) };
+            match ready {
+                -1 if Errno::default().0 == EINTR => continue,
+                -1 => return Err(Errno::default()),
+                0 if timeout == 0 => return Ok(None),
+                0 => continue,
+                _ => (),
+            }
+            let pid = event.u64 as pid_t;
+            let status = match self.children.get_mut(&pid) {
+                Some(child) => child.try_wait()?,
+                None => continue,
+            };
+            if let Some(status) = status {
+                let child = self.remove(pid).expect("child is in the set");
+                return Ok(Some((child, status)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn epoll_ctl(&self, op: c_int, fd: c_int, event: &mut epoll_event) -> Result<(), Errno> {
+        if unsafe { c::epoll_ctl(self.epoll.as_raw_fd(), op, fd, event) } == -1 {
+            return Err(Errno::default());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::SIGKILL;
+    use crate::Clone3;
+
+    #[test]
+    fn wait_any() {
+        let mut set = ChildSet::new().unwrap();
+        for code in 1..4 {
+            match unsafe { Clone3::default().spawn() }.unwrap() {
+                None => unsafe { c::_exit(code) },
+                Some(child) => set.insert(child).unwrap(),
+            }
+        }
+        let mut codes = Vec::new();
+        while let Some((child, status)) = set.wait_any().unwrap() {
+            assert!(child.pidfd().is_some());
+            match status {
+                ChildStatus::Exited(code) => codes.push(code),
+                status => panic!("{:?}", status),
+            }
+        }
+        codes.sort_unstable();
+        assert_eq!(codes, [1, 2, 3]);
+    }
+
+    #[test]
+    fn wait_any_timeout() {
+        let mut set = ChildSet::new().unwrap();
+        match unsafe { Clone3::default().spawn() }.unwrap() {
+            None => loop {
+                unsafe { c::pause() };
+            },
+            Some(child) => set.insert(child).unwrap(),
+        }
+        assert_eq!(
+            set.wait_any_timeout(Duration::from_millis(10))
+                .unwrap()
+                .map(|(_, status)| status),
+            None
+        );
+        let pid = set.iter().next().unwrap().pid();
+        pid.kill(SIGKILL).unwrap();
+        let (child, status) = set
+            .wait_any_timeout(Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+        assert_eq!(child.pid(), pid);
+        assert_eq!(status, ChildStatus::Signaled(SIGKILL, false));
+        assert!(set.is_empty());
+    }
+}
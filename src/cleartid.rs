@@ -0,0 +1,150 @@
+use crate::sys::{
+    c::{self, pid_t, timespec, SYS_futex, EAGAIN, EINTR, ETIMEDOUT, FUTEX_WAIT, FUTEX_WAKE},
+    Errno,
+};
+use std::{
+    os::raw::c_int,
+    ptr,
+    sync::atomic::{AtomicI32, Ordering},
+    time::{Duration, Instant},
+};
+
+/// A tid word for `CHILD_CLEARTID` that can be joined like a thread.
+///
+/// Pass it to [`flag_child_cleartid_handle`](crate::Clone3::flag_child_cleartid_handle). The
+/// kernel writes the tid of the child to the word through `PARENT_SETTID` before the child runs.
+/// When the child exits the kernel clears the word and wakes a futex waiter on it which is how
+/// `pthread_join` works. Unlike waiting with `waitpid` this also works for threads created with
+/// `THREAD` and for children without an exit signal.
+///
+/// The child must share the memory of the caller through `VM` because the kernel clears the word
+/// in the address space of the child. The word is cleared when the child releases that address
+/// space, so also when it calls execve.
+#[derive(Debug, Default)]
+pub struct ClearTidHandle {
+    // Boxed because the kernel writes to this location after the handle may have moved.
+    word: Box<AtomicI32>,
+}
+
+impl ClearTidHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The location passed to the kernel.
+    pub fn as_ptr(&self) -> *mut pid_t {
+        self.word.as_ptr()
+    }
+
+    /// The tid of the child or 0 if the child has not been created yet or has exited.
+    pub fn tid(&self) -> pid_t {
+        self.word.load(Ordering::Acquire)
+    }
+
+    /// Whether the child has exited. Also true if no child has been created with the handle.
+    pub fn is_finished(&self) -> bool {
+        self.tid() == 0
+    }
+
+    /// Waits until the child has exited.
+    pub fn join(&self) -> Result<(), Errno> {
+        self.join_until(None).map(drop)
+    }
+
+    /// Like [`join`](Self::join) but returns `false` if the child has not exited within `timeout`.
+    pub fn join_timeout(&self, timeout: Duration) -> Result<bool, Errno> {
+        self.join_until(Some(Instant::now() + timeout))
+    }
+
+    fn join_until(&self, deadline: Option<Instant>) -> Result<bool, Errno> {
+        loop {
+            let tid = self.tid();
+            if tid == 0 {
+                // The kernel only wakes one waiter so pass the wake up on to other joiners.
+                self.futex(FUTEX_WAKE, c_int::MAX, ptr::null());
+                return Ok(true);
+            }
+            let timeout = match deadline {
+                None => None,
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Ok(false);
+                    }
+                    Some(timespec {
+                        tv_sec: remaining.as_secs() as _,
+                        tv_nsec: remaining.subsec_nanos() as _,
+                    })
+                }
+            };
+            let timeout = timeout
+                .as_ref()
+                .map_or(ptr::null(), |timeout| timeout as *const _);
+            // The kernel wakes the word as a shared futex.
+            if self.futex(FUTEX_WAIT, tid, timeout) == -1 {
+                match Errno::default().0 {
+                    EAGAIN | EINTR | ETIMEDOUT => (),
+                    errno => return Err(Errno(errno)),
+                }
+            }
+        }
+    }
+
+    fn futex(&self, op: c_int, value: c_int, timeout: *const timespec) -> i64 {
+        unsafe { c::syscall(SYS_futex, self.as_ptr(), op, value, timeout) as i64 }
+    }
+}
+
+// The child has to be started on its own stack which is only implemented for these.
+#[cfg(all(test, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+    use crate::sys::c::{SYS_exit, SIGCHLD};
+    use crate::{vfork::clone3_with_entry, Clone3};
+    use std::{
+        os::raw::c_void,
+        sync::{atomic::AtomicBool, Arc},
+        thread,
+    };
+
+    static RELEASE: AtomicBool = AtomicBool::new(false);
+
+    unsafe extern "C" fn wait_for_release(_: *mut c_void) -> ! {
+        while !RELEASE.load(Ordering::Acquire) {
+            c::sched_yield();
+        }
+        c::syscall(SYS_exit, 0);
+        unreachable!()
+    }
+
+    #[test]
+    fn join_vm_child() {
+        let handle = Arc::new(ClearTidHandle::new());
+        assert!(handle.is_finished());
+        let mut stack = vec![0u8; 64 * 1024];
+        let mut clone3 = Clone3::default();
+        clone3
+            .flag_vm(&mut stack)
+            .flag_child_cleartid_handle(&handle)
+            .exit_signal(SIGCHLD as u64);
+        let cl_args = clone3.as_clone_args();
+        let size = clone3.effective_clone_args_size();
+        let pid = unsafe { clone3_with_entry(&cl_args, size, wait_for_release, ptr::null_mut()) };
+        assert!(pid > 0);
+        assert_eq!(handle.tid(), pid as pid_t);
+        assert_eq!(handle.join_timeout(Duration::from_millis(10)), Ok(false));
+        let joiners: Vec<_> = (0..2)
+            .map(|_| {
+                let handle = handle.clone();
+                thread::spawn(move || handle.join())
+            })
+            .collect();
+        RELEASE.store(true, Ordering::Release);
+        handle.join().unwrap();
+        for joiner in joiners {
+            joiner.join().unwrap().unwrap();
+        }
+        assert!(handle.is_finished());
+        unsafe { c::waitpid(pid as pid_t, ptr::null_mut(), 0) };
+    }
+}
@@ -0,0 +1,312 @@
+use crate::sys::{
+    c::{self, EACCES, ENOENT, ENOTDIR},
+    Errno,
+};
+use crate::{
+    setup::{cloexec_pipe_above, read_message, Step},
+    Child, Clone3Error, Flags, OwnedClone3,
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{CString, OsStr, OsString},
+    os::{
+        raw::{c_char, c_int},
+        unix::{
+            ffi::OsStrExt,
+            io::{AsRawFd, OwnedFd, RawFd},
+        },
+    },
+    ptr,
+};
+
+/// Builder that creates a child through clone3 and executes a program in it.
+///
+/// This mirrors `std::process::Command` for the common case of clone3 followed by execve. The
+/// clone3 arguments are configured through [`clone3`](Self::clone3). Everything the child needs is
+/// prepared in the parent so the child only calls execve and `_exit`.
+///
+/// A program without a `/` is searched for in the `PATH` of the environment the child receives.
+#[derive(Debug)]
+pub struct Command {
+    program: OsString,
+    args: Vec<OsString>,
+    env_clear: bool,
+    // `None` removes the variable.
+    env: BTreeMap<OsString, Option<OsString>>,
+    clone3: OwnedClone3,
+}
+
+impl Command {
+    /// Creates a builder for executing `program` with no arguments and the environment of the
+    /// current process.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: vec![program.as_ref().to_owned()],
+            env_clear: false,
+            env: BTreeMap::new(),
+            clone3: OwnedClone3::default(),
+        }
+    }
+
+    /// Sets the first argument which by convention is the program name. Defaults to the program.
+    pub fn arg0<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args[0] = arg.as_ref().to_owned();
+        self
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.env
+            .insert(key.as_ref().to_owned(), Some(value.as_ref().to_owned()));
+        self
+    }
+
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, value) in vars {
+            self.env(key, value);
+        }
+        self
+    }
+
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.env.insert(key.as_ref().to_owned(), None);
+        self
+    }
+
+    /// Do not inherit the environment of the current process.
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
+    /// The clone3 arguments used to create the child.
+    pub fn clone3(&mut self) -> &mut OwnedClone3 {
+        &mut self.clone3
+    }
+
+    /// Creates the child and executes the program in it.
+    ///
+    /// Returns after the child has executed the program. If execve fails the error is sent to the
+    /// parent through a close-on-exec pipe and returned as
+    /// [`Clone3Error::ChildSetup`](crate::Clone3Error::ChildSetup) after reaping the child.
+    ///
+    /// With `FILES` the child shares the pipe with the parent so errors cannot be reported. The
+    /// child exits with code 127 instead.
+    ///
+    /// # Safety
+    ///
+    /// The clone3 arguments must be sound for a child that only calls execve and `_exit`. See
+    /// [`Clone3::call`](crate::Clone3::call).
+    pub unsafe fn spawn(&mut self) -> Result<Child, Clone3Error> {
+        let exec = self.prepare()?;
+        let pipe = if self.clone3.flags().contains(Flags::FILES) {
+            None
+        } else {
+            let pipe = cloexec_pipe_above(self.clone3.min_fd());
+            Some(pipe.map_err(Clone3Error::System)?)
+        };
+        let mut child = match self.clone3.spawn()? {
+            Some(child) => child,
+            None => exec.exec(pipe.as_ref().map(|(_, write)| write.as_raw_fd())),
+        };
+        if let Some((read, write)) = pipe {
+            drop(write);
+            if let Some(errno) = read_errno(&read) {
+                let _ = child.wait();
+                return Err(Clone3Error::ChildSetup {
+                    step: Step::Execve.name(),
+                    errno,
+                });
+            }
+        }
+        Ok(child)
+    }
+
+    fn prepare(&self) -> Result<Exec, Clone3Error> {
+        let mut env: BTreeMap<OsString, OsString> = if self.env_clear {
+            BTreeMap::new()
+        } else {
+            env::vars_os().collect()
+        };
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => env.insert(key.clone(), value.clone()),
+                None => env.remove(key),
+            };
+        }
+        let paths = if self.program.as_bytes().contains(&b'/') {
+            vec![cstring(&self.program)?]
+        } else {
+            let search_path = env
+                .get(OsStr::new("PATH"))
+                .cloned()
+                .unwrap_or_else(|| OsString::from("/bin:/usr/bin"));
+            env::split_paths(&search_path)
+                .map(|dir| cstring(dir.join(&self.program).as_os_str()))
+                .collect::<Result<_, _>>()?
+        };
+        let args = self
+            .args
+            .iter()
+            .map(|arg| cstring(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let env = env
+            .iter()
+            .map(|(key, value)| {
+                let mut var = key.clone();
+                var.push("=");
+                var.push(value);
+                cstring(&var)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Exec::new(paths, args, env))
+    }
+}
+
+/// Everything the child needs to execute the program, allocated before clone3.
+struct Exec {
+    paths: Vec<CString>,
+    argv: Vec<*const c_char>,
+    envp: Vec<*const c_char>,
+    // The pointers above point into these.
+    _args: Vec<CString>,
+    _env: Vec<CString>,
+}
+
+impl Exec {
+    fn new(paths: Vec<CString>, args: Vec<CString>, env: Vec<CString>) -> Self {
+        fn pointers(strings: &[CString]) -> Vec<*const c_char> {
+            strings
+                .iter()
+                .map(|string| string.as_ptr())
+                .chain(Some(ptr::null()))
+                .collect()
+        }
+        Self {
+            paths,
+            argv: pointers(&args),
+            envp: pointers(&env),
+            _args: args,
+            _env: env,
+        }
+    }
+
+    /// Executes the program. Only called in the child so it must not allocate.
+    ///
+    /// On failure the errno is written to `error_pipe`.
+    unsafe fn exec(&self, error_pipe: Option<RawFd>) -> ! {
+        let mut errno = ENOENT;
+        for path in &self.paths {
+            c::execve(path.as_ptr(), self.argv.as_ptr(), self.envp.as_ptr());
+            match Errno::default().0 {
+                // Try the next directory in `PATH` but report that one was not accessible.
+                ENOENT | ENOTDIR => (),
+                EACCES => errno = EACCES,
+                other => {
+                    errno = other;
+                    break;
+                }
+            }
+        }
+        if let Some(fd) = error_pipe {
+            let bytes = errno.to_ne_bytes();
+            c::write(fd, bytes.as_ptr() as *const _, bytes.len());
+        }
+        c::_exit(127)
+    }
+}
+
+/// Reads the errno written by a failing child. Returns `None` when the pipe is closed without
+/// data because the child executed the program.
+pub(crate) fn read_errno(pipe: &OwnedFd) -> Option<Errno> {
+    read_message(pipe).map(|bytes| Errno(c_int::from_ne_bytes(bytes)))
+}
+
+fn cstring(string: &OsStr) -> Result<CString, Clone3Error> {
+    CString::new(string.as_bytes()).map_err(|_| Clone3Error::NulByte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChildStatus, Stdio};
+    use std::io::Read;
+
+    #[test]
+    fn exec_true_and_false() {
+        let mut child = unsafe { Command::new("true").spawn() }.unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(0)));
+        let mut child = unsafe { Command::new("false").spawn() }.unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+    }
+
+    #[test]
+    fn args_and_env() {
+        let mut command = Command::new("/bin/sh");
+        command
+            .args(["-c", "exit $CODE"])
+            .env_clear()
+            .env("CODE", "9");
+        command.clone3().flag_pidfd();
+        let mut child = unsafe { command.spawn() }.unwrap();
+        assert!(child.pidfd().is_some());
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(9)));
+    }
+
+    #[test]
+    fn piped_stdout() {
+        let mut command = Command::new("echo");
+        command.arg("hello");
+        command.clone3().stdout(Stdio::Piped);
+        let mut child = unsafe { command.spawn() }.unwrap();
+        let mut output = String::new();
+        child
+            .take_stdout()
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "hello\n");
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(0)));
+    }
+
+    #[test]
+    fn not_found() {
+        match unsafe { Command::new("clone3-does-not-exist").spawn() } {
+            Err(Clone3Error::ChildSetup {
+                step: "execve",
+                errno: Errno(ENOENT),
+            }) => (),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn not_found_with_files() {
+        let mut command = Command::new("clone3-does-not-exist");
+        command.clone3().flag_files();
+        let mut child = unsafe { command.spawn() }.unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(127)));
+    }
+}
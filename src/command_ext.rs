@@ -0,0 +1,88 @@
+use crate::sys::c::{self, EINVAL};
+use crate::{
+    command::read_errno,
+    setup::{cloexec_pipe_above, Step},
+    Child, Clone3, Clone3Error, Flags,
+};
+use std::{
+    os::unix::{io::AsRawFd, process::CommandExt as _},
+    process::Command,
+};
+
+/// Extension trait to spawn a `std::process::Command` through clone3.
+///
+/// This allows using clone3 features like pidfds, namespaces and `INTO_CGROUP` with an existing
+/// `Command` configuration including its stdio, environment, working directory and `pre_exec`
+/// hooks. [`crate::Command`] is the alternative that does not allocate in the child.
+pub trait CommandExt {
+    /// Creates a child with `clone3` and executes the command in it.
+    ///
+    /// Errors from executing the command are reported like in
+    /// [`Command::spawn`](crate::Command::spawn).
+    ///
+    /// # Safety
+    ///
+    /// The child sets up the command through `std::os::unix::process::CommandExt::exec` which
+    /// allocates. Unlike `fork`, clone3 does not run the `pthread_atfork` handlers that make the
+    /// allocator usable in the child so another thread of the parent holding an allocator lock can
+    /// deadlock the child. Only use this in single threaded processes or with a fork safe
+    /// allocator. The clone3 arguments must be sound as described in
+    /// [`Clone3::call`](crate::Clone3::call).
+    ///
+    /// `Stdio::piped` creates the pipes in the child so the parent never receives their other
+    /// ends. Use `Stdio::inherit`, `Stdio::null` or `Stdio::from` with a file descriptor created
+    /// in the parent, or redirect stdio through [`Clone3::stdin`](crate::Clone3::stdin) and
+    /// friends.
+    unsafe fn spawn_clone3(&mut self, clone3: &mut Clone3<'_>) -> Result<Child, Clone3Error>;
+}
+
+impl CommandExt for Command {
+    unsafe fn spawn_clone3(&mut self, clone3: &mut Clone3<'_>) -> Result<Child, Clone3Error> {
+        let pipe = if clone3.flags.contains(Flags::FILES) {
+            None
+        } else {
+            let pipe = cloexec_pipe_above(clone3.setup.min_fd());
+            Some(pipe.map_err(Clone3Error::System)?)
+        };
+        let mut child = match clone3.spawn()? {
+            Some(child) => child,
+            None => {
+                let err = self.exec();
+                if let Some((_, write)) = &pipe {
+                    let bytes = err.raw_os_error().unwrap_or(EINVAL).to_ne_bytes();
+                    c::write(write.as_raw_fd(), bytes.as_ptr() as *const _, bytes.len());
+                }
+                c::_exit(127)
+            }
+        };
+        if let Some((read, write)) = pipe {
+            drop(write);
+            if let Some(errno) = read_errno(&read) {
+                let _ = child.wait();
+                return Err(Clone3Error::ChildSetup {
+                    step: Step::Execve.name(),
+                    errno,
+                });
+            }
+        }
+        Ok(child)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChildStatus;
+
+    #[test]
+    fn spawn_std_command() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let mut command = Command::new("sh");
+        command.args(["-c", "exit $CODE"]).env("CODE", "6");
+        let mut child = unsafe { command.spawn_clone3(&mut clone3) }.unwrap();
+        assert!(child.pidfd().is_some());
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(6)));
+    }
+}
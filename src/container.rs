@@ -0,0 +1,217 @@
+use crate::sys::{
+    c::{self, EINVAL, SIGKILL},
+    Errno,
+};
+use crate::{
+    setup::try_clone, Child, Clone3Error, Command, IdMap, MountPlan, OwnedClone3, Propagation,
+};
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    os::unix::io::OwnedFd,
+    path::{Path, PathBuf},
+};
+
+/// A minimal container runtime built from the other features of this crate.
+///
+/// [`spawn`](Self::spawn) executes a command in a new root file system inside the namespaces of
+/// [`Clone3::sandboxed`](crate::Clone3::sandboxed). The caller is mapped to root in the container
+/// so no privileges are needed. The container sees its own processes at `/proc` and only a
+/// loopback interface. Its processes are killed when the parent thread exits.
+///
+/// The root file system must contain the program and a `/proc` directory. It is not modified
+/// outside of the container.
+#[derive(Debug, Default)]
+pub struct Container {
+    hostname: Option<OsString>,
+    mounts: Vec<ContainerMount>,
+    env: BTreeMap<OsString, OsString>,
+    cgroup: Option<OwnedFd>,
+}
+
+/// A mount with a target relative to the root of the container.
+#[derive(Debug)]
+enum ContainerMount {
+    Bind {
+        source: PathBuf,
+        target: PathBuf,
+        readonly: bool,
+    },
+    Tmpfs {
+        target: PathBuf,
+        options: OsString,
+    },
+}
+
+impl Container {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the hostname in the container.
+    pub fn hostname<S: AsRef<OsStr>>(&mut self, hostname: S) -> &mut Self {
+        self.hostname = Some(hostname.as_ref().to_owned());
+        self
+    }
+
+    /// Makes `source` on the host available at `target` in the container. See
+    /// [`MountPlan::bind`].
+    pub fn bind<S: AsRef<Path>, P: AsRef<Path>>(&mut self, source: S, target: P) -> &mut Self {
+        self.mounts.push(ContainerMount::Bind {
+            source: source.as_ref().to_owned(),
+            target: target.as_ref().to_owned(),
+            readonly: false,
+        });
+        self
+    }
+
+    /// Like [`bind`](Self::bind) but read-only. See [`MountPlan::bind_readonly`].
+    pub fn bind_readonly<S: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        source: S,
+        target: P,
+    ) -> &mut Self {
+        self.mounts.push(ContainerMount::Bind {
+            source: source.as_ref().to_owned(),
+            target: target.as_ref().to_owned(),
+            readonly: true,
+        });
+        self
+    }
+
+    /// Mounts a new tmpfs at `target` in the container. See [`MountPlan::tmpfs`].
+    pub fn tmpfs<P: AsRef<Path>, O: AsRef<OsStr>>(&mut self, target: P, options: O) -> &mut Self {
+        self.mounts.push(ContainerMount::Tmpfs {
+            target: target.as_ref().to_owned(),
+            options: options.as_ref().to_owned(),
+        });
+        self
+    }
+
+    /// Sets an environment variable of the command.
+    ///
+    /// The command does not inherit the environment of the current process. `PATH` defaults to
+    /// the usual directories of the container.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.env
+            .insert(key.as_ref().to_owned(), value.as_ref().to_owned());
+        self
+    }
+
+    /// Creates the container in the cgroup referred to by `cgroup`, a directory fd of a cgroup2
+    /// file system. The container sees this cgroup as its root.
+    pub fn cgroup(&mut self, cgroup: OwnedFd) -> &mut Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
+    /// Executes `cmd` with `rootfs` as the root file system of the container.
+    ///
+    /// The first element of `cmd` is the program which is searched for in the `PATH` of the
+    /// container if it does not contain a `/`. Targets of the mounts are resolved in `rootfs` and
+    /// must exist. Returns `EINVAL` if `cmd` is empty. Errors of the setup and of execve are
+    /// returned like from [`Command::spawn`](crate::Command::spawn).
+    pub fn spawn<P, I, S>(&self, rootfs: P, cmd: I) -> Result<Child, Clone3Error>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let rootfs = rootfs.as_ref();
+        let mut cmd = cmd.into_iter();
+        let program = cmd.next().ok_or(Clone3Error::System(Errno(EINVAL)))?;
+        let mut command = Command::new(program);
+        command.args(cmd).env_clear().env(
+            "PATH",
+            "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+        );
+        command.envs(&self.env);
+        let clone3 = command.clone3();
+        *clone3 = OwnedClone3::sandboxed();
+        let (uid, gid) = unsafe { (c::getuid(), c::getgid()) };
+        clone3
+            .userns_map(
+                &[IdMap {
+                    inside: 0,
+                    outside: uid,
+                    count: 1,
+                }],
+                &[IdMap {
+                    inside: 0,
+                    outside: gid,
+                    count: 1,
+                }],
+            )
+            .loopback_up()
+            .mounts(&self.mount_plan(rootfs))
+            .pivot_root(rootfs)
+            .pid_init()
+            .current_dir("/")
+            .parent_death_signal(SIGKILL);
+        if let Some(hostname) = &self.hostname {
+            clone3.hostname(hostname);
+        }
+        if let Some(cgroup) = &self.cgroup {
+            clone3.flag_into_cgroup(try_clone(cgroup).map_err(Clone3Error::System)?);
+        }
+        // The clone3 arguments are fully controlled here and the child only performs the setup
+        // and execve.
+        unsafe { command.spawn() }
+    }
+
+    fn mount_plan(&self, rootfs: &Path) -> MountPlan {
+        let inside = |target: &Path| rootfs.join(target.strip_prefix("/").unwrap_or(target));
+        let mut plan = MountPlan::new();
+        // procfs can only be mounted in a user namespace while another one is fully visible so
+        // it is mounted before leaving the root of the parent.
+        plan.propagation("/", Propagation::Private)
+            .proc(rootfs.join("proc"));
+        for mount in &self.mounts {
+            match mount {
+                ContainerMount::Bind {
+                    source,
+                    target,
+                    readonly: false,
+                } => plan.bind(source, inside(target)),
+                ContainerMount::Bind {
+                    source,
+                    target,
+                    readonly: true,
+                } => plan.bind_readonly(source, inside(target)),
+                ContainerMount::Tmpfs { target, options } => plan.tmpfs(inside(target), options),
+            };
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChildStatus;
+    use std::{fs, os::unix::fs::symlink};
+
+    #[test]
+    fn spawn_shell() {
+        let rootfs = std::env::temp_dir().join(format!("clone3-rootfs-{}", std::process::id()));
+        fs::create_dir_all(rootfs.join("proc")).unwrap();
+        fs::create_dir_all(rootfs.join("usr")).unwrap();
+        fs::create_dir_all(rootfs.join("tmp")).unwrap();
+        for dir in ["bin", "lib", "lib64", "sbin"] {
+            let _ = symlink(Path::new("usr").join(dir), rootfs.join(dir));
+        }
+        let mut container = Container::new();
+        container
+            .hostname("clone3-container")
+            .bind_readonly("/usr", "/usr")
+            .tmpfs("/tmp", "size=1m")
+            .env("GREETING", "hello");
+        let script = r#"[ $$ = 2 ] && [ "$(id -u)" = 0 ] && [ "$GREETING" = hello ] &&
+            [ "$(cat /proc/sys/kernel/hostname)" = clone3-container ] &&
+            [ "$(ls /proc/1/root/)" = "$(ls /)" ] && touch /tmp/file && ! touch /usr/file 2>&-"#;
+        let mut child = container.spawn(&rootfs, ["sh", "-c", script]).unwrap();
+        let status = child.wait();
+        fs::remove_dir_all(&rootfs).unwrap();
+        assert_eq!(status, Ok(ChildStatus::Exited(0)));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::sys::{
+    c::{E2BIG, EAGAIN, EBADF, EBUSY, EINVAL, ENOSPC, ENOSYS, EOPNOTSUPP, EPERM, EUSERS},
+    Errno,
+};
+use crate::{
+    check_clone3_available,
+    setup::effective_capabilities,
+    tid_spec::{CAP_CHECKPOINT_RESTORE, CAP_SYS_ADMIN},
+    wrapper::find_incompatible_flags,
+    CgroupFd, Clone3Unavailable, CloneArgs, Flags, IncompatibleFlags, Namespace,
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs, io,
+    os::{
+        raw::c_int,
+        unix::{
+            fs::MetadataExt,
+            io::{BorrowedFd, OwnedFd},
+        },
+    },
+};
+
+const NAMESPACES: [Namespace; 8] = [
+    Namespace::User,
+    Namespace::Pid,
+    Namespace::Net,
+    Namespace::Mount,
+    Namespace::Ipc,
+    Namespace::Uts,
+    Namespace::Cgroup,
+    Namespace::Time,
+];
+
+/// The likely cause of a failed clone3 system call. Returned by [`explain`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Diagnosis {
+    /// The kernel always rejects the arguments. See [`CloneArgs::find_invalid`].
+    InvalidArgs(&'static str),
+    /// The kernel always rejects the combination of flags.
+    IncompatibleFlags(IncompatibleFlags),
+    /// The running kernel does not know the used fields or flags. `required` and `supported` are
+    /// `CLONE_ARGS_SIZE_VER*` sizes identifying the kernel versions.
+    UnsupportedByKernel { required: usize, supported: usize },
+    /// clone3 cannot be used for the contained reason. See [`check_clone3_available`].
+    Clone3Unavailable(Clone3Unavailable),
+    /// The kernel was built without `config`, which is needed for the namespace created by `flag`.
+    NamespaceNotConfigured { flag: Flags, config: &'static str },
+    /// The caller has unshared a time namespace which its children enter. A child in it cannot
+    /// share the memory of the caller.
+    TimeNamespacePending,
+    /// Creating user namespaces is disabled or restricted for unprivileged users by the sysctl
+    /// `setting`.
+    UserNamespacesRestricted { setting: &'static str },
+    /// The caller is in a chroot, in which user namespaces cannot be created.
+    UserNamespaceInChroot,
+    /// The caller lacks `capability`, which is needed for `needed_for`.
+    MissingCapability {
+        capability: &'static str,
+        needed_for: &'static str,
+    },
+    /// A namespace of the type created by `flag` would be nested too deeply or the limit in
+    /// `/proc/sys/user/max_*_namespaces` is reached.
+    NamespaceLimit { flag: Flags },
+    /// The cgroup passed with `INTO_CGROUP` cannot take the child. `errno` is the error returned
+    /// by [`CgroupFd::new`] which describes the cause.
+    CgroupUnusable { errno: Errno },
+    /// The user or the pids cgroup of the caller has too many processes, or `pid_max` is reached.
+    ProcessLimit,
+    /// No known cause.
+    Unknown(Errno),
+}
+
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidArgs(reason) => write!(f, "clone_args are invalid: {}", reason),
+            Self::IncompatibleFlags(reason) => write!(f, "flags are inconsistent: {}", reason),
+            Self::UnsupportedByKernel {
+                required,
+                supported,
+            } => write!(
+                f,
+                "clone3 arguments require Linux {} but the running kernel only supports those of \
+                 Linux {}",
+                crate::raw::kernel_version_for_size(*required),
+                crate::raw::kernel_version_for_size(*supported)
+            ),
+            Self::Clone3Unavailable(reason) => write!(
+                f,
+                "clone3 is not available: {}, see Clone3::legacy_fallback",
+                reason
+            ),
+            Self::NamespaceNotConfigured { flag, config } => write!(
+                f,
+                "{:?} needs a kernel built with {} which the running kernel is not",
+                flag, config
+            ),
+            Self::TimeNamespacePending => write!(
+                f,
+                "the caller has unshared a time namespace so children cannot share its memory"
+            ),
+            Self::UserNamespacesRestricted { setting } => {
+                write!(f, "user namespaces are restricted by {}", setting)
+            }
+            Self::UserNamespaceInChroot => {
+                write!(f, "user namespaces cannot be created in a chroot")
+            }
+            Self::MissingCapability {
+                capability,
+                needed_for,
+            } => write!(f, "{} needs {}", needed_for, capability),
+            Self::NamespaceLimit { flag } => write!(
+                f,
+                "{:?} exceeds the nesting depth of 32 or the limit in /proc/sys/user",
+                flag
+            ),
+            Self::CgroupUnusable { errno } => write!(
+                f,
+                "the cgroup cannot take the child: {}",
+                io::Error::from_raw_os_error(errno.0)
+            ),
+            Self::ProcessLimit => write!(
+                f,
+                "too many processes for RLIMIT_NPROC, pids.max of the cgroup or pid_max"
+            ),
+            Self::Unknown(errno) => write!(
+                f,
+                "clone3 system call failed: {}",
+                io::Error::from_raw_os_error(errno.0)
+            ),
+        }
+    }
+}
+
+/// Returns the likely cause of `errno` returned by a clone3 system call with `cl_args`.
+///
+/// The cause is found by inspecting the arguments, the running kernel and the calling process.
+/// It is a guess because the kernel uses the same errno for many unrelated failures. Call it
+/// right after the failure while the state it inspects has not changed.
+pub fn explain(cl_args: &CloneArgs, errno: Errno) -> Diagnosis {
+    let flags = Flags::from_bits_truncate(cl_args.flags);
+    if errno.0 == ENOSYS || errno.0 == EPERM {
+        if let Err(reason) = check_clone3_available() {
+            return Diagnosis::Clone3Unavailable(reason);
+        }
+    }
+    let diagnosis = match errno.0 {
+        EINVAL | E2BIG => explain_invalid(cl_args, flags),
+        EPERM => explain_permission(cl_args, flags),
+        ENOSPC | EUSERS => namespaces(flags)
+            .next()
+            .map(|namespace| Diagnosis::NamespaceLimit {
+                flag: namespace.flag(),
+            }),
+        EBUSY | EBADF | EOPNOTSUPP if flags.contains(Flags::INTO_CGROUP) => explain_cgroup(cl_args),
+        EAGAIN => Some(Diagnosis::ProcessLimit),
+        _ => None,
+    };
+    diagnosis.unwrap_or(Diagnosis::Unknown(errno))
+}
+
+/// The namespaces created by `flags`, the user namespace first.
+fn namespaces(flags: Flags) -> impl Iterator<Item = Namespace> {
+    NAMESPACES
+        .into_iter()
+        .filter(move |namespace| flags.contains(namespace.flag()))
+}
+
+fn explain_invalid(cl_args: &CloneArgs, flags: Flags) -> Option<Diagnosis> {
+    if let Some(reason) = cl_args.find_invalid() {
+        return Some(Diagnosis::InvalidArgs(reason));
+    }
+    if let Some(reason) = find_incompatible_flags(flags) {
+        return Some(Diagnosis::IncompatibleFlags(reason));
+    }
+    let required = cl_args.required_kernel_size();
+    if let Ok(supported) = crate::max_clone_args_size() {
+        if supported < required {
+            return Some(Diagnosis::UnsupportedByKernel {
+                required,
+                supported,
+            });
+        }
+    }
+    // Each namespace compiled into the kernel has a file in /proc/self/ns.
+    if let Some(namespace) = namespaces(flags).find(|namespace| {
+        let path = format!("/proc/self/ns/{}", namespace.file_name());
+        fs::symlink_metadata(path).is_err()
+    }) {
+        return Some(Diagnosis::NamespaceNotConfigured {
+            flag: namespace.flag(),
+            config: config_option(namespace),
+        });
+    }
+    if flags.intersects(Flags::VM | Flags::THREAD) {
+        let time = fs::metadata("/proc/self/ns/time");
+        let for_children = fs::metadata("/proc/self/ns/time_for_children");
+        if let (Ok(time), Ok(for_children)) = (time, for_children) {
+            if time.ino() != for_children.ino() {
+                return Some(Diagnosis::TimeNamespacePending);
+            }
+        }
+    }
+    None
+}
+
+fn explain_permission(cl_args: &CloneArgs, flags: Flags) -> Option<Diagnosis> {
+    let capabilities = effective_capabilities().unwrap_or(0);
+    let has = |capability: u32| capabilities & 1 << capability != 0;
+    if flags.contains(Flags::NEWUSER) {
+        let settings = [
+            ("/proc/sys/user/max_user_namespaces", "0"),
+            ("/proc/sys/kernel/unprivileged_userns_clone", "0"),
+            (
+                "/proc/sys/kernel/apparmor_restrict_unprivileged_userns",
+                "1",
+            ),
+        ];
+        for (setting, restricted) in settings.iter() {
+            if read_sysctl(setting).as_deref() == Some(restricted) {
+                return Some(Diagnosis::UserNamespacesRestricted { setting });
+            }
+        }
+        if let (Ok(root), Ok(init_root)) = (fs::metadata("/"), fs::metadata("/proc/1/root/")) {
+            if (root.dev(), root.ino()) != (init_root.dev(), init_root.ino()) {
+                return Some(Diagnosis::UserNamespaceInChroot);
+            }
+        }
+    } else if namespaces(flags).next().is_some() && !has(CAP_SYS_ADMIN) {
+        return Some(Diagnosis::MissingCapability {
+            capability: "CAP_SYS_ADMIN",
+            needed_for: "creating namespaces without NEWUSER",
+        });
+    }
+    if cl_args.set_tid_size != 0 && !has(CAP_SYS_ADMIN) && !has(CAP_CHECKPOINT_RESTORE) {
+        return Some(Diagnosis::MissingCapability {
+            capability: "CAP_CHECKPOINT_RESTORE",
+            needed_for: "set_tid",
+        });
+    }
+    None
+}
+
+fn explain_cgroup(cl_args: &CloneArgs) -> Option<Diagnosis> {
+    let fd = c_int::try_from(cl_args.cgroup).ok()?;
+    // The fd is only borrowed for the duration of the check.
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    let result = fd
+        .try_clone_to_owned()
+        .map_err(|err| Errno(err.raw_os_error().unwrap_or(EBADF)))
+        .and_then(|fd: OwnedFd| CgroupFd::new(fd).map(drop));
+    result
+        .err()
+        .map(|errno| Diagnosis::CgroupUnusable { errno })
+}
+
+fn config_option(namespace: Namespace) -> &'static str {
+    match namespace {
+        Namespace::Cgroup => "CONFIG_CGROUPS",
+        Namespace::Ipc => "CONFIG_IPC_NS",
+        Namespace::Mount => "CONFIG_NAMESPACES",
+        Namespace::Net => "CONFIG_NET_NS",
+        Namespace::Pid => "CONFIG_PID_NS",
+        Namespace::Time => "CONFIG_TIME_NS",
+        Namespace::User => "CONFIG_USER_NS",
+        Namespace::Uts => "CONFIG_UTS_NS",
+    }
+}
+
+fn read_sysctl(path: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::{ENOMEM, ENOTDIR};
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn explain_failures() {
+        let args = CloneArgs {
+            stack_size: 4096,
+            ..Default::default()
+        };
+        assert!(matches!(
+            explain(&args, Errno(EINVAL)),
+            Diagnosis::InvalidArgs(_)
+        ));
+        let args = CloneArgs {
+            flags: (Flags::NEWIPC | Flags::SYSVSEM).bits(),
+            ..Default::default()
+        };
+        let diagnosis = explain(&args, Errno(EINVAL));
+        assert!(matches!(diagnosis, Diagnosis::IncompatibleFlags(_)));
+        assert!(diagnosis.to_string().contains("NEWIPC"));
+        let args = CloneArgs::default();
+        assert_eq!(
+            explain(&args, Errno(ENOSYS)),
+            Diagnosis::Unknown(Errno(ENOSYS))
+        );
+        assert_eq!(
+            explain(&args, Errno(ENOMEM)),
+            Diagnosis::Unknown(Errno(ENOMEM))
+        );
+        let args = CloneArgs {
+            flags: (Flags::NEWUSER | Flags::NEWPID).bits(),
+            ..Default::default()
+        };
+        assert_eq!(
+            explain(&args, Errno(ENOSPC)),
+            Diagnosis::NamespaceLimit {
+                flag: Flags::NEWUSER
+            }
+        );
+        // /dev/null is not a cgroup directory.
+        let null = fs::File::open("/dev/null").unwrap();
+        let args = CloneArgs {
+            flags: Flags::INTO_CGROUP.bits(),
+            cgroup: null.as_raw_fd() as u64,
+            ..Default::default()
+        };
+        assert_eq!(
+            explain(&args, Errno(EBADF)),
+            Diagnosis::CgroupUnusable {
+                errno: Errno(ENOTDIR)
+            }
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use crate::sys::Errno;
+use crate::{raw::kernel_version_for_size, Clone3Unavailable, Flags};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+/// Errors returned by [`Clone3::try_call`](crate::Clone3::try_call).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Clone3Error {
+    /// The set flags are incompatible with each other. The system call was not performed.
+    IncompatibleFlags {
+        flags: Flags,
+        reason: IncompatibleFlags,
+    },
+    /// The exit signal is rejected by the kernel for the contained reason. The system call was not
+    /// performed.
+    InvalidExitSignal {
+        exit_signal: u64,
+        reason: &'static str,
+    },
+    /// The [`TidSpec`](crate::TidSpec) passed to [`set_tid`](crate::Clone3::set_tid) is invalid at
+    /// `level` counted from the innermost pid namespace. The system call was not performed.
+    InvalidSetTid { level: usize, reason: &'static str },
+    /// The [`TidSpec`](crate::TidSpec) has more levels than the pid namespace of the child. `max`
+    /// is [`TidSpec::max_levels`](crate::TidSpec::max_levels). The system call was not performed.
+    SetTidTooLong { levels: usize, max: usize },
+    /// Fields are set that are not included in the size passed to the kernel. The system call was
+    /// not performed.
+    CloneArgsSizeTooSmall { size: usize, required: usize },
+    /// The assembled [`CloneArgs`](crate::CloneArgs) are rejected by the kernel for the contained
+    /// reason. See [`CloneArgs::find_invalid`](crate::CloneArgs::find_invalid). The system call was
+    /// not performed.
+    InvalidCloneArgs(&'static str),
+    /// The stack is smaller than [`MIN_STACK_SIZE`](crate::MIN_STACK_SIZE) after aligning its top.
+    /// The system call was not performed.
+    StackTooSmall { size: usize, required: usize },
+    /// The system call failed because the running kernel does not support the used fields or
+    /// flags. `required` and `supported` are `CLONE_ARGS_SIZE_VER*` sizes identifying the kernel
+    /// versions. `errno` is the error returned by the system call.
+    UnsupportedByKernel {
+        required: usize,
+        supported: usize,
+        errno: Errno,
+    },
+    /// clone3 is not available for the contained reason. `errno` is the error returned by the
+    /// system call. See [`Clone3::legacy_fallback`](crate::Clone3::legacy_fallback).
+    Unavailable {
+        reason: Clone3Unavailable,
+        errno: Errno,
+    },
+    /// clone3 is not available and the arguments cannot be passed to the legacy clone system call.
+    /// See [`Clone3::legacy_fallback`](crate::Clone3::legacy_fallback).
+    LegacyFallbackUnsupported(&'static str),
+    /// A program, argument, environment variable or path passed to [`Command`](crate::Command) or
+    /// the child setup of [`Clone3`](crate::Clone3) contains a nul byte. The system call was not
+    /// performed.
+    NulByte,
+    /// A step performed in the child before it runs the user's code or program failed. The child
+    /// has exited and been reaped.
+    ChildSetup { step: &'static str, errno: Errno },
+    /// A child setup step would also change the parent because `flag` makes the child share the
+    /// affected state. The system call was not performed.
+    SetupConflictsWithFlag { setup: &'static str, flag: Flags },
+    /// A child setup step would also change the parent because `flag` which gives the child its
+    /// own copy of the affected state is not set. The system call was not performed.
+    SetupRequiresFlag { setup: &'static str, flag: Flags },
+    /// The system call returned -1. [`explain`](crate::explain) finds likely causes.
+    System(Errno),
+}
+
+impl Display for Clone3Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IncompatibleFlags { flags, reason } => {
+                write!(f, "flags {:?} are inconsistent: {}", flags, reason)
+            }
+            Self::InvalidExitSignal {
+                exit_signal,
+                reason,
+            } => write!(f, "exit signal {} is invalid: {}", exit_signal, reason),
+            Self::InvalidSetTid { level, reason } => {
+                write!(f, "set_tid level {} is invalid: {}", level, reason)
+            }
+            Self::SetTidTooLong { levels, max } => write!(
+                f,
+                "set_tid has {} levels but the child is only in {} pid namespaces",
+                levels, max
+            ),
+            Self::CloneArgsSizeTooSmall { size, required } => write!(
+                f,
+                "clone_args size {} does not include all set fields which need size {}",
+                size, required
+            ),
+            Self::InvalidCloneArgs(reason) => write!(f, "clone_args are invalid: {}", reason),
+            Self::StackTooSmall { size, required } => write!(
+                f,
+                "stack of {} bytes is smaller than the minimum of {} bytes",
+                size, required
+            ),
+            Self::UnsupportedByKernel {
+                required,
+                supported,
+                ..
+            } => write!(
+                f,
+                "clone3 arguments require Linux {} but the running kernel only supports those of \
+                 Linux {}",
+                kernel_version_for_size(*required),
+                kernel_version_for_size(*supported)
+            ),
+            Self::Unavailable { reason, .. } => write!(f, "clone3 is not available: {}", reason),
+            Self::LegacyFallbackUnsupported(reason) => write!(
+                f,
+                "clone3 is not available and the legacy clone fallback cannot be used: {}",
+                reason
+            ),
+            Self::NulByte => write!(f, "string passed to the child contains a nul byte"),
+            Self::ChildSetup { step, errno } => write!(
+                f,
+                "{} failed in the child: {}",
+                step,
+                io::Error::from_raw_os_error(errno.0)
+            ),
+            Self::SetupConflictsWithFlag { setup, flag } => write!(
+                f,
+                "{} cannot be set up in the child because {:?} shares it with the parent",
+                setup, flag
+            ),
+            Self::SetupRequiresFlag { setup, flag } => write!(
+                f,
+                "{} cannot be set up in the child without {:?} because it would change the parent",
+                setup, flag
+            ),
+            Self::System(errno) => write!(
+                f,
+                "clone3 system call failed: {}",
+                io::Error::from_raw_os_error(errno.0)
+            ),
+        }
+    }
+}
+
+impl Error for Clone3Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IncompatibleFlags { .. }
+            | Self::InvalidExitSignal { .. }
+            | Self::InvalidSetTid { .. }
+            | Self::SetTidTooLong { .. }
+            | Self::CloneArgsSizeTooSmall { .. }
+            | Self::InvalidCloneArgs(_)
+            | Self::StackTooSmall { .. }
+            | Self::LegacyFallbackUnsupported(_)
+            | Self::NulByte
+            | Self::SetupConflictsWithFlag { .. }
+            | Self::SetupRequiresFlag { .. } => None,
+            Self::UnsupportedByKernel { errno, .. }
+            | Self::Unavailable { errno, .. }
+            | Self::ChildSetup { errno, .. }
+            | Self::System(errno) => Some(errno),
+        }
+    }
+}
+
+impl From<Errno> for Clone3Error {
+    fn from(errno: Errno) -> Self {
+        Self::System(errno)
+    }
+}
+
+/// Errors carrying an errno become an `io::Error` with it as the raw os error. The others become
+/// `InvalidInput` or `Unsupported` errors wrapping the `Clone3Error`.
+impl From<Clone3Error> for io::Error {
+    fn from(err: Clone3Error) -> Self {
+        match err {
+            Clone3Error::UnsupportedByKernel { errno, .. }
+            | Clone3Error::Unavailable { errno, .. }
+            | Clone3Error::ChildSetup { errno, .. }
+            | Clone3Error::System(errno) => Self::from_raw_os_error(errno.0),
+            Clone3Error::LegacyFallbackUnsupported(_) => Self::new(io::ErrorKind::Unsupported, err),
+            err => Self::new(io::ErrorKind::InvalidInput, err),
+        }
+    }
+}
+
+/// Like [`Clone3::call`](crate::Clone3::call) errors that do not come from a system call become
+/// `EINVAL` and [`LegacyFallbackUnsupported`](Clone3Error::LegacyFallbackUnsupported) becomes
+/// `ENOSYS`.
+#[cfg(feature = "rustix")]
+impl From<Clone3Error> for rustix::io::Errno {
+    fn from(err: Clone3Error) -> Self {
+        match err {
+            Clone3Error::UnsupportedByKernel { errno, .. }
+            | Clone3Error::Unavailable { errno, .. }
+            | Clone3Error::ChildSetup { errno, .. }
+            | Clone3Error::System(errno) => Self::from_raw_os_error(errno.0),
+            Clone3Error::LegacyFallbackUnsupported(_) => Self::NOSYS,
+            _ => Self::INVAL,
+        }
+    }
+}
+
+/// Reason why a combination of [`Flags`](crate::Flags) is rejected.
+///
+/// This is a small `Copy` type so that validation does not allocate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IncompatibleFlags {
+    /// `flag` is set together with at least one of `conflicting`.
+    MutuallyExclusive { flag: Flags, conflicting: Flags },
+    /// `flag` is set without `required`.
+    MissingRequired { flag: Flags, required: Flags },
+}
+
+impl Display for IncompatibleFlags {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MutuallyExclusive { flag, conflicting } => {
+                write!(f, "{:?} and any of {:?} is set", flag, conflicting)
+            }
+            Self::MissingRequired { flag, required } => {
+                write!(f, "{:?} is set without {:?}", flag, required)
+            }
+        }
+    }
+}
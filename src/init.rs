@@ -0,0 +1,132 @@
+use crate::sys::{
+    c::{
+        self, pid_t, sigset_t, MS_NODEV, MS_NOEXEC, MS_NOSUID, MS_REC, MS_SLAVE, SIGCHLD,
+        SIG_SETMASK, WNOHANG,
+    },
+    Errno,
+};
+use crate::{raw::pid_from_libc_result, setup::check, sys::sigwaitinfo, CloneArgs};
+use std::{mem, ptr};
+
+/// Mounts a new procfs at `/proc` for the pid namespace of the child.
+///
+/// Changes the propagation of all mounts to slave first so that the mount does not propagate to
+/// the mount namespace of the parent. Private mounts stay private.
+pub(crate) unsafe fn mount_proc() -> Result<(), Errno> {
+    check(c::mount(
+        ptr::null(),
+        c"/".as_ptr(),
+        ptr::null(),
+        MS_REC | MS_SLAVE,
+        ptr::null(),
+    ))?;
+    check(c::mount(
+        c"proc".as_ptr(),
+        c"/proc".as_ptr(),
+        c"proc".as_ptr(),
+        MS_NOSUID | MS_NODEV | MS_NOEXEC,
+        ptr::null(),
+    ))?;
+    Ok(())
+}
+
+/// Creates the process that runs the user's code while the caller stays the init process of the
+/// pid namespace.
+///
+/// Returns the pid of the payload in the init process and `None` in the payload. All signals
+/// stay blocked in the init process for [`run`].
+pub(crate) unsafe fn fork_payload() -> Result<Option<pid_t>, Errno> {
+    let mut all: sigset_t = mem::zeroed();
+    c::sigfillset(&mut all);
+    let mut original: sigset_t = mem::zeroed();
+    // Before forking so that signals sent before init waits for them are not lost. Blocked
+    // signals are queued even though init ignores signals without a handler.
+    c::sigprocmask(SIG_SETMASK, &all, &mut original);
+    let cl_args = CloneArgs {
+        exit_signal: SIGCHLD as u64,
+        ..CloneArgs::default()
+    };
+    match pid_from_libc_result(crate::clone3_system_call(&cl_args)) {
+        Err(errno) => {
+            c::sigprocmask(SIG_SETMASK, &original, ptr::null_mut());
+            Err(errno)
+        }
+        Ok(0) => {
+            c::sigprocmask(SIG_SETMASK, &original, ptr::null_mut());
+            Ok(None)
+        }
+        Ok(pid) => Ok(Some(pid)),
+    }
+}
+
+/// Forwards signals to `payload` and reaps all children including orphaned descendants until the
+/// payload exits.
+///
+/// Exits with the exit code of the payload or 128 plus the signal that killed it.
+pub(crate) unsafe fn run(payload: pid_t) -> ! {
+    let mut all: sigset_t = mem::zeroed();
+    c::sigfillset(&mut all);
+    loop {
+        // SIGCHLD is only queued once for several exited children.
+        loop {
+            let mut status = 0;
+            match c::waitpid(-1, &mut status, WNOHANG) {
+                pid if pid == payload => {
+                    if c::WIFSIGNALED(status) {
+                        c::_exit(128 + c::WTERMSIG(status));
+                    }
+                    c::_exit(c::WEXITSTATUS(status));
+                }
+                pid if pid > 0 => (),
+                _ => break,
+            }
+        }
+        let signal = sigwaitinfo(&all, ptr::null_mut());
+        if signal > 0 && signal != SIGCHLD {
+            c::kill(payload, signal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sys::c::{self, SIGTERM};
+    use crate::{ChildStatus, Clone3, Clone3Error, Flags};
+    use std::os::raw::c_int;
+
+    #[test]
+    fn pid_init() {
+        let mut clone3 = Clone3::default();
+        clone3.pid_init();
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::SetupRequiresFlag {
+                setup: "pid_init",
+                flag: Flags::NEWPID,
+            }) => (),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+        clone3.flag_newuser().flag_newpid().flag_newns();
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                let mut link = [0u8; 8];
+                let n = c::readlink(c"/proc/self".as_ptr(), link.as_mut_ptr() as *mut _, 8);
+                let ok =
+                    c::getpid() == 2 && c::getppid() == 1 && link[..n.max(0) as usize] == *b"2";
+                c::_exit(if ok { 7 } else { 0 })
+            },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(7)));
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => loop {
+                unsafe { c::pause() };
+            },
+            Some(child) => child,
+        };
+        unsafe { c::kill(child.pid().as_raw(), SIGTERM) };
+        assert_eq!(
+            child.wait(),
+            Ok(ChildStatus::Exited(128 + SIGTERM as c_int))
+        );
+    }
+}
@@ -11,31 +11,226 @@
 //! ```
 //! use clone3::Clone3;
 //!
-//! let mut pidfd = -1;
+//! let mut pidfd = None;
 //! let mut clone3 = Clone3::default();
 //! clone3.flag_pidfd(&mut pidfd);
 //!
 //! match unsafe { clone3.call() }.unwrap() {
 //!     0 => println!("i am the child"),
-//!     child => println!("i am the parent, my child has pid {} and my pidfd is {}", child, pidfd),
+//!     child => println!(
+//!         "i am the parent, my child has pid {} and my pidfd is {:?}",
+//!         child, pidfd
+//!     ),
 //! }
 //! ```
 //!
-//! # Features
+//! # Kernel versions
 //!
 //! The clone3 api can change in a backward compatible manner between Linux kernel versions. This
-//! crate supports picking the target version through Cargo features like `linux_5-5`, `linux_5-7`.
-//! The default is the newest kernel version. Specifying no `linux` feature corresponds to the
-//! initial clone3 api.
+//! crate always includes all fields and flags. Older kernels accept the arguments as long as the
+//! newer fields are unset. Using a field or flag the running kernel does not know results in
+//! [`Clone3Error::UnsupportedByKernel`].
+//!
+//! | Linux | Additions                                  | Size                     |
+//! |-------|--------------------------------------------|--------------------------|
+//! | 5.3   | initial version                            | [`CLONE_ARGS_SIZE_VER0`] |
+//! | 5.5   | `set_tid`, `set_tid_size`, `CLEAR_SIGHAND` | [`CLONE_ARGS_SIZE_VER1`] |
+//! | 5.7   | `cgroup`, `INTO_CGROUP`                    | [`CLONE_ARGS_SIZE_VER2`] |
+//!
+//! # Other operating systems
+//!
+//! The crate also supports Android. It builds on other targets so that it can be depended on
+//! unconditionally. There it only contains [`Flags`] and a minimal [`Clone3`] whose calls fail
+//! with `Unsupported`.
+//!
+//! # Dependencies
+//!
+//! The crate binds to the kernel through the `uapi` crate by default. Disabling the default
+//! `uapi` feature and enabling `libc` instead makes the crate depend only on `libc` and
+//! `bitflags`. [`Errno`] is then a type of this crate with the same api.
+//!
+//! The `rustix` feature implies `libc` and opens pidfds through `rustix`. It adds conversions
+//! between [`Pid`] and `rustix::process::Pid`, from [`Clone3Error`] to `rustix::io::Errno` and,
+//! without `uapi`, between [`Errno`] and `rustix::io::Errno`. rustix has no binding for clone3
+//! or the other system calls of this crate so they still go through libc.
 
 #![doc(html_root_url = "https://docs.rs/clone3/0.2.3")]
 #![allow(clippy::missing_safety_doc)]
 
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "tokio"))]
+mod async_pidfd;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod cgroup;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod child;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod child_set;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod cleartid;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod command;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod command_ext;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "container"))]
+mod container;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod diagnosis;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod error;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod init;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod mount;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod namespace;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod net;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod owned;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod pid;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod pidfd;
+#[cfg(any(target_os = "linux", target_os = "android"))]
 mod raw;
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    feature = "asm-syscall",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+mod raw_syscall;
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod raw_thread;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "async-io"))]
+mod readable;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod restore;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod retry;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod setup;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod siginfo;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod signal;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod stack;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys;
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod test_util;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod tid_spec;
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")
+))]
+mod tls;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod unshare;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod unsupported;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "io-uring"))]
+mod uring;
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod vfork;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod wait;
+#[cfg(any(target_os = "linux", target_os = "android"))]
 mod wrapper;
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
 pub use crate::wrapper::*;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "tokio"))]
+pub use async_pidfd::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use cgroup::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use child::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use child_set::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use cleartid::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use command::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use command_ext::*;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "container"))]
+pub use container::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use diagnosis::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use error::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use mount::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use namespace::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use owned::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use pid::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use pidfd::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
 pub use raw::*;
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    feature = "asm-syscall",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+pub use raw_syscall::*;
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use raw_thread::*;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "async-io"))]
+pub use readable::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use restore::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use retry::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use setup::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use siginfo::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use signal::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use stack::*;
+#[cfg(target_os = "android")]
+pub use sys::sched_attr;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use sys::Errno;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use tid_spec::*;
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")
+))]
+pub use tls::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use unshare::*;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub use unsupported::*;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "io-uring"))]
+pub use uring::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use wait::*;
 
 // The libc crate does not include some of the newer constants so define all of them.
 bitflags::bitflags! {
@@ -44,11 +239,9 @@ bitflags::bitflags! {
     pub struct Flags: u64 {
         const CHILD_CLEARTID = 0x00200000;
         const CHILD_SETTID = 0x01000000;
-        #[cfg(feature = "linux_5-5")]
         const CLEAR_SIGHAND = 0x100000000;
         const FILES = 0x00000400;
         const FS = 0x00000200;
-        #[cfg(feature = "linux_5-7")]
         const INTO_CGROUP = 0x200000000;
         const IO = 0x80000000;
         const NEWCGROUP = 0x02000000;
@@ -72,3 +265,17 @@ bitflags::bitflags! {
         const VM = 0x00000100;
     }
 }
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Flags {
+    /// Checks for combinations of flags that the kernel always rejects with `EINVAL` without
+    /// performing the system call.
+    ///
+    /// [`Clone3::try_call`] performs the same check.
+    pub fn check_consistency(self) -> Result<(), IncompatibleFlags> {
+        match wrapper::find_incompatible_flags(self) {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+}
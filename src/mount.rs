@@ -0,0 +1,349 @@
+use crate::setup::{check, to_cstring, Step};
+use crate::sys::{
+    c::{
+        self, SYS_pivot_root, MNT_DETACH, MS_BIND, MS_NODEV, MS_NOEXEC, MS_NOSUID, MS_PRIVATE,
+        MS_RDONLY, MS_REC, MS_REMOUNT, MS_SHARED, MS_SLAVE, MS_UNBINDABLE, ST_NOATIME, ST_NODEV,
+        ST_NODIRATIME, ST_NOEXEC, ST_NOSUID, ST_RELATIME,
+    },
+    Errno,
+};
+use std::{
+    ffi::{CString, OsStr},
+    mem,
+    os::raw::{c_int, c_ulong},
+    path::Path,
+    ptr,
+};
+
+/// Mounts performed by the child in a new mount namespace. See
+/// [`mounts`](crate::Clone3::mounts).
+///
+/// The mounts are performed in the order they are added. Targets must exist.
+#[derive(Debug, Clone, Default)]
+pub struct MountPlan {
+    pub(crate) mounts: Vec<Mount>,
+    // Set if a path passed to the builder contains a nul byte.
+    pub(crate) nul_byte: bool,
+}
+
+/// The propagation type set by [`MountPlan::propagation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    /// Mount events are not propagated to or from other mount namespaces.
+    Private,
+    /// Mount events are received from the parent mount namespace but not sent to it.
+    Slave,
+    /// Mount events are propagated in both directions.
+    Shared,
+    /// Like `Private` and the mounts cannot be the source of bind mounts.
+    Unbindable,
+}
+
+/// A single `mount` call.
+#[derive(Debug, Clone)]
+pub(crate) struct Mount {
+    source: Option<CString>,
+    target: CString,
+    fstype: Option<CString>,
+    flags: c_ulong,
+    data: Option<CString>,
+    // Remount the bind mount read-only after creating it.
+    readonly: bool,
+}
+
+impl MountPlan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the propagation type of `target` and all mounts below it.
+    ///
+    /// Making `/` private or a slave first keeps the following mounts from propagating to the
+    /// mount namespace of the parent.
+    pub fn propagation<P: AsRef<Path>>(
+        &mut self,
+        target: P,
+        propagation: Propagation,
+    ) -> &mut Self {
+        let flags = match propagation {
+            Propagation::Private => MS_PRIVATE,
+            Propagation::Slave => MS_SLAVE,
+            Propagation::Shared => MS_SHARED,
+            Propagation::Unbindable => MS_UNBINDABLE,
+        };
+        self.push(None, target.as_ref(), None, MS_REC | flags, None, false)
+    }
+
+    /// Makes the directory or file `source` and the mounts below it also available at `target`.
+    pub fn bind<S: AsRef<Path>, P: AsRef<Path>>(&mut self, source: S, target: P) -> &mut Self {
+        let source = source.as_ref().as_os_str();
+        self.push(
+            Some(source),
+            target.as_ref(),
+            None,
+            MS_BIND | MS_REC,
+            None,
+            false,
+        )
+    }
+
+    /// Like [`bind`](Self::bind) but the mount at `target` is read-only.
+    ///
+    /// The bind mount is remounted read-only while keeping its `nosuid`, `nodev`, `noexec` and
+    /// atime flags which cannot be cleared in a user namespace. Mounts below `target` are not
+    /// made read-only.
+    pub fn bind_readonly<S: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        source: S,
+        target: P,
+    ) -> &mut Self {
+        let source = source.as_ref().as_os_str();
+        self.push(
+            Some(source),
+            target.as_ref(),
+            None,
+            MS_BIND | MS_REC,
+            None,
+            true,
+        )
+    }
+
+    /// Mounts a new tmpfs at `target` with `nosuid` and `nodev`.
+    ///
+    /// `options` are passed as is like `size=64m,mode=755` and may be empty.
+    pub fn tmpfs<P: AsRef<Path>, O: AsRef<OsStr>>(&mut self, target: P, options: O) -> &mut Self {
+        let tmpfs = OsStr::new("tmpfs");
+        let options = Some(options.as_ref()).filter(|options| !options.is_empty());
+        self.push(
+            Some(tmpfs),
+            target.as_ref(),
+            Some(tmpfs),
+            MS_NOSUID | MS_NODEV,
+            options,
+            false,
+        )
+    }
+
+    /// Mounts procfs at `target` with `nosuid`, `nodev` and `noexec`.
+    ///
+    /// The procfs shows the processes of the pid namespace of the child so this is usually
+    /// combined with `NEWPID`. Mounting it requires `CAP_SYS_ADMIN` in the user namespace owning
+    /// the pid namespace.
+    pub fn proc<P: AsRef<Path>>(&mut self, target: P) -> &mut Self {
+        let proc = OsStr::new("proc");
+        let flags = MS_NOSUID | MS_NODEV | MS_NOEXEC;
+        self.push(Some(proc), target.as_ref(), Some(proc), flags, None, false)
+    }
+
+    /// Mounts sysfs at `target` with `nosuid`, `nodev` and `noexec`.
+    ///
+    /// Requires `CAP_SYS_ADMIN` in the user namespace owning the network namespace of the child.
+    pub fn sysfs<P: AsRef<Path>>(&mut self, target: P) -> &mut Self {
+        let sysfs = OsStr::new("sysfs");
+        let flags = MS_NOSUID | MS_NODEV | MS_NOEXEC;
+        self.push(
+            Some(sysfs),
+            target.as_ref(),
+            Some(sysfs),
+            flags,
+            None,
+            false,
+        )
+    }
+
+    /// Performs `mount` with the given arguments for mounts not covered by the other methods.
+    ///
+    /// An empty `data` is passed as a null pointer.
+    pub fn mount<S, P, T, D>(
+        &mut self,
+        source: S,
+        target: P,
+        fstype: T,
+        flags: c_ulong,
+        data: D,
+    ) -> &mut Self
+    where
+        S: AsRef<OsStr>,
+        P: AsRef<Path>,
+        T: AsRef<OsStr>,
+        D: AsRef<OsStr>,
+    {
+        let data = Some(data.as_ref()).filter(|data| !data.is_empty());
+        self.push(
+            Some(source.as_ref()),
+            target.as_ref(),
+            Some(fstype.as_ref()),
+            flags,
+            data,
+            false,
+        )
+    }
+
+    fn push(
+        &mut self,
+        source: Option<&OsStr>,
+        target: &Path,
+        fstype: Option<&OsStr>,
+        flags: c_ulong,
+        data: Option<&OsStr>,
+        readonly: bool,
+    ) -> &mut Self {
+        let nul_byte = &mut self.nul_byte;
+        let mount = Mount {
+            source: source.map(|source| to_cstring(source, nul_byte)),
+            target: to_cstring(target.as_os_str(), nul_byte),
+            fstype: fstype.map(|fstype| to_cstring(fstype, nul_byte)),
+            flags,
+            data: data.map(|data| to_cstring(data, nul_byte)),
+            readonly,
+        };
+        self.mounts.push(mount);
+        self
+    }
+}
+
+/// The change of the root directory of the child.
+#[derive(Debug)]
+pub(crate) enum Root {
+    PivotRoot(CString),
+    Chroot(CString),
+}
+
+impl Root {
+    /// The name of the step for errors.
+    pub(crate) fn step(&self) -> Step {
+        match self {
+            Self::PivotRoot(_) => Step::PivotRoot,
+            Self::Chroot(_) => Step::Chroot,
+        }
+    }
+
+    /// Changes the root in the child. Must not allocate.
+    pub(crate) unsafe fn apply(&self) -> Result<(), Errno> {
+        match self {
+            Self::PivotRoot(new_root) => {
+                // pivot_root fails if the parent mount of the old or new root is shared.
+                check(c::mount(
+                    ptr::null(),
+                    c"/".as_ptr(),
+                    ptr::null(),
+                    MS_REC | MS_PRIVATE,
+                    ptr::null(),
+                ))?;
+                // The new root must be a mount point.
+                check(c::mount(
+                    new_root.as_ptr(),
+                    new_root.as_ptr(),
+                    ptr::null(),
+                    MS_BIND | MS_REC,
+                    ptr::null(),
+                ))?;
+                check(c::chdir(new_root.as_ptr()))?;
+                // Stacks the old root on top of the new one so that it needs no directory in the
+                // new root. Detaching the top mount then leaves the new root.
+                check(c::syscall(SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as c_int)?;
+                check(c::umount2(c".".as_ptr(), MNT_DETACH))?;
+            }
+            Self::Chroot(new_root) => check(c::chroot(new_root.as_ptr())).map(drop)?,
+        }
+        check(c::chdir(c"/".as_ptr())).map(drop)
+    }
+}
+
+impl Mount {
+    /// Performs the mount in the child. Must not allocate.
+    pub(crate) unsafe fn apply(&self) -> Result<(), Errno> {
+        let as_ptr = |string: &Option<CString>| string.as_ref().map_or(ptr::null(), |s| s.as_ptr());
+        check(c::mount(
+            as_ptr(&self.source),
+            self.target.as_ptr(),
+            as_ptr(&self.fstype),
+            self.flags,
+            as_ptr(&self.data) as *const _,
+        ))?;
+        if self.readonly {
+            // A remount must keep the locked flags of the mount.
+            let mut stat: c::statvfs = mem::zeroed();
+            check(c::statvfs(self.target.as_ptr(), &mut stat))?;
+            let locked =
+                ST_NOSUID | ST_NODEV | ST_NOEXEC | ST_NOATIME | ST_NODIRATIME | ST_RELATIME;
+            // The ST_ flags have the values of the corresponding MS_ flags.
+            let flags = MS_BIND | MS_REMOUNT | MS_RDONLY | (stat.f_flag & locked);
+            check(c::mount(
+                ptr::null(),
+                self.target.as_ptr(),
+                ptr::null(),
+                flags,
+                ptr::null(),
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::{ENOENT, EROFS};
+    use crate::test_util::assert_child;
+    use crate::{Clone3, Clone3Error, Flags};
+
+    #[test]
+    fn tmpfs_and_readonly_bind() {
+        let mut plan = MountPlan::new();
+        plan.propagation("/", Propagation::Private)
+            .tmpfs("/tmp", "size=1m")
+            .bind_readonly("/tmp", "/tmp");
+        let mut clone3 = Clone3::default();
+        clone3.mounts(&plan);
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::SetupRequiresFlag {
+                setup: "mounts",
+                flag: Flags::NEWNS,
+            }) => (),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+        // The new user namespace grants CAP_SYS_ADMIN over the new mount namespace.
+        clone3.flag_newuser().flag_newns();
+        assert_child(&mut clone3, || unsafe {
+            let mut stat: c::statfs = mem::zeroed();
+            c::statfs(c"/tmp".as_ptr(), &mut stat);
+            let tmpfs = stat.f_type == c::TMPFS_MAGIC;
+            let readonly =
+                c::mkdir(c"/tmp/dir".as_ptr(), 0o700) == -1 && Errno::default().0 == EROFS;
+            tmpfs && readonly
+        });
+        plan.tmpfs("/clone3-does-not-exist", "");
+        clone3.mounts(&plan);
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::ChildSetup {
+                step: "mounts",
+                errno: Errno(ENOENT),
+            }) => (),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn pivot_root_and_chroot() {
+        let mut plan = MountPlan::new();
+        plan.propagation("/", Propagation::Private)
+            .tmpfs("/tmp", "");
+        for chroot in [false, true] {
+            let mut clone3 = Clone3::default();
+            clone3.flag_newuser().flag_newns().mounts(&plan);
+            if chroot {
+                clone3.chroot("/tmp");
+            } else {
+                clone3.pivot_root("/tmp");
+            }
+            assert_child(&mut clone3, || unsafe {
+                let mut stat: c::statfs = mem::zeroed();
+                c::statfs(c".".as_ptr(), &mut stat);
+                let tmpfs = stat.f_type == c::TMPFS_MAGIC;
+                let empty = c::access(c"/proc".as_ptr(), c::F_OK) == -1;
+                tmpfs && empty
+            });
+        }
+    }
+}
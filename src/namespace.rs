@@ -0,0 +1,203 @@
+use crate::sys::{
+    c::{self, pid_t, EINVAL, EPERM, MNT_DETACH, MS_BIND, O_CLOEXEC, O_CREAT, O_RDONLY},
+    Errno,
+};
+use crate::{setup::check, Flags};
+use std::{
+    ffi::CString,
+    os::raw::c_int,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+    path::Path,
+    ptr,
+};
+
+/// A type of namespace. See `namespaces(7)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    Cgroup,
+    Ipc,
+    Mount,
+    Net,
+    Pid,
+    Time,
+    User,
+    Uts,
+}
+
+impl Namespace {
+    /// The flag that creates a new namespace of this type.
+    pub fn flag(self) -> Flags {
+        match self {
+            Self::Cgroup => Flags::NEWCGROUP,
+            Self::Ipc => Flags::NEWIPC,
+            Self::Mount => Flags::NEWNS,
+            Self::Net => Flags::NEWNET,
+            Self::Pid => Flags::NEWPID,
+            Self::Time => Flags::NEWTIME,
+            Self::User => Flags::NEWUSER,
+            Self::Uts => Flags::NEWUTS,
+        }
+    }
+
+    /// The name of the file for the namespace in `/proc/<pid>/ns`.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Cgroup => "cgroup",
+            Self::Ipc => "ipc",
+            Self::Mount => "mnt",
+            Self::Net => "net",
+            Self::Pid => "pid",
+            Self::Time => "time",
+            Self::User => "user",
+            Self::Uts => "uts",
+        }
+    }
+}
+
+/// Joins the namespaces in the child. Must not allocate.
+///
+/// The other namespaces are joined before the user namespace while the child still has the
+/// capabilities it inherited. Those failing with `EPERM` are retried with the capabilities gained
+/// by joining the user namespace.
+pub(crate) unsafe fn join(namespaces: &[(OwnedFd, Namespace)]) -> Result<(), Errno> {
+    let user = namespaces
+        .iter()
+        .find(|(_, namespace)| *namespace == Namespace::User);
+    // There is at most one entry per type.
+    let mut retry = 0u32;
+    for (i, (fd, namespace)) in namespaces.iter().enumerate() {
+        if *namespace == Namespace::User {
+            continue;
+        }
+        match setns(fd, *namespace) {
+            Err(Errno(EPERM)) if user.is_some() => retry |= 1 << i,
+            result => result?,
+        }
+    }
+    if let Some((fd, namespace)) = user {
+        setns(fd, *namespace)?;
+    }
+    for (i, (fd, namespace)) in namespaces.iter().enumerate() {
+        if retry & 1 << i != 0 {
+            setns(fd, *namespace)?;
+        }
+    }
+    Ok(())
+}
+
+unsafe fn setns(fd: &OwnedFd, namespace: Namespace) -> Result<(), Errno> {
+    check(c::setns(fd.as_raw_fd(), namespace.flag().bits() as c_int))?;
+    Ok(())
+}
+
+/// Keeps the namespace of type `namespace` of the process `pid` alive by bind mounting it onto
+/// `path`, like `ip netns add` does for network namespaces.
+///
+/// The namespace survives the exit of all its processes until it is unpinned with
+/// [`unpin_namespace`] and can be joined by opening `path`. `path` is created as an empty file if
+/// it does not exist. Requires `CAP_SYS_ADMIN` in the user namespace owning the mount namespace of
+/// the caller. Pinning a mount namespace requires `path` to be in a different mount namespace
+/// than the pinned one.
+pub fn pin_namespace<P: AsRef<Path>>(
+    pid: pid_t,
+    namespace: Namespace,
+    path: P,
+) -> Result<(), Errno> {
+    let source = format!("/proc/{}/ns/{}", pid, namespace.file_name());
+    let source = CString::new(source).unwrap();
+    let target = CString::new(path.as_ref().as_os_str().as_bytes()).map_err(|_| Errno(EINVAL))?;
+    unsafe {
+        let fd = check(c::open(
+            target.as_ptr(),
+            O_RDONLY | O_CREAT | O_CLOEXEC,
+            0o444,
+        ))?;
+        drop(OwnedFd::from_raw_fd(fd));
+        check(c::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            ptr::null(),
+            MS_BIND,
+            ptr::null(),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Unmounts a namespace pinned with [`pin_namespace`].
+///
+/// The namespace is destroyed once no process or fd refers to it anymore. The file at `path` is
+/// not removed.
+pub fn unpin_namespace<P: AsRef<Path>>(path: P) -> Result<(), Errno> {
+    let target = CString::new(path.as_ref().as_os_str().as_bytes()).map_err(|_| Errno(EINVAL))?;
+    check(unsafe { c::umount2(target.as_ptr(), MNT_DETACH) })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_child;
+    use crate::{ChildStatus, Clone3};
+    use std::{fs, mem};
+
+    #[test]
+    fn pin_uts_namespace() {
+        // Mounting in the mount namespace of the tests requires CAP_SYS_ADMIN.
+        if unsafe { c::geteuid() } != 0 {
+            return;
+        }
+        let path = std::env::temp_dir().join(format!("clone3-uts-{}", std::process::id()));
+        let mut clone3 = Clone3::default();
+        clone3.flag_newuts().hostname("clone3-pinned").barrier();
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                let barrier = clone3.take_child_barrier().unwrap();
+                c::_exit(barrier.wait_for_parent().is_ok() as c_int)
+            },
+            Some(child) => child,
+        };
+        child.pin_namespace(Namespace::Uts, &path).unwrap();
+        child.take_barrier().unwrap().release_child().unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+        let path_c = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let mut stat: c::statfs = unsafe { mem::zeroed() };
+        assert_eq!(unsafe { c::statfs(path_c.as_ptr(), &mut stat) }, 0);
+        // NSFS_MAGIC
+        assert_eq!(stat.f_type as u64, 0x6e73_6673);
+        unpin_namespace(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn join_uts_namespace() {
+        let mut clone3 = Clone3::default();
+        clone3
+            .flag_newuser()
+            .flag_newuts()
+            .hostname("clone3-joined")
+            .barrier();
+        let mut owner = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                let barrier = clone3.take_child_barrier().unwrap();
+                c::_exit(barrier.wait_for_parent().is_ok() as c_int)
+            },
+            Some(child) => child,
+        };
+        let path = format!("/proc/{}/ns/uts", owner.pid());
+        let uts = OwnedFd::from(fs::File::open(path).unwrap());
+        let mut clone3 = Clone3::default();
+        clone3.join_namespace(Namespace::Uts, uts);
+        assert_child(&mut clone3, || unsafe {
+            let mut name: c::utsname = mem::zeroed();
+            c::uname(&mut name);
+            let hostname = std::ffi::CStr::from_ptr(name.nodename.as_ptr());
+            hostname == c"clone3-joined"
+        });
+        owner.take_barrier().unwrap().release_child().unwrap();
+        assert_eq!(owner.wait(), Ok(ChildStatus::Exited(1)));
+    }
+}
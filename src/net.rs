@@ -0,0 +1,180 @@
+use crate::setup::check;
+use crate::sys::{
+    c::{
+        self, pid_t, AF_INET, AF_NETLINK, EINVAL, IFF_UP, NETLINK_ROUTE, NLMSG_ERROR, NLM_F_ACK,
+        NLM_F_CREATE, NLM_F_EXCL, NLM_F_REQUEST, RTM_NEWLINK, SIOCGIFFLAGS, SIOCSIFFLAGS,
+        SOCK_CLOEXEC, SOCK_DGRAM, SOCK_RAW,
+    },
+    Errno,
+};
+use std::{
+    ffi::CStr,
+    mem,
+    os::{
+        raw::{c_char, c_short},
+        unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+};
+
+// From linux/if_link.h and linux/veth.h.
+const IFLA_IFNAME: u16 = 3;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_NET_NS_PID: u16 = 19;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const VETH_INFO_PEER: u16 = 1;
+
+const NLMSGHDR_SIZE: usize = 16;
+const IFINFOMSG_SIZE: usize = 16;
+
+/// Sets the network interface `name` of the current network namespace up like
+/// `ip link set <name> up`. Does not allocate.
+pub(crate) unsafe fn set_up(name: &CStr) -> Result<(), Errno> {
+    let mut request: c::ifreq = mem::zeroed();
+    let name = name.to_bytes_with_nul();
+    if name.len() > request.ifr_name.len() {
+        return Err(Errno(EINVAL));
+    }
+    for (dst, &src) in request.ifr_name.iter_mut().zip(name) {
+        *dst = src as c_char;
+    }
+    let socket = check(c::socket(AF_INET, SOCK_DGRAM | SOCK_CLOEXEC, 0))?;
+    let socket = OwnedFd::from_raw_fd(socket);
+    check(c::ioctl(socket.as_raw_fd(), SIOCGIFFLAGS, &mut request))?;
+    request.ifr_ifru.ifru_flags |= IFF_UP as c_short;
+    check(c::ioctl(socket.as_raw_fd(), SIOCSIFFLAGS, &request))?;
+    Ok(())
+}
+
+/// Creates a veth pair through rtnetlink with `host` in the current network namespace and `peer`
+/// in the one of `pid` like `ip link add <host> type veth peer <peer> netns <pid>`.
+pub(crate) fn create_veth(host: &CStr, peer: &CStr, pid: pid_t) -> Result<(), Errno> {
+    let flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL;
+    let mut message = Message::new(RTM_NEWLINK, flags as u16);
+    message.attribute(IFLA_IFNAME, host.to_bytes_with_nul());
+    let link_info = message.begin_nested(IFLA_LINKINFO);
+    message.attribute(IFLA_INFO_KIND, b"veth");
+    let data = message.begin_nested(IFLA_INFO_DATA);
+    let peer_info = message.begin_nested(VETH_INFO_PEER);
+    message.bytes.extend_from_slice(&[0; IFINFOMSG_SIZE]);
+    message.attribute(IFLA_IFNAME, peer.to_bytes_with_nul());
+    message.attribute(IFLA_NET_NS_PID, &(pid as u32).to_ne_bytes());
+    message.end_nested(peer_info);
+    message.end_nested(data);
+    message.end_nested(link_info);
+    message.send()
+}
+
+/// An rtnetlink request for a link.
+struct Message {
+    bytes: Vec<u8>,
+}
+
+impl Message {
+    fn new(kind: u16, flags: u16) -> Self {
+        let mut bytes = Vec::with_capacity(256);
+        // nlmsghdr with the length filled in by `send`, sequence number 0 and port id 0.
+        bytes.extend_from_slice(&0u32.to_ne_bytes());
+        bytes.extend_from_slice(&kind.to_ne_bytes());
+        bytes.extend_from_slice(&flags.to_ne_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        // ifinfomsg for AF_UNSPEC without an interface index.
+        bytes.extend_from_slice(&[0; IFINFOMSG_SIZE]);
+        Self { bytes }
+    }
+
+    fn attribute(&mut self, kind: u16, data: &[u8]) {
+        let start = self.begin_nested(kind);
+        self.bytes.extend_from_slice(data);
+        self.end_nested(start);
+    }
+
+    /// Starts an attribute whose data are the following attributes.
+    fn begin_nested(&mut self, kind: u16) -> usize {
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(&0u16.to_ne_bytes());
+        self.bytes.extend_from_slice(&kind.to_ne_bytes());
+        start
+    }
+
+    /// Sets the length of the attribute at `start` and pads it to 4 bytes.
+    fn end_nested(&mut self, start: usize) {
+        let len = (self.bytes.len() - start) as u16;
+        self.bytes[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self.bytes.resize((self.bytes.len() + 3) & !3, 0);
+    }
+
+    /// Sends the request and waits for the acknowledgement.
+    fn send(mut self) -> Result<(), Errno> {
+        let len = self.bytes.len() as u32;
+        self.bytes[..4].copy_from_slice(&len.to_ne_bytes());
+        let socket =
+            check(unsafe { c::socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_ROUTE) })?;
+        let socket = unsafe { OwnedFd::from_raw_fd(socket) };
+        // Without a bound address the request goes to the kernel.
+        let sent = unsafe {
+            c::send(
+                socket.as_raw_fd(),
+                self.bytes.as_ptr() as *const _,
+                self.bytes.len(),
+                0,
+            )
+        };
+        if sent == -1 {
+            return Err(Errno::default());
+        }
+        let mut response = [0u8; 1024];
+        let received = unsafe {
+            c::recv(
+                socket.as_raw_fd(),
+                response.as_mut_ptr() as *mut _,
+                response.len(),
+                0,
+            )
+        };
+        if received == -1 {
+            return Err(Errno::default());
+        }
+        // An nlmsgerr with the negated errno or 0 for success.
+        let kind = u16::from_ne_bytes(response[4..6].try_into().unwrap());
+        if received < (NLMSGHDR_SIZE + 4) as isize || kind != NLMSG_ERROR as u16 {
+            return Err(Errno(EINVAL));
+        }
+        match i32::from_ne_bytes(response[16..20].try_into().unwrap()) {
+            0 => Ok(()),
+            error => Err(Errno(-error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_child;
+    use crate::Clone3;
+
+    unsafe fn is_up(name: &CStr) -> bool {
+        let mut request: c::ifreq = mem::zeroed();
+        for (dst, &src) in request.ifr_name.iter_mut().zip(name.to_bytes()) {
+            *dst = src as c_char;
+        }
+        let socket = OwnedFd::from_raw_fd(c::socket(AF_INET, SOCK_DGRAM | SOCK_CLOEXEC, 0));
+        c::ioctl(socket.as_raw_fd(), SIOCGIFFLAGS, &mut request) == 0
+            && request.ifr_ifru.ifru_flags & IFF_UP as c_short != 0
+    }
+
+    #[test]
+    fn loopback_and_veth() {
+        let mut clone3 = Clone3::default();
+        clone3.flag_newuser().flag_newnet().loopback_up();
+        assert_child(&mut clone3, || unsafe { is_up(c"lo") });
+        // Creating the host end requires CAP_NET_ADMIN in the namespace of the parent.
+        if unsafe { c::geteuid() } != 0 {
+            return;
+        }
+        let host = format!("clone3h{}", std::process::id() % 100_000);
+        let mut clone3 = Clone3::default();
+        clone3.flag_newnet().veth(&host, "clone3c");
+        assert_child(&mut clone3, || unsafe { is_up(c"clone3c") });
+    }
+}
@@ -0,0 +1,774 @@
+use crate::sys::{
+    c::{gid_t, mode_t, pid_t, rlim_t, rlimit, sock_filter, uid_t, SIGCHLD},
+    Errno,
+};
+use crate::{
+    default_stack_size,
+    mount::Root,
+    setup::{to_cstring, ChildSetup, Hook, Lsm, SchedAttr, SetupRef},
+    sys::sched_attr,
+    wrapper::{sandbox_flags, RawStack},
+    CgroupFd, Child, ChildBarrier, ClearTidHandle, Clone3, Clone3Error, CloneStack, CpuSet, Flags,
+    IdMap, IoPriorityClass, MountPlan, Namespace, ParentBarrier, RetryPolicy, ScopedCgroup, Stdio,
+    TidSpec,
+};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::File,
+    io,
+    mem::{self, MaybeUninit},
+    os::{
+        raw::{c_int, c_long, c_ulong},
+        unix::io::{AsFd, BorrowedFd, OwnedFd, RawFd},
+    },
+    path::Path,
+    ptr::NonNull,
+    sync::Arc,
+};
+
+/// Variant of [`Clone3`](crate::Clone3) that owns all of its arguments.
+///
+/// `Clone3` borrows the variables the kernel writes to and the stack it hands to the child which
+/// ties the builder to their lifetime. `OwnedClone3` stores them itself so it is `'static` and
+/// `Send`. This makes it possible to keep a configured builder in long lived structs or to move it
+/// across threads.
+///
+/// Values written by the kernel can be read after the system call through the getters like
+/// [`pidfd`](Self::pidfd).
+#[derive(Debug, Default)]
+pub struct OwnedClone3 {
+    flags: Flags,
+    pidfd: Option<OwnedFd>,
+    // Boxed because with `VM` the child writes to this location after `call` returns so it must
+    // not move when `self` does.
+    child_tid: Option<Box<pid_t>>,
+    parent_tid: Option<pid_t>,
+    cleartid: Option<Arc<ClearTidHandle>>,
+    exit_signal: u64,
+    stack: Option<Stack>,
+    tls: Option<u64>,
+    set_tid: Option<TidSpec>,
+    cgroup: Option<OwnedFd>,
+    clone_args_size: Option<usize>,
+    legacy_fallback: bool,
+    eagain_retry: Option<RetryPolicy>,
+    setup: ChildSetup,
+}
+
+impl OwnedClone3 {
+    /// See [`Clone3::sandboxed`](crate::Clone3::sandboxed).
+    pub fn sandboxed() -> Self {
+        Self {
+            flags: sandbox_flags(),
+            ..Self::default()
+        }
+    }
+
+    /// See [`Clone3::fork_like`](crate::Clone3::fork_like).
+    pub fn fork_like() -> Self {
+        Self {
+            exit_signal: SIGCHLD as u64,
+            ..Self::default()
+        }
+    }
+
+    pub fn flag_child_cleartid(&mut self) -> &mut Self {
+        self.flags.set(Flags::CHILD_CLEARTID, true);
+        self.child_tid.get_or_insert_with(Default::default);
+        self.cleartid = None;
+        self
+    }
+
+    /// See [`Clone3::flag_child_cleartid_handle`](crate::Clone3::flag_child_cleartid_handle).
+    pub fn flag_child_cleartid_handle(&mut self, handle: Arc<ClearTidHandle>) -> &mut Self {
+        self.flags
+            .set(Flags::CHILD_CLEARTID | Flags::PARENT_SETTID, true);
+        self.child_tid = None;
+        self.parent_tid = None;
+        self.cleartid = Some(handle);
+        self
+    }
+
+    pub fn flag_child_settid(&mut self) -> &mut Self {
+        self.flags.set(Flags::CHILD_SETTID, true);
+        self.child_tid.get_or_insert_with(Default::default);
+        self.cleartid = None;
+        self
+    }
+
+    pub fn flag_clear_sighand(&mut self) -> &mut Self {
+        self.flags.set(Flags::CLEAR_SIGHAND, true);
+        self
+    }
+
+    pub fn flag_files(&mut self) -> &mut Self {
+        self.flags.set(Flags::FILES, true);
+        self
+    }
+
+    pub fn flag_fs(&mut self) -> &mut Self {
+        self.flags.set(Flags::FS, true);
+        self
+    }
+
+    pub fn flag_into_cgroup(&mut self, cgroup: OwnedFd) -> &mut Self {
+        self.flags.set(Flags::INTO_CGROUP, true);
+        self.cgroup = Some(cgroup);
+        self.setup.cgroup_path = None;
+        self
+    }
+
+    /// See [`Clone3::flag_into_cgroup_path`](crate::Clone3::flag_into_cgroup_path).
+    pub fn flag_into_cgroup_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.flags.set(Flags::INTO_CGROUP, true);
+        self.cgroup = None;
+        self.setup.cgroup_path = Some(CgroupFd::open(path));
+        self
+    }
+
+    /// See [`Clone3::flag_into_cgroups_rs`](crate::Clone3::flag_into_cgroups_rs).
+    #[cfg(feature = "cgroups-rs")]
+    pub fn flag_into_cgroups_rs(&mut self, cgroup: &cgroups_rs::Cgroup) -> &mut Self {
+        self.flags.set(Flags::INTO_CGROUP, true);
+        self.cgroup = None;
+        self.setup.cgroup_path = Some(CgroupFd::from_cgroups_rs(cgroup));
+        self
+    }
+
+    /// See [`Clone3::scoped_cgroup`](crate::Clone3::scoped_cgroup).
+    pub fn scoped_cgroup(&mut self, cgroup: ScopedCgroup) -> &mut Self {
+        self.setup.scoped_cgroup = Some(cgroup);
+        self
+    }
+
+    pub fn flag_io(&mut self) -> &mut Self {
+        self.flags.set(Flags::IO, true);
+        self
+    }
+
+    pub fn flag_newcgroup(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWCGROUP, true);
+        self
+    }
+
+    pub fn flag_newipc(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWIPC, true);
+        self
+    }
+
+    pub fn flag_newnet(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWNET, true);
+        self
+    }
+
+    pub fn flag_newns(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWNS, true);
+        self
+    }
+
+    pub fn flag_newpid(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWPID, true);
+        self
+    }
+
+    pub fn flag_newtime(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWTIME, true);
+        self
+    }
+
+    pub fn flag_newuser(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWUSER, true);
+        self
+    }
+
+    pub fn flag_newuts(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWUTS, true);
+        self
+    }
+
+    pub fn flag_parent(&mut self) -> &mut Self {
+        self.flags.set(Flags::PARENT, true);
+        self
+    }
+
+    pub fn flag_parent_settid(&mut self) -> &mut Self {
+        self.flags.set(Flags::PARENT_SETTID, true);
+        self.parent_tid.get_or_insert(0);
+        self.cleartid = None;
+        self
+    }
+
+    pub fn flag_pidfd(&mut self) -> &mut Self {
+        self.flags.set(Flags::PIDFD, true);
+        self
+    }
+
+    pub fn flag_ptrace(&mut self) -> &mut Self {
+        self.flags.set(Flags::PTRACE, true);
+        self
+    }
+
+    pub fn flag_settls(&mut self, tls: u64) -> &mut Self {
+        self.flags.set(Flags::SETTLS, true);
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn flag_sighand(&mut self) -> &mut Self {
+        self.flags.set(Flags::SIGHAND, true);
+        self
+    }
+
+    pub fn flag_sysvsem(&mut self) -> &mut Self {
+        self.flags.set(Flags::SYSVSEM, true);
+        self
+    }
+
+    pub fn flag_thread(&mut self) -> &mut Self {
+        self.flags.set(Flags::THREAD, true);
+        self
+    }
+
+    pub fn flag_untraced(&mut self) -> &mut Self {
+        self.flags.set(Flags::UNTRACED, true);
+        self
+    }
+
+    pub fn flag_vfork(&mut self) -> &mut Self {
+        self.flags.set(Flags::VFORK, true);
+        self
+    }
+
+    pub fn flag_vm(&mut self, stack: Vec<u8>) -> &mut Self {
+        self.flags.set(Flags::VM, true);
+        self.stack(stack);
+        self
+    }
+
+    pub fn exit_signal(&mut self, exit_signal: u64) -> &mut Self {
+        self.exit_signal = exit_signal;
+        self
+    }
+
+    pub fn stack(&mut self, stack: Vec<u8>) -> &mut Self {
+        let mut stack = mem::ManuallyDrop::new(stack);
+        // MaybeUninit<u8> has the layout of u8.
+        let stack = unsafe {
+            Vec::from_raw_parts(
+                stack.as_mut_ptr() as *mut MaybeUninit<u8>,
+                stack.len(),
+                stack.capacity(),
+            )
+        };
+        self.stack_uninit(stack)
+    }
+
+    /// See [`Clone3::stack_uninit`](crate::Clone3::stack_uninit).
+    ///
+    /// A stack of `size` bytes without initializing it is created with
+    /// `iter::repeat_with(MaybeUninit::uninit).take(size).collect()`.
+    pub fn stack_uninit(&mut self, stack: Vec<MaybeUninit<u8>>) -> &mut Self {
+        self.stack = Some(Stack::Heap(stack));
+        self
+    }
+
+    /// Uses the memory of `stack` as the stack of the child.
+    pub fn stack_mapped(&mut self, stack: CloneStack) -> &mut Self {
+        self.stack = Some(Stack::Mapped(stack));
+        self
+    }
+
+    /// Maps a [`CloneStack`] of [`default_stack_size`] and uses it as the stack of the child.
+    pub fn with_default_stack(&mut self) -> Result<&mut Self, Errno> {
+        let stack = CloneStack::new(default_stack_size())?;
+        Ok(self.stack_mapped(stack))
+    }
+
+    /// See [`Clone3::stack_raw`](crate::Clone3::stack_raw).
+    ///
+    /// # Safety
+    ///
+    /// The memory must stay valid for as long as `self` is used to create children.
+    pub unsafe fn stack_raw(&mut self, ptr: NonNull<u8>, len: usize) -> &mut Self {
+        self.stack = Some(Stack::Raw(RawStack { ptr, len }));
+        self
+    }
+
+    pub fn set_tid(&mut self, set_tid: TidSpec) -> &mut Self {
+        self.set_tid = Some(set_tid);
+        self
+    }
+
+    /// See [`Clone3::clone_args_size`](crate::Clone3::clone_args_size).
+    pub fn clone_args_size(&mut self, size: usize) -> &mut Self {
+        // Validate eagerly like `Clone3` does.
+        Clone3::default().clone_args_size(size);
+        self.clone_args_size = Some(size);
+        self
+    }
+
+    /// See [`Clone3::legacy_fallback`](crate::Clone3::legacy_fallback).
+    pub fn legacy_fallback(&mut self) -> &mut Self {
+        self.legacy_fallback = true;
+        self
+    }
+
+    /// See [`Clone3::retry_eagain`](crate::Clone3::retry_eagain).
+    pub fn retry_eagain(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.eagain_retry = Some(policy);
+        self
+    }
+
+    /// See [`Clone3::stdin`](crate::Clone3::stdin).
+    pub fn stdin(&mut self, stdin: Stdio) -> &mut Self {
+        self.setup.stdio[0] = stdin;
+        self
+    }
+
+    /// See [`Clone3::stdout`](crate::Clone3::stdout).
+    pub fn stdout(&mut self, stdout: Stdio) -> &mut Self {
+        self.setup.stdio[1] = stdout;
+        self
+    }
+
+    /// See [`Clone3::stderr`](crate::Clone3::stderr).
+    pub fn stderr(&mut self, stderr: Stdio) -> &mut Self {
+        self.setup.stdio[2] = stderr;
+        self
+    }
+
+    /// See [`Clone3::take_stdin`](crate::Clone3::take_stdin).
+    pub fn take_stdin(&mut self) -> Option<File> {
+        self.setup.parent_stdio[0].take().map(File::from)
+    }
+
+    /// See [`Clone3::take_stdout`](crate::Clone3::take_stdout).
+    pub fn take_stdout(&mut self) -> Option<File> {
+        self.setup.parent_stdio[1].take().map(File::from)
+    }
+
+    /// See [`Clone3::take_stderr`](crate::Clone3::take_stderr).
+    pub fn take_stderr(&mut self) -> Option<File> {
+        self.setup.parent_stdio[2].take().map(File::from)
+    }
+
+    /// See [`Clone3::child_setup`](crate::Clone3::child_setup).
+    pub unsafe fn child_setup<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut() -> Result<(), Errno> + Send + 'static,
+    {
+        self.setup.hooks.push(Hook(Box::new(f)));
+        self
+    }
+
+    /// See [`Clone3::name`](crate::Clone3::name).
+    pub fn name<S: AsRef<OsStr>>(&mut self, name: S) -> &mut Self {
+        let name = to_cstring(name.as_ref(), &mut self.setup.nul_byte);
+        self.setup.name = Some(name);
+        self
+    }
+
+    /// See [`Clone3::rlimit`](crate::Clone3::rlimit).
+    pub fn rlimit(&mut self, resource: c_int, soft: rlim_t, hard: rlim_t) -> &mut Self {
+        let limit = rlimit {
+            rlim_cur: soft,
+            rlim_max: hard,
+        };
+        self.setup.rlimits.push((resource, limit));
+        self
+    }
+
+    /// See [`Clone3::oom_score_adj`](crate::Clone3::oom_score_adj).
+    pub fn oom_score_adj(&mut self, value: c_int) -> &mut Self {
+        self.setup.oom_score_adj = Some(value.to_string().into_bytes());
+        self
+    }
+
+    /// See [`Clone3::nice`](crate::Clone3::nice).
+    pub fn nice(&mut self, level: c_int) -> &mut Self {
+        self.setup.nice = Some(level);
+        self
+    }
+
+    /// See [`Clone3::cpu_affinity`](crate::Clone3::cpu_affinity).
+    pub fn cpu_affinity(&mut self, cpus: &CpuSet) -> &mut Self {
+        self.setup.cpu_affinity = Some(*cpus);
+        self
+    }
+
+    /// See [`Clone3::sched_attr`](crate::Clone3::sched_attr).
+    pub fn sched_attr(&mut self, attr: &sched_attr) -> &mut Self {
+        let mut attr = *attr;
+        attr.size = mem::size_of::<sched_attr>() as u32;
+        self.setup.sched_attr = Some(SchedAttr(attr));
+        self
+    }
+
+    /// See [`Clone3::mempolicy`](crate::Clone3::mempolicy).
+    pub fn mempolicy(&mut self, mode: c_int, nodemask: &[c_ulong]) -> &mut Self {
+        self.setup.mempolicy = Some((mode, nodemask.to_vec()));
+        self
+    }
+
+    /// See [`Clone3::personality`](crate::Clone3::personality).
+    pub fn personality(&mut self, persona: c_ulong) -> &mut Self {
+        self.setup.personality = Some(persona);
+        self
+    }
+
+    /// See [`Clone3::hostname`](crate::Clone3::hostname).
+    pub fn hostname<S: AsRef<OsStr>>(&mut self, hostname: S) -> &mut Self {
+        let hostname = to_cstring(hostname.as_ref(), &mut self.setup.nul_byte);
+        self.setup.hostname = Some(hostname);
+        self
+    }
+
+    /// See [`Clone3::domainname`](crate::Clone3::domainname).
+    pub fn domainname<S: AsRef<OsStr>>(&mut self, domainname: S) -> &mut Self {
+        let domainname = to_cstring(domainname.as_ref(), &mut self.setup.nul_byte);
+        self.setup.domainname = Some(domainname);
+        self
+    }
+
+    /// See [`Clone3::loopback_up`](crate::Clone3::loopback_up).
+    pub fn loopback_up(&mut self) -> &mut Self {
+        self.setup.loopback_up = true;
+        self
+    }
+
+    /// See [`Clone3::veth`](crate::Clone3::veth).
+    pub fn veth<H: AsRef<OsStr>, C: AsRef<OsStr>>(&mut self, host: H, child: C) -> &mut Self {
+        let host = to_cstring(host.as_ref(), &mut self.setup.nul_byte);
+        let child = to_cstring(child.as_ref(), &mut self.setup.nul_byte);
+        self.setup.veth = Some((host, child));
+        self
+    }
+
+    /// See [`Clone3::mounts`](crate::Clone3::mounts).
+    pub fn mounts(&mut self, plan: &MountPlan) -> &mut Self {
+        self.setup.mounts = plan.mounts.clone();
+        self.setup.nul_byte |= plan.nul_byte;
+        self
+    }
+
+    /// See [`Clone3::pivot_root`](crate::Clone3::pivot_root).
+    pub fn pivot_root<P: AsRef<Path>>(&mut self, new_root: P) -> &mut Self {
+        let new_root = to_cstring(new_root.as_ref().as_os_str(), &mut self.setup.nul_byte);
+        self.setup.root = Some(Root::PivotRoot(new_root));
+        self
+    }
+
+    /// See [`Clone3::chroot`](crate::Clone3::chroot).
+    pub fn chroot<P: AsRef<Path>>(&mut self, new_root: P) -> &mut Self {
+        let new_root = to_cstring(new_root.as_ref().as_os_str(), &mut self.setup.nul_byte);
+        self.setup.root = Some(Root::Chroot(new_root));
+        self
+    }
+
+    /// See [`Clone3::pid_init`](crate::Clone3::pid_init).
+    pub fn pid_init(&mut self) -> &mut Self {
+        self.setup.pid_init = true;
+        self
+    }
+
+    /// See [`Clone3::io_priority`](crate::Clone3::io_priority).
+    pub fn io_priority(&mut self, class: IoPriorityClass, level: u8) -> &mut Self {
+        self.setup.io_priority = Some(class.value(level));
+        self
+    }
+
+    /// See [`Clone3::join_namespace`](crate::Clone3::join_namespace).
+    pub fn join_namespace(&mut self, namespace: Namespace, fd: OwnedFd) -> &mut Self {
+        let namespaces = &mut self.setup.join_namespaces;
+        namespaces.retain(|(_, other)| *other != namespace);
+        namespaces.push((fd, namespace));
+        self
+    }
+
+    /// See [`Clone3::userns_map`](crate::Clone3::userns_map).
+    pub fn userns_map(&mut self, uid_maps: &[IdMap], gid_maps: &[IdMap]) -> &mut Self {
+        self.setup.userns_map = Some((IdMap::format(uid_maps), IdMap::format(gid_maps)));
+        self
+    }
+
+    /// See [`Clone3::groups`](crate::Clone3::groups).
+    pub fn groups(&mut self, groups: &[gid_t]) -> &mut Self {
+        self.setup.groups = Some(groups.to_vec());
+        self
+    }
+
+    /// See [`Clone3::gid`](crate::Clone3::gid).
+    pub fn gid(&mut self, gid: gid_t) -> &mut Self {
+        self.setup.gid = Some(gid);
+        self
+    }
+
+    /// See [`Clone3::uid`](crate::Clone3::uid).
+    pub fn uid(&mut self, uid: uid_t) -> &mut Self {
+        self.setup.uid = Some(uid);
+        self
+    }
+
+    /// See [`Clone3::capabilities`](crate::Clone3::capabilities).
+    pub fn capabilities(&mut self, keep: u64) -> &mut Self {
+        self.setup.capabilities = Some(keep);
+        self
+    }
+
+    /// See [`Clone3::parent_death_signal`](crate::Clone3::parent_death_signal).
+    pub fn parent_death_signal(&mut self, signal: c_int) -> &mut Self {
+        self.setup.parent_death_signal = Some(signal);
+        self
+    }
+
+    /// See [`Clone3::current_dir`](crate::Clone3::current_dir).
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        let dir = to_cstring(dir.as_ref().as_os_str(), &mut self.setup.nul_byte);
+        self.setup.current_dir = Some(dir);
+        self
+    }
+
+    /// See [`Clone3::umask`](crate::Clone3::umask).
+    pub fn umask(&mut self, mask: mode_t) -> &mut Self {
+        self.setup.umask = Some(mask);
+        self
+    }
+
+    /// See [`Clone3::setsid`](crate::Clone3::setsid).
+    pub fn setsid(&mut self) -> &mut Self {
+        self.setup.setsid = true;
+        self
+    }
+
+    /// See [`Clone3::process_group`](crate::Clone3::process_group).
+    pub fn process_group(&mut self, pgid: pid_t) -> &mut Self {
+        self.setup.process_group = Some(pgid);
+        self
+    }
+
+    /// See [`Clone3::close_fds`](crate::Clone3::close_fds).
+    pub fn close_fds(&mut self, first: RawFd) -> &mut Self {
+        self.setup.close_fds = Some(first);
+        self
+    }
+
+    /// See [`Clone3::pass_fds`](crate::Clone3::pass_fds).
+    pub fn pass_fds<I: IntoIterator<Item = (RawFd, RawFd)>>(&mut self, fds: I) -> &mut Self {
+        self.setup.pass_fds.extend(fds);
+        self
+    }
+
+    /// See [`Clone3::pty`](crate::Clone3::pty).
+    pub fn pty(&mut self) -> &mut Self {
+        self.setup.pty = true;
+        self
+    }
+
+    /// See [`Clone3::controlling_terminal`](crate::Clone3::controlling_terminal).
+    pub fn controlling_terminal(&mut self, tty: OwnedFd) -> &mut Self {
+        self.setup.controlling_terminal = Some(tty);
+        self
+    }
+
+    /// See [`Clone3::selinux_exec_context`](crate::Clone3::selinux_exec_context).
+    pub fn selinux_exec_context<S: AsRef<OsStr>>(&mut self, context: S) -> &mut Self {
+        let context = to_cstring(context.as_ref(), &mut self.setup.nul_byte);
+        self.setup.exec_label = Some((Lsm::SELinux, context.into_bytes()));
+        self
+    }
+
+    /// See [`Clone3::apparmor_exec_profile`](crate::Clone3::apparmor_exec_profile).
+    pub fn apparmor_exec_profile<S: AsRef<OsStr>>(&mut self, profile: S) -> &mut Self {
+        let mut command = OsString::from("exec ");
+        command.push(profile);
+        let command = to_cstring(&command, &mut self.setup.nul_byte);
+        self.setup.exec_label = Some((Lsm::AppArmor, command.into_bytes()));
+        self
+    }
+
+    /// See [`Clone3::non_dumpable`](crate::Clone3::non_dumpable).
+    pub fn non_dumpable(&mut self) -> &mut Self {
+        self.setup.non_dumpable = true;
+        self
+    }
+
+    /// See [`Clone3::no_new_privs`](crate::Clone3::no_new_privs).
+    pub fn no_new_privs(&mut self) -> &mut Self {
+        self.setup.no_new_privs = true;
+        self
+    }
+
+    /// See [`Clone3::landlock_ruleset`](crate::Clone3::landlock_ruleset).
+    pub fn landlock_ruleset(&mut self, ruleset: OwnedFd) -> &mut Self {
+        self.setup.landlock_ruleset = Some(ruleset);
+        self
+    }
+
+    /// See [`Clone3::seccomp_filter`](crate::Clone3::seccomp_filter).
+    pub fn seccomp_filter(&mut self, filter: &[sock_filter]) -> &mut Self {
+        self.setup.seccomp_filter = Some(filter.to_vec());
+        self
+    }
+
+    /// See [`Clone3::take_pty_master`](crate::Clone3::take_pty_master).
+    pub fn take_pty_master(&mut self) -> Option<File> {
+        self.setup.pty_master.take().map(File::from)
+    }
+
+    /// See [`Clone3::barrier`](crate::Clone3::barrier).
+    pub fn barrier(&mut self) -> &mut Self {
+        self.setup.barrier = true;
+        self
+    }
+
+    /// See [`Clone3::take_parent_barrier`](crate::Clone3::take_parent_barrier).
+    pub fn take_parent_barrier(&mut self) -> Option<ParentBarrier> {
+        self.setup.parent_barrier.take()
+    }
+
+    /// See [`Clone3::take_child_barrier`](crate::Clone3::take_child_barrier).
+    pub fn take_child_barrier(&mut self) -> Option<ChildBarrier> {
+        self.setup.child_barrier.take()
+    }
+
+    /// The pidfd of the child of the last successful call if `PIDFD` is set.
+    pub fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+        self.pidfd.as_ref().map(AsFd::as_fd)
+    }
+
+    /// Takes ownership of the pidfd of the child of the last successful call if `PIDFD` is set.
+    pub fn take_pidfd(&mut self) -> Option<OwnedFd> {
+        self.pidfd.take()
+    }
+
+    /// The child tid written by the kernel if `CHILD_CLEARTID` or `CHILD_SETTID` is set.
+    pub fn child_tid(&self) -> Option<pid_t> {
+        self.child_tid.as_deref().copied()
+    }
+
+    /// The child tid written by the kernel if `PARENT_SETTID` is set.
+    pub fn parent_tid(&self) -> Option<pid_t> {
+        self.parent_tid
+    }
+
+    pub(crate) fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    pub(crate) fn min_fd(&self) -> RawFd {
+        self.setup.min_fd()
+    }
+
+    /// Performs the system call.
+    ///
+    /// See [`Clone3::call`](crate::Clone3::call).
+    pub unsafe fn call(&mut self) -> Result<pid_t, Errno> {
+        self.as_clone3().call()
+    }
+
+    /// Performs the system call.
+    ///
+    /// See [`Clone3::call_io`](crate::Clone3::call_io).
+    pub unsafe fn call_io(&mut self) -> io::Result<pid_t> {
+        self.as_clone3().call_io()
+    }
+
+    /// Performs the system call.
+    ///
+    /// See [`Clone3::try_call`](crate::Clone3::try_call).
+    pub unsafe fn try_call(&mut self) -> Result<pid_t, Clone3Error> {
+        self.as_clone3().try_call()
+    }
+
+    /// Performs the system call and returns a [`Child`](crate::Child) handle in the parent.
+    ///
+    /// See [`Clone3::spawn`](crate::Clone3::spawn).
+    pub unsafe fn spawn(&mut self) -> Result<Option<Child>, Clone3Error> {
+        self.as_clone3().spawn()
+    }
+
+    /// Performs the system call.
+    ///
+    /// See [`Clone3::call_unchecked`](crate::Clone3::call_unchecked).
+    pub unsafe fn call_unchecked(&mut self) -> c_long {
+        self.as_clone3().call_unchecked()
+    }
+
+    /// Returns a [`Clone3`](crate::Clone3) borrowing the arguments owned by `self`.
+    pub fn as_clone3(&mut self) -> Clone3<'_> {
+        Clone3 {
+            flags: self.flags,
+            pidfd: None,
+            pidfd_owned: if self.flags.contains(Flags::PIDFD) {
+                Some(&mut self.pidfd)
+            } else {
+                None
+            },
+            pidfd_slot: -1,
+            child_tid: self.child_tid.as_deref_mut(),
+            parent_tid: self.parent_tid.as_mut(),
+            cleartid: self.cleartid.as_deref(),
+            exit_signal: self.exit_signal,
+            stack: self.stack.as_mut().map(Stack::as_raw),
+            tls: self.tls,
+            set_tid: self.set_tid.as_ref(),
+            cgroup: self.cgroup.as_ref().map(AsFd::as_fd),
+            clone_args_size: self.clone_args_size,
+            legacy_fallback: self.legacy_fallback,
+            eagain_retry: self.eagain_retry,
+            setup: SetupRef::Borrowed(&mut self.setup),
+        }
+    }
+}
+
+/// The memory used as the stack.
+#[derive(Debug)]
+enum Stack {
+    Heap(Vec<MaybeUninit<u8>>),
+    Mapped(CloneStack),
+    Raw(RawStack),
+}
+
+impl Stack {
+    fn as_raw(&mut self) -> RawStack {
+        match self {
+            Self::Heap(stack) => RawStack::from(stack.as_mut_slice()),
+            Self::Mapped(stack) => RawStack::from(stack.as_uninit_slice_mut()),
+            Self::Raw(stack) => *stack,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_static_and_send() {
+        fn assert_static_send<T: 'static + Send>() {}
+        assert_static_send::<OwnedClone3>();
+    }
+
+    #[test]
+    fn writes_pidfd() {
+        let mut clone3 = OwnedClone3::default();
+        clone3.flag_pidfd();
+        let mut clone3 = std::thread::spawn(move || clone3).join().unwrap();
+        match unsafe { clone3.call() }.unwrap() {
+            0 => unsafe { crate::sys::c::_exit(0) },
+            _ => assert!(clone3.take_pidfd().is_some()),
+        }
+    }
+
+    #[test]
+    fn default_stack() {
+        let mut clone3 = OwnedClone3::default();
+        let cl_args = clone3
+            .with_default_stack()
+            .unwrap()
+            .as_clone3()
+            .as_clone_args();
+        assert_ne!(cl_args.stack, 0);
+        assert_eq!(cl_args.stack_size as usize, default_stack_size());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::sys::{
+    c::{self, pid_t, EINVAL, EPERM},
+    Errno,
+};
+use crate::PidFd;
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    os::raw::{c_int, c_uint},
+};
+
+/// A process id.
+///
+/// Always positive. 0 and negative values passed to kill refer to process groups or all
+/// processes instead of a single process and are rejected.
+///
+/// The kernel reuses the id of a process once it has been reaped. Operations on a `Pid` can
+/// therefore affect an unrelated process. Use a [`PidFd`] to refer to a process without this
+/// race.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(pid_t);
+
+impl Pid {
+    /// Returns `None` if `pid` is not positive.
+    pub fn from_raw(pid: pid_t) -> Option<Self> {
+        (pid > 0).then_some(Self(pid))
+    }
+
+    /// `pid` must be positive.
+    pub(crate) fn from_raw_unchecked(pid: pid_t) -> Self {
+        debug_assert!(pid > 0);
+        Self(pid)
+    }
+
+    pub fn as_raw(self) -> pid_t {
+        self.0
+    }
+
+    /// The id of the calling process.
+    pub fn current() -> Self {
+        Self(unsafe { c::getpid() })
+    }
+
+    /// Sends `signal` to the process.
+    pub fn kill(self, signal: c_int) -> Result<(), Errno> {
+        if unsafe { c::kill(self.0, signal) } == -1 {
+            return Err(Errno::default());
+        }
+        Ok(())
+    }
+
+    /// Whether a process with this id exists including zombies and processes the caller has no
+    /// permission to signal.
+    pub fn exists(self) -> bool {
+        matches!(self.kill(0), Ok(()) | Err(Errno(EPERM)))
+    }
+
+    /// Opens a pidfd for the process. See [`pidfd_open`](crate::pidfd_open).
+    pub fn open_pidfd(self, flags: c_uint) -> Result<PidFd, Errno> {
+        PidFd::open(self.0, flags)
+    }
+}
+
+/// Fails with `EINVAL` if `pid` is not positive.
+impl TryFrom<pid_t> for Pid {
+    type Error = Errno;
+
+    fn try_from(pid: pid_t) -> Result<Self, Errno> {
+        Self::from_raw(pid).ok_or(Errno(EINVAL))
+    }
+}
+
+#[cfg(feature = "rustix")]
+impl From<Pid> for rustix::process::Pid {
+    fn from(pid: Pid) -> Self {
+        Self::from_raw(pid.0).expect("pid is positive")
+    }
+}
+
+#[cfg(feature = "rustix")]
+impl From<rustix::process::Pid> for Pid {
+    fn from(pid: rustix::process::Pid) -> Self {
+        Self::from_raw_unchecked(pid.as_raw_pid())
+    }
+}
+
+impl From<Pid> for pid_t {
+    fn from(pid: Pid) -> Self {
+        pid.0
+    }
+}
+
+impl Display for Pid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::SIGKILL;
+    use crate::{ChildStatus, Clone3};
+
+    #[test]
+    fn kill_and_exists() {
+        assert!(Pid::current().exists());
+        assert_eq!(Pid::from_raw(0), None);
+        assert_eq!(Pid::try_from(-1), Err(Errno(EINVAL)));
+        let child = match unsafe { Clone3::default().spawn() }.unwrap() {
+            None => loop {
+                unsafe { c::pause() };
+            },
+            Some(child) => child,
+        };
+        let pid = child.pid();
+        assert_eq!(Pid::try_from(pid_t::from(pid)), Ok(pid));
+        let pidfd = pid.open_pidfd(0).unwrap();
+        pid.kill(SIGKILL).unwrap();
+        assert_eq!(pidfd.wait(), Ok(ChildStatus::Signaled(SIGKILL, false)));
+    }
+
+    #[cfg(feature = "rustix")]
+    #[test]
+    fn rustix_conversions() {
+        let pid = Pid::current();
+        let rustix_pid = rustix::process::Pid::from(pid);
+        assert_eq!(rustix_pid, rustix::process::getpid());
+        assert_eq!(Pid::from(rustix_pid), pid);
+        assert_eq!(crate::pidfd_open(0, 0).unwrap_err(), Errno(EINVAL));
+        assert!(crate::pidfd_open(pid.as_raw(), 0).is_ok());
+        let errno = rustix::io::Errno::from(crate::Clone3Error::System(Errno(EPERM)));
+        assert_eq!(errno, rustix::io::Errno::PERM);
+    }
+}
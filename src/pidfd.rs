@@ -0,0 +1,299 @@
+use crate::sys::{
+    c::{gid_t, ioctl, pid_t, syscall, uid_t, O_EXCL, O_NONBLOCK},
+    Errno,
+};
+use crate::{sys::SYS_pidfd_send_signal, ChildStatus, WaitOptions};
+use std::{
+    os::{
+        raw::{c_int, c_uint},
+        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    },
+    ptr,
+};
+
+/// Flag for [`pidfd_open`]: Open the pidfd in non blocking mode.
+pub const PIDFD_NONBLOCK: c_uint = O_NONBLOCK as c_uint;
+/// Flag for [`pidfd_open`]: Refer to a thread instead of a thread group leader. Linux 6.9.
+pub const PIDFD_THREAD: c_uint = O_EXCL as c_uint;
+
+/// Owned pidfd referring to a process.
+///
+/// The fd becomes readable when the process exits. This makes it usable with event loops and async
+/// runtimes that wait for readiness and then call [`try_wait`](Self::try_wait). With the `tokio`
+/// feature `AsyncPidFd` does this for tokio. With the `mio` feature the pidfd is a
+/// `mio::event::Source`.
+#[derive(Debug)]
+pub struct PidFd(OwnedFd);
+
+impl PidFd {
+    /// Opens a pidfd for the existing process `pid`. See [`pidfd_open`](crate::pidfd_open).
+    pub fn open(pid: pid_t, flags: c_uint) -> Result<Self, Errno> {
+        pidfd_open(pid, flags).map(Self)
+    }
+
+    /// See [`pidfd_send_signal`](crate::pidfd_send_signal).
+    pub fn send_signal(&self, signal: c_int) -> Result<(), Errno> {
+        pidfd_send_signal(self.as_fd(), signal)
+    }
+
+    /// See [`pidfd_get_info`](crate::pidfd_get_info).
+    pub fn info(&self) -> Result<PidFdInfo, Errno> {
+        pidfd_get_info(self.as_fd())
+    }
+
+    /// Waits for the process to exit. The process must be a child of the caller.
+    ///
+    /// Fails with `EAGAIN` if the pidfd was opened with [`PIDFD_NONBLOCK`].
+    pub fn wait(&self) -> Result<ChildStatus, Errno> {
+        let status = crate::wait_pidfd(self.as_fd(), WaitOptions::empty())?;
+        Ok(status.expect("waitid without WNOHANG returned no status"))
+    }
+
+    /// Returns the status of the process if it has exited without blocking. The process must be a
+    /// child of the caller.
+    pub fn try_wait(&self) -> Result<Option<ChildStatus>, Errno> {
+        crate::wait_pidfd(self.as_fd(), WaitOptions::NOHANG)
+    }
+}
+
+impl AsFd for PidFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for PidFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for PidFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl FromRawFd for PidFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+#[cfg(feature = "mio")]
+impl mio::event::Source for PidFd {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
+
+impl From<OwnedFd> for PidFd {
+    fn from(fd: OwnedFd) -> Self {
+        Self(fd)
+    }
+}
+
+impl From<PidFd> for OwnedFd {
+    fn from(pidfd: PidFd) -> Self {
+        pidfd.0
+    }
+}
+
+/// Opens a pidfd for the existing process `pid`.
+///
+/// This allows using pidfds for processes that were not created through clone3 with `PIDFD`. See
+/// the [pidfd_open man page](https://man7.org/linux/man-pages/man2/pidfd_open.2.html).
+pub fn pidfd_open(pid: pid_t, flags: c_uint) -> Result<OwnedFd, Errno> {
+    #[cfg(feature = "rustix")]
+    {
+        // The kernel rejects pids that are not positive with the same errno.
+        let pid = crate::Pid::from_raw(pid).ok_or(Errno(crate::sys::c::EINVAL))?;
+        let flags = rustix::process::PidfdFlags::from_bits_retain(flags);
+        rustix::process::pidfd_open(pid.into(), flags).map_err(|errno| Errno(errno.raw_os_error()))
+    }
+    #[cfg(not(feature = "rustix"))]
+    {
+        let return_value = unsafe { syscall(crate::sys::SYS_pidfd_open, pid, flags) };
+        if return_value == -1 {
+            return Err(Errno::default());
+        }
+        Ok(unsafe { OwnedFd::from_raw_fd(return_value as c_int) })
+    }
+}
+
+/// Sends `signal` to the process referred to by `pidfd`.
+///
+/// Unlike `kill` this cannot signal an unrelated process that reused the pid after the original
+/// process was reaped. See the
+/// [pidfd_send_signal man page](https://man7.org/linux/man-pages/man2/pidfd_send_signal.2.html).
+pub fn pidfd_send_signal(pidfd: BorrowedFd<'_>, signal: c_int) -> Result<(), Errno> {
+    let return_value = unsafe {
+        syscall(
+            SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal,
+            ptr::null::<()>(),
+            0,
+        )
+    };
+    if return_value == -1 {
+        return Err(Errno::default());
+    }
+    Ok(())
+}
+
+const PIDFD_INFO_PID: u64 = 1 << 0;
+const PIDFD_INFO_CREDS: u64 = 1 << 1;
+const PIDFD_INFO_CGROUPID: u64 = 1 << 2;
+const PIDFD_INFO_EXIT: u64 = 1 << 3;
+
+/// `struct pidfd_info` as defined in `/usr/include/linux/pidfd.h`.
+#[repr(C)]
+#[derive(Default)]
+struct RawPidFdInfo {
+    mask: u64,
+    cgroupid: u64,
+    pid: u32,
+    tgid: u32,
+    ppid: u32,
+    ruid: u32,
+    rgid: u32,
+    euid: u32,
+    egid: u32,
+    suid: u32,
+    sgid: u32,
+    fsuid: u32,
+    fsgid: u32,
+    exit_code: i32,
+}
+
+// _IOWR(PIDFS_IOCTL_MAGIC, 11, struct pidfd_info)
+#[cfg(not(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "sparc64"
+)))]
+const PIDFD_GET_INFO: u64 = (3 << 30) | (64 << 16) | (0xFF << 8) | 11;
+#[cfg(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "sparc64"
+))]
+const PIDFD_GET_INFO: u64 = (6 << 29) | (64 << 16) | (0xFF << 8) | 11;
+
+/// Information about a process queried through its pidfd with
+/// [`pidfd_get_info`](crate::pidfd_get_info).
+///
+/// The ids are as seen from the pid and user namespaces of the caller.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PidFdInfo {
+    pub pid: pid_t,
+    pub tgid: pid_t,
+    pub ppid: pid_t,
+    pub ruid: uid_t,
+    pub rgid: gid_t,
+    pub euid: uid_t,
+    pub egid: gid_t,
+    pub suid: uid_t,
+    pub sgid: gid_t,
+    pub fsuid: uid_t,
+    pub fsgid: gid_t,
+    /// The id of the cgroup2 the process is in. `None` without cgroup2.
+    pub cgroup_id: Option<u64>,
+    /// How the process exited. `None` while it has not been reaped yet. Linux 6.15.
+    pub exit_status: Option<ChildStatus>,
+}
+
+/// Queries information about the process referred to by `pidfd` through the `PIDFD_GET_INFO` ioctl.
+///
+/// Requires Linux 6.13. Older kernels fail with `ENOTTY`. Fails with `ESRCH` if the process has
+/// been reaped and the kernel does not provide exit information.
+pub fn pidfd_get_info(pidfd: BorrowedFd<'_>) -> Result<PidFdInfo, Errno> {
+    let mut info = RawPidFdInfo {
+        mask: PIDFD_INFO_PID | PIDFD_INFO_CREDS | PIDFD_INFO_CGROUPID | PIDFD_INFO_EXIT,
+        ..Default::default()
+    };
+    let return_value = unsafe {
+        ioctl(
+            pidfd.as_raw_fd(),
+            PIDFD_GET_INFO as _,
+            &mut info as *mut RawPidFdInfo,
+        )
+    };
+    if return_value == -1 {
+        return Err(Errno::default());
+    }
+    Ok(PidFdInfo {
+        pid: info.pid as pid_t,
+        tgid: info.tgid as pid_t,
+        ppid: info.ppid as pid_t,
+        ruid: info.ruid,
+        rgid: info.rgid,
+        euid: info.euid,
+        egid: info.egid,
+        suid: info.suid,
+        sgid: info.sgid,
+        fsuid: info.fsuid,
+        fsgid: info.fsgid,
+        cgroup_id: (info.mask & PIDFD_INFO_CGROUPID != 0).then_some(info.cgroupid),
+        exit_status: (info.mask & PIDFD_INFO_EXIT != 0)
+            .then(|| ChildStatus::from_wait_status(info.exit_code)),
+    })
+}
+
+#[cfg(all(test, feature = "mio"))]
+mod tests {
+    use super::*;
+    use crate::sys::c;
+    use crate::Clone3;
+    use mio::{Events, Interest, Poll, Token};
+
+    #[test]
+    fn mio_readable() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let mut pidfd = match unsafe { clone3.spawn() }.unwrap() {
+            None => {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                unsafe { c::_exit(7) }
+            }
+            Some(mut child) => child.take_pidfd().unwrap(),
+        };
+        let mut poll = Poll::new().unwrap();
+        poll.registry()
+            .register(&mut pidfd, Token(7), Interest::READABLE)
+            .unwrap();
+        let mut events = Events::with_capacity(1);
+        while events.is_empty() {
+            poll.poll(&mut events, None).unwrap();
+        }
+        let event = events.iter().next().unwrap();
+        assert_eq!(event.token(), Token(7));
+        assert!(event.is_readable());
+        assert_eq!(pidfd.try_wait(), Ok(Some(ChildStatus::Exited(7))));
+        poll.registry().deregister(&mut pidfd).unwrap();
+    }
+}
@@ -1,5 +1,31 @@
-use std::os::raw::c_long;
-use uapi::c::{syscall, SYS_clone3};
+use crate::sys::{
+    c::{
+        pid_t, prctl, syscall, uname, utsname, SYS_clone, E2BIG, EINVAL, ENOSYS, PR_GET_SECCOMP,
+        SECCOMP_MODE_FILTER,
+    },
+    Errno,
+};
+use crate::Flags;
+use std::{
+    ffi::CStr,
+    fmt::{self, Display, Formatter},
+    io, mem,
+    os::raw::{c_int, c_long, c_void},
+    ptr,
+};
+
+/// Size of the first version of [`CloneArgs`] supported since Linux 5.3.
+pub const CLONE_ARGS_SIZE_VER0: usize = 64;
+/// Size of [`CloneArgs`] including `set_tid` and `set_tid_size` supported since Linux 5.5.
+pub const CLONE_ARGS_SIZE_VER1: usize = 80;
+/// Size of [`CloneArgs`] including `cgroup` supported since Linux 5.7.
+pub const CLONE_ARGS_SIZE_VER2: usize = 88;
+
+/// Alignment of the top of the stack passed to clone3. The ABIs of all architectures supported by
+/// Linux require at most 16 bytes.
+pub const STACK_ALIGN: usize = 16;
+/// The smallest stack accepted by [`Clone3::try_call`](crate::Clone3::try_call).
+pub const MIN_STACK_SIZE: usize = 16 * 1024;
 
 /// Arguments to the clone3 system call as defined in `/usr/include/linux/sched.h`.
 #[repr(C, align(8))]
@@ -13,19 +39,465 @@ pub struct CloneArgs {
     pub stack: u64,
     pub stack_size: u64,
     pub tls: u64,
-    #[cfg(feature = "linux_5-5")]
     pub set_tid: u64,
-    #[cfg(feature = "linux_5-5")]
     pub set_tid_size: u64,
-    #[cfg(feature = "linux_5-7")]
     pub cgroup: u64,
 }
 
 /// The raw clone3 system call.
 pub unsafe fn clone3_system_call(cl_args: &CloneArgs) -> c_long {
-    syscall(
-        SYS_clone3,
-        cl_args as *const CloneArgs,
-        core::mem::size_of::<CloneArgs>(),
-    )
+    clone3_system_call_with_size(cl_args, mem::size_of::<CloneArgs>())
+}
+
+/// The raw clone3 system call passing `size` as the size of `cl_args`.
+///
+/// The kernel ignores the fields past `size`. Passing one of the `CLONE_ARGS_SIZE_VER*` constants
+/// allows a binary built with newer fields to run on older kernels which would otherwise fail with
+/// `E2BIG`.
+///
+/// # Panics
+///
+/// Panics if `size` is larger than `size_of::<CloneArgs>()`.
+pub unsafe fn clone3_system_call_with_size(cl_args: &CloneArgs, size: usize) -> c_long {
+    assert!(
+        size <= mem::size_of::<CloneArgs>(),
+        "clone_args size {} is larger than CloneArgs",
+        size
+    );
+    crate::sys::clone3(cl_args as *const CloneArgs as *const c_void, size)
+}
+
+/// The legacy clone system call with the arguments taken from `cl_args`.
+///
+/// The legacy system call takes the top of the stack instead of the stack base and size and
+/// returns the pidfd through `parent_tid`. Only the flags in the lower 32 bits are available and
+/// `set_tid` and `cgroup` are ignored. It is the caller's responsibility to check that `cl_args` is
+/// representable.
+pub unsafe fn legacy_clone_system_call(cl_args: &CloneArgs) -> c_long {
+    let flags = cl_args.flags | cl_args.exit_signal;
+    // The stack grows downwards on all architectures supported by Rust.
+    let stack = match cl_args.stack {
+        0 => 0,
+        stack => stack + cl_args.stack_size,
+    };
+    let parent_tid = if cl_args.flags & Flags::PIDFD.bits() != 0 {
+        cl_args.pidfd
+    } else {
+        cl_args.parent_tid
+    };
+    let (child_tid, tls) = (cl_args.child_tid, cl_args.tls);
+    // The argument order differs between architectures. See `CONFIG_CLONE_BACKWARDS*` in the
+    // kernel.
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "x86"
+    ))]
+    return syscall(SYS_clone, flags, stack, parent_tid, tls, child_tid);
+    #[cfg(target_arch = "s390x")]
+    return syscall(SYS_clone, stack, flags, parent_tid, child_tid, tls);
+    #[cfg(not(any(
+        target_arch = "arm",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "x86",
+        target_arch = "s390x"
+    )))]
+    return syscall(SYS_clone, flags, stack, parent_tid, child_tid, tls);
+}
+
+/// Converts the result of [`clone3_system_call`] or [`legacy_clone_system_call`] which is -1 with
+/// errno set on failure.
+///
+/// `c_long` has 32 bits on 32 bit targets like arm and i686 where it is the same type as `pid_t`
+/// and 64 bits otherwise. The result is checked against the values the kernel can return instead
+/// of relying on a conversion that only fails on 64 bit targets. Other values indicate a broken
+/// libc and panic on all targets.
+pub(crate) fn pid_from_libc_result(return_value: c_long) -> Result<pid_t, Errno> {
+    match return_value {
+        -1 => Err(Errno::default()),
+        pid if (0..=pid_t::MAX as c_long).contains(&pid) => Ok(pid as pid_t),
+        _ => panic!("invalid result {} of the clone system call", return_value),
+    }
+}
+
+/// Like [`pid_from_libc_result`] for results of system calls that bypass libc which are the
+/// negated errno between -4095 and -1 on failure.
+pub(crate) fn pid_from_raw_result(return_value: c_long) -> Result<pid_t, Errno> {
+    match return_value {
+        errno if (-MAX_ERRNO..0).contains(&errno) => Err(Errno(-errno as c_int)),
+        pid if (0..=pid_t::MAX as c_long).contains(&pid) => Ok(pid as pid_t),
+        _ => panic!("invalid result {} of the clone system call", return_value),
+    }
+}
+
+/// Performs clone3 for [`Clone3`](crate::Clone3) and the probes and converts the result.
+///
+/// With the `asm-syscall` feature the system call is made through
+/// [`raw_syscall`](crate::raw_syscall) so that errno is not written, which matters for `VM`
+/// children without `SETTLS` and callers in signal handlers. Otherwise this goes through libc.
+pub(crate) unsafe fn clone3_result(args: *const c_void, size: usize) -> Result<pid_t, Errno> {
+    #[cfg(all(
+        feature = "asm-syscall",
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "riscv64"
+        )
+    ))]
+    {
+        if !crate::sys::clone3_allowed() {
+            return Err(Errno(ENOSYS));
+        }
+        let args = [args as usize, size, 0, 0, 0, 0];
+        pid_from_raw_result(crate::raw_syscall(crate::sys::SYS_clone3, args))
+    }
+    #[cfg(not(all(
+        feature = "asm-syscall",
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "riscv64"
+        )
+    )))]
+    pid_from_libc_result(crate::sys::clone3(args, size))
+}
+
+/// The largest errno the kernel returns, `MAX_ERRNO` in the kernel.
+const MAX_ERRNO: c_long = 4095;
+
+/// Returns the largest `CLONE_ARGS_SIZE_VER*` size supported by the running kernel.
+///
+/// The kernel is probed with arguments it always rejects so no process is created. A version is
+/// supported if setting its last field makes the kernel fail validation with `EINVAL` instead of
+/// failing with `E2BIG` because of unknown trailing fields.
+///
+/// # Errors
+///
+/// Errors if the kernel rejects the probe for another reason. `ENOSYS` indicates that the kernel
+/// does not support clone3 at all.
+pub fn max_clone_args_size() -> Result<usize, Errno> {
+    let mut args = [0u64; CLONE_ARGS_SIZE_VER2 / 8];
+    // An exit signal with the high bits set is rejected by all kernels.
+    args[4] = u64::MAX;
+    for &size in [
+        CLONE_ARGS_SIZE_VER2,
+        CLONE_ARGS_SIZE_VER1,
+        CLONE_ARGS_SIZE_VER0,
+    ]
+    .iter()
+    {
+        let last = size / 8 - 1;
+        let previous = mem::replace(&mut args[last], 1);
+        let result = unsafe { clone3_result(args.as_ptr() as *const c_void, size) };
+        args[last] = previous;
+        match result {
+            Err(Errno(EINVAL)) => return Ok(size),
+            Err(Errno(E2BIG)) => continue,
+            Err(errno) => return Err(errno),
+            Ok(_) => unreachable!("the kernel accepted an invalid exit signal"),
+        }
+    }
+    Err(Errno(E2BIG))
+}
+
+/// Why clone3 cannot be used. Returned by [`check_clone3_available`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Clone3Unavailable {
+    /// The kernel is older than 5.3.
+    KernelTooOld,
+    /// A seccomp filter like older versions of Docker's default profile rejects clone3 although
+    /// the kernel supports it.
+    Seccomp,
+    /// clone3 failed with an unexpected errno without a seccomp filter being installed, for
+    /// example from a security module.
+    Other(Errno),
+}
+
+impl Display for Clone3Unavailable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KernelTooOld => write!(f, "the kernel is older than 5.3"),
+            Self::Seccomp => write!(f, "clone3 is blocked by a seccomp filter"),
+            Self::Other(errno) => write!(
+                f,
+                "clone3 is rejected: {}",
+                io::Error::from_raw_os_error(errno.0)
+            ),
+        }
+    }
+}
+
+/// Checks whether clone3 can be used and if not whether the kernel is too old, clone3 is blocked
+/// by seccomp or rejected for another reason.
+///
+/// The kernel is probed with a null pointer and a size of 0 which it always rejects with `EINVAL`
+/// so no process is created. A seccomp filter only sees the register values and cannot read the
+/// `clone_args` behind the pointer so filters that block clone3 fail the probe with their own
+/// errno, typically `ENOSYS` or `EPERM`. `ENOSYS` is attributed to seccomp if the kernel version
+/// is at least 5.3 or a seccomp filter is installed and the release cannot be parsed. Other errnos
+/// are attributed to seccomp only if a seccomp filter is installed. Does not allocate.
+pub fn check_clone3_available() -> Result<(), Clone3Unavailable> {
+    match unsafe { clone3_result(ptr::null(), 0) } {
+        Err(Errno(EINVAL)) => Ok(()),
+        Err(errno) => Err(classify_unavailable(
+            errno,
+            kernel_version(),
+            seccomp_filtered(),
+        )),
+        // Only a seccomp filter returning 0 as errno makes the probe succeed.
+        Ok(_) => Err(classify_unavailable(
+            Errno(0),
+            kernel_version(),
+            seccomp_filtered(),
+        )),
+    }
+}
+
+/// Classifies the errno of a failed availability probe.
+fn classify_unavailable(
+    errno: Errno,
+    version: Option<(u32, u32)>,
+    seccomp_filtered: bool,
+) -> Clone3Unavailable {
+    match (errno.0, version) {
+        (ENOSYS, Some(version)) if version < (5, 3) => Clone3Unavailable::KernelTooOld,
+        (ENOSYS, Some(_)) => Clone3Unavailable::Seccomp,
+        (ENOSYS, None) if !seccomp_filtered => Clone3Unavailable::KernelTooOld,
+        _ if seccomp_filtered => Clone3Unavailable::Seccomp,
+        _ => Clone3Unavailable::Other(errno),
+    }
+}
+
+/// Whether a seccomp filter is installed for the calling thread.
+fn seccomp_filtered() -> bool {
+    unsafe { prctl(PR_GET_SECCOMP) == SECCOMP_MODE_FILTER as c_int }
+}
+
+/// The major and minor version of the running kernel.
+fn kernel_version() -> Option<(u32, u32)> {
+    let mut uts: utsname = unsafe { mem::zeroed() };
+    if unsafe { uname(&mut uts) } == -1 {
+        return None;
+    }
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) }
+        .to_str()
+        .ok()?;
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+impl CloneArgs {
+    /// Returns the smallest `CLONE_ARGS_SIZE_VER*` size that includes all fields that are set.
+    ///
+    /// Passing a smaller size to the kernel silently drops those fields.
+    pub fn min_size(&self) -> usize {
+        if self.cgroup != 0 || self.flags & Flags::INTO_CGROUP.bits() != 0 {
+            return CLONE_ARGS_SIZE_VER2;
+        }
+        if self.set_tid != 0 || self.set_tid_size != 0 {
+            return CLONE_ARGS_SIZE_VER1;
+        }
+        CLONE_ARGS_SIZE_VER0
+    }
+
+    /// Returns the smallest `CLONE_ARGS_SIZE_VER*` size of a kernel that understands all set fields
+    /// and flags.
+    ///
+    /// Unlike [`min_size`](Self::min_size) this also considers flags that were added together with
+    /// a new version of the struct.
+    pub fn required_kernel_size(&self) -> usize {
+        let size = if self.flags & Flags::CLEAR_SIGHAND.bits() != 0 {
+            CLONE_ARGS_SIZE_VER1
+        } else {
+            CLONE_ARGS_SIZE_VER0
+        };
+        size.max(self.min_size())
+    }
+
+    /// Returns why the kernel rejects these arguments with `EINVAL` before using them.
+    ///
+    /// Mirrors the checks of `copy_clone_args_from_user` and `clone3_args_valid` in
+    /// `kernel/fork.c`. [`Clone3::try_call`](crate::Clone3::try_call) performs this check before
+    /// the system call.
+    pub fn find_invalid(&self) -> Option<&'static str> {
+        if self.flags & !Flags::all().bits() != 0 {
+            return Some("unknown flag bits are set");
+        }
+        let flags = Flags::from_bits_truncate(self.flags);
+        if let Some(reason) = crate::wrapper::find_invalid_exit_signal(flags, self.exit_signal) {
+            return Some(reason);
+        }
+        if flags.contains(Flags::CLEAR_SIGHAND | Flags::SIGHAND) {
+            return Some("CLEAR_SIGHAND and SIGHAND cannot be set together");
+        }
+        if (self.stack == 0) != (self.stack_size == 0) {
+            return Some("stack and stack_size must both be zero or both be nonzero");
+        }
+        if (self.set_tid == 0) != (self.set_tid_size == 0) {
+            return Some("set_tid and set_tid_size must both be zero or both be nonzero");
+        }
+        if self.set_tid_size > crate::MAX_PID_NS_LEVEL as u64 {
+            return Some("set_tid_size is larger than MAX_PID_NS_LEVEL");
+        }
+        if flags.contains(Flags::INTO_CGROUP) && self.cgroup > c_int::MAX as u64 {
+            return Some("cgroup is not a file descriptor");
+        }
+        None
+    }
+}
+
+/// Returns the first Linux version that supports the given `CLONE_ARGS_SIZE_VER*` size.
+pub(crate) fn kernel_version_for_size(size: usize) -> &'static str {
+    match size {
+        CLONE_ARGS_SIZE_VER0 => "5.3",
+        CLONE_ARGS_SIZE_VER1 => "5.5",
+        _ => "5.7",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_child;
+
+    #[test]
+    fn pid_results() {
+        use crate::sys::{c::ENOSYS, set_errno};
+        use std::panic::catch_unwind;
+
+        set_errno(ENOSYS);
+        assert_eq!(pid_from_libc_result(-1), Err(Errno(ENOSYS)));
+        assert_eq!(pid_from_libc_result(0), Ok(0));
+        assert_eq!(pid_from_libc_result(pid_t::MAX as c_long), Ok(pid_t::MAX));
+        assert!(catch_unwind(|| pid_from_libc_result(-2)).is_err());
+        assert_eq!(pid_from_raw_result(-1), Err(Errno(1)));
+        assert_eq!(pid_from_raw_result(-4095), Err(Errno(4095)));
+        assert_eq!(pid_from_raw_result(7), Ok(7));
+        assert!(catch_unwind(|| pid_from_raw_result(-4096)).is_err());
+        assert!(catch_unwind(|| pid_from_raw_result(c_long::MIN)).is_err());
+        #[cfg(target_pointer_width = "32")]
+        {
+            assert_eq!(mem::size_of::<c_long>(), mem::size_of::<pid_t>());
+            assert_eq!(pid_from_raw_result(c_long::MAX), Ok(pid_t::MAX));
+        }
+        #[cfg(target_pointer_width = "64")]
+        {
+            assert!(catch_unwind(|| pid_from_libc_result(pid_t::MAX as c_long + 1)).is_err());
+            assert!(catch_unwind(|| pid_from_raw_result(c_long::MAX)).is_err());
+        }
+    }
+
+    #[test]
+    fn kernel_supports_full_clone_args() {
+        assert_eq!(max_clone_args_size(), Ok(CLONE_ARGS_SIZE_VER2));
+    }
+
+    #[test]
+    fn clone3_available() {
+        use crate::sys::c;
+        use crate::{Clone3, Clone3Error};
+
+        assert_eq!(check_clone3_available(), Ok(()));
+        assert!(kernel_version().unwrap() >= (5, 3));
+        // Fail clone3 with ENOSYS and allow everything else.
+        let filter = unsafe {
+            [
+                c::BPF_STMT((c::BPF_LD | c::BPF_W | c::BPF_ABS) as u16, 0),
+                c::BPF_JUMP(
+                    (c::BPF_JMP | c::BPF_JEQ | c::BPF_K) as u16,
+                    crate::sys::SYS_clone3 as u32,
+                    0,
+                    1,
+                ),
+                c::BPF_STMT(
+                    (c::BPF_RET | c::BPF_K) as u16,
+                    c::SECCOMP_RET_ERRNO | ENOSYS as u32,
+                ),
+                c::BPF_STMT((c::BPF_RET | c::BPF_K) as u16, c::SECCOMP_RET_ALLOW),
+            ]
+        };
+        // Without no_new_privs installing a filter requires CAP_SYS_ADMIN.
+        if unsafe { c::geteuid() } != 0 {
+            return;
+        }
+        let mut clone3 = Clone3::default();
+        clone3.seccomp_filter(&filter);
+        assert_child(&mut clone3, || unsafe {
+            let blocked = check_clone3_available() == Err(Clone3Unavailable::Seccomp);
+            let classified = matches!(
+                Clone3::default().try_call(),
+                Err(Clone3Error::Unavailable {
+                    reason: Clone3Unavailable::Seccomp,
+                    errno: Errno(ENOSYS),
+                })
+            );
+            blocked && classified
+        });
+    }
+
+    #[test]
+    fn classify_unavailable_errnos() {
+        use Clone3Unavailable::*;
+        let errno = Errno(ENOSYS);
+        assert_eq!(
+            classify_unavailable(errno, Some((5, 2)), true),
+            KernelTooOld
+        );
+        assert_eq!(classify_unavailable(errno, Some((5, 3)), false), Seccomp);
+        assert_eq!(classify_unavailable(errno, None, false), KernelTooOld);
+        assert_eq!(classify_unavailable(errno, None, true), Seccomp);
+        let errno = Errno(crate::sys::c::EACCES);
+        assert_eq!(classify_unavailable(errno, Some((6, 1)), true), Seccomp);
+        assert_eq!(
+            classify_unavailable(errno, Some((6, 1)), false),
+            Other(errno)
+        );
+    }
+
+    #[test]
+    fn min_size_of_default_args() {
+        assert_eq!(CloneArgs::default().min_size(), CLONE_ARGS_SIZE_VER0);
+    }
+
+    #[test]
+    fn find_invalid_matches_kernel() {
+        assert_eq!(CloneArgs::default().find_invalid(), None);
+        let invalid = [
+            CloneArgs {
+                flags: 1 << 40,
+                ..Default::default()
+            },
+            CloneArgs {
+                flags: Flags::THREAD.bits(),
+                exit_signal: 17,
+                ..Default::default()
+            },
+            CloneArgs {
+                stack_size: 4096,
+                ..Default::default()
+            },
+            CloneArgs {
+                set_tid_size: 1,
+                ..Default::default()
+            },
+            CloneArgs {
+                flags: Flags::INTO_CGROUP.bits(),
+                cgroup: u64::MAX,
+                ..Default::default()
+            },
+        ];
+        for args in &invalid {
+            assert!(args.find_invalid().is_some(), "{:?}", args);
+            assert_eq!(unsafe { clone3_system_call(args) }, -1);
+            assert_eq!(Errno::default(), Errno(EINVAL), "{:?}", args);
+        }
+    }
 }
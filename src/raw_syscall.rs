@@ -0,0 +1,149 @@
+//! System calls through inline assembly that do not touch errno.
+
+use crate::{
+    sys::{SYS_clone3, SYS_pidfd_open, SYS_pidfd_send_signal},
+    CloneArgs,
+};
+use std::{
+    arch::asm,
+    mem,
+    os::raw::{c_int, c_long, c_uint},
+    ptr,
+};
+
+/// Performs system call `number` with up to six arguments without going through libc.
+///
+/// Returns the raw result which is the negated errno on failure. Unlike libc's `syscall` this
+/// never writes the thread local errno which can be shared with the parent in a `VM` child
+/// without `SETTLS` and must not be clobbered in signal handlers.
+pub unsafe fn raw_syscall(number: c_long, args: [usize; 6]) -> c_long {
+    let return_value: c_long;
+    #[cfg(target_arch = "x86_64")]
+    asm!(
+        "syscall",
+        inlateout("rax") number => return_value,
+        in("rdi") args[0],
+        in("rsi") args[1],
+        in("rdx") args[2],
+        in("r10") args[3],
+        in("r8") args[4],
+        in("r9") args[5],
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack),
+    );
+    #[cfg(target_arch = "aarch64")]
+    asm!(
+        "svc 0",
+        inlateout("x0") args[0] => return_value,
+        in("x1") args[1],
+        in("x2") args[2],
+        in("x3") args[3],
+        in("x4") args[4],
+        in("x5") args[5],
+        in("x8") number,
+        options(nostack),
+    );
+    #[cfg(target_arch = "riscv64")]
+    asm!(
+        "ecall",
+        inlateout("a0") args[0] => return_value,
+        in("a1") args[1],
+        in("a2") args[2],
+        in("a3") args[3],
+        in("a4") args[4],
+        in("a5") args[5],
+        in("a7") number,
+        options(nostack),
+    );
+    return_value
+}
+
+/// Like [`clone3_system_call_with_size`](crate::clone3_system_call_with_size) but through
+/// [`raw_syscall`].
+///
+/// Returns the negated errno on failure. `-ENOSYS` is returned without a system call where
+/// clone3 is known to be blocked.
+///
+/// # Panics
+///
+/// Panics if `size` is larger than `size_of::<CloneArgs>()`.
+pub unsafe fn raw_clone3(cl_args: &CloneArgs, size: usize) -> c_long {
+    assert!(
+        size <= mem::size_of::<CloneArgs>(),
+        "clone_args size {} is larger than CloneArgs",
+        size
+    );
+    if !crate::sys::clone3_allowed() {
+        return -(crate::sys::c::ENOSYS as c_long);
+    }
+    let args = [cl_args as *const CloneArgs as usize, size, 0, 0, 0, 0];
+    raw_syscall(SYS_clone3, args)
+}
+
+/// pidfd_open through [`raw_syscall`]. Returns the fd or the negated errno.
+pub fn raw_pidfd_open(pid: c_int, flags: c_uint) -> c_long {
+    unsafe { raw_syscall(SYS_pidfd_open, [pid as usize, flags as usize, 0, 0, 0, 0]) }
+}
+
+/// pidfd_send_signal without `siginfo` through [`raw_syscall`]. Returns 0 or the negated errno.
+pub fn raw_pidfd_send_signal(pidfd: c_int, signal: c_int) -> c_long {
+    let args = [
+        pidfd as usize,
+        signal as usize,
+        ptr::null::<u8>() as usize,
+        0,
+        0,
+        0,
+    ];
+    unsafe { raw_syscall(SYS_pidfd_send_signal, args) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sys::{
+            c::{self, EBADF, EINVAL, SIGKILL},
+            set_errno, Errno,
+        },
+        ChildStatus, Clone3, Flags, PidFd, TidSpec, CLONE_ARGS_SIZE_VER0,
+    };
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    #[test]
+    fn errno_untouched() {
+        set_errno(0);
+        assert_eq!(raw_pidfd_open(-1, 0), -(EINVAL as c_long));
+        assert_eq!(raw_pidfd_send_signal(-1, 0), -(EBADF as c_long));
+        assert_eq!(Errno::default(), Errno(0));
+        let pid = unsafe { raw_syscall(c::SYS_getpid, [0; 6]) };
+        assert_eq!(pid, unsafe { c::getpid() } as c_long);
+        let mut pidfd = -1;
+        let cl_args = CloneArgs {
+            flags: Flags::PIDFD.bits(),
+            pidfd: &mut pidfd as *mut c_int as u64,
+            ..Default::default()
+        };
+        match unsafe { raw_clone3(&cl_args, CLONE_ARGS_SIZE_VER0) } {
+            0 => loop {
+                unsafe { c::pause() };
+            },
+            pid => assert!(pid > 0, "{}", pid),
+        }
+        let pidfd = unsafe { PidFd::from_raw_fd(pidfd) };
+        assert_eq!(raw_pidfd_send_signal(pidfd.as_raw_fd(), SIGKILL), 0);
+        assert_eq!(pidfd.wait(), Ok(ChildStatus::Signaled(SIGKILL, false)));
+    }
+
+    #[test]
+    fn try_call_leaves_errno() {
+        // Fails with EEXIST or without privileges with EPERM.
+        let set_tid = TidSpec::from(&[1][..]);
+        let mut clone3 = Clone3::default();
+        clone3.set_tid(&set_tid);
+        set_errno(0);
+        assert!(unsafe { clone3.try_call() }.is_err());
+        assert_eq!(Errno::default(), Errno(0));
+    }
+}
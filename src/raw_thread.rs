@@ -0,0 +1,180 @@
+use crate::sys::{
+    c::{self, pid_t, sigset_t, SYS_exit, SIG_SETMASK},
+    Errno,
+};
+use crate::{
+    default_stack_size, raw::pid_from_raw_result, vfork::clone3_with_entry, ClearTidHandle,
+    CloneArgs, CloneStack, Flags, TlsBlock, CLONE_ARGS_SIZE_VER0,
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    mem::{self, ManuallyDrop},
+    os::raw::c_void,
+    ptr,
+};
+
+type Entry = Box<dyn FnMut() + Send>;
+
+/// A thread created directly with clone3 instead of pthreads.
+///
+/// The thread is created with `VM | FS | FILES | SIGHAND | THREAD | SYSVSEM` like a pthread so it
+/// is part of the thread group of the caller. It runs on its own [`CloneStack`] with a
+/// [`TlsBlock`] set through `SETTLS` and starts with all signals blocked. The exit of the thread
+/// is observed through a [`ClearTidHandle`].
+///
+/// Dropping a `RawThread` that is still running leaks its stack and closure because the thread
+/// keeps using them.
+///
+/// Only available on x86_64 and aarch64 because the thread has to be started on the new stack in
+/// assembly.
+pub struct RawThread {
+    tid: pid_t,
+    handle: ManuallyDrop<ClearTidHandle>,
+    stack: ManuallyDrop<CloneStack>,
+    tls: ManuallyDrop<TlsBlock>,
+    entry: ManuallyDrop<Box<Entry>>,
+}
+
+impl RawThread {
+    /// Spawns a thread with a stack of [`default_stack_size`] that calls `f` once and exits.
+    ///
+    /// # Safety
+    ///
+    /// The thread has no thread locals of libc or Rust. `f` must not use thread locals, `errno`,
+    /// functions of libc that depend on them like `malloc`, allocate, panic or print. Captured
+    /// values are dropped by the caller.
+    pub unsafe fn spawn<F>(f: F) -> Result<Self, Errno>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self::spawn_with_stack(CloneStack::new(default_stack_size())?, f)
+    }
+
+    /// Like [`spawn`](Self::spawn) with the thread running on `stack`.
+    ///
+    /// # Safety
+    ///
+    /// See [`spawn`](Self::spawn).
+    pub unsafe fn spawn_with_stack<F>(mut stack: CloneStack, f: F) -> Result<Self, Errno>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let handle = ClearTidHandle::new();
+        let tls = TlsBlock::new();
+        let mut entry: Box<Entry> = Box::new(Box::new(f));
+        let memory = stack.as_uninit_slice_mut();
+        let flags = Flags::VM
+            | Flags::FS
+            | Flags::FILES
+            | Flags::SIGHAND
+            | Flags::THREAD
+            | Flags::SYSVSEM
+            | Flags::SETTLS
+            | Flags::PARENT_SETTID
+            | Flags::CHILD_CLEARTID;
+        let cl_args = CloneArgs {
+            flags: flags.bits(),
+            child_tid: handle.as_ptr() as u64,
+            parent_tid: handle.as_ptr() as u64,
+            stack: memory.as_mut_ptr() as u64,
+            stack_size: memory.len() as u64,
+            tls: tls.tls(),
+            ..CloneArgs::default()
+        };
+        // Signal handlers must not run on the thread because they may use thread locals.
+        let mut all: sigset_t = mem::zeroed();
+        let mut mask: sigset_t = mem::zeroed();
+        c::sigfillset(&mut all);
+        c::pthread_sigmask(SIG_SETMASK, &all, &mut mask);
+        let return_value = clone3_with_entry(
+            &cl_args,
+            CLONE_ARGS_SIZE_VER0,
+            thread_main,
+            &mut *entry as *mut Entry as *mut c_void,
+        );
+        c::pthread_sigmask(SIG_SETMASK, &mask, ptr::null_mut());
+        let tid = pid_from_raw_result(return_value)?;
+        Ok(Self {
+            tid,
+            handle: ManuallyDrop::new(handle),
+            stack: ManuallyDrop::new(stack),
+            tls: ManuallyDrop::new(tls),
+            entry: ManuallyDrop::new(entry),
+        })
+    }
+
+    /// The thread id.
+    pub fn tid(&self) -> pid_t {
+        self.tid
+    }
+
+    /// Whether the thread has exited.
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits until the thread has exited and frees its stack.
+    pub fn join(self) -> Result<(), Errno> {
+        self.handle.join()
+    }
+}
+
+impl Debug for RawThread {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawThread")
+            .field("tid", &self.tid)
+            .field("stack", &*self.stack)
+            .field("tls", &*self.tls)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for RawThread {
+    fn drop(&mut self) {
+        if !self.is_finished() {
+            return;
+        }
+        unsafe {
+            ManuallyDrop::drop(&mut self.handle);
+            ManuallyDrop::drop(&mut self.stack);
+            ManuallyDrop::drop(&mut self.tls);
+            ManuallyDrop::drop(&mut self.entry);
+        }
+    }
+}
+
+unsafe extern "C" fn thread_main(entry: *mut c_void) -> ! {
+    (*(entry as *mut Entry))();
+    // Only exits this thread unlike `_exit` which exits the thread group.
+    c::syscall(SYS_exit, 0);
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::SYS_gettid;
+    use std::sync::{
+        atomic::{AtomicI32, AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn runs_closure() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let tid = Arc::new(AtomicI32::new(0));
+        let thread = unsafe {
+            let (calls, tid) = (calls.clone(), tid.clone());
+            RawThread::spawn(move || {
+                tid.store(c::syscall(SYS_gettid) as pid_t, Ordering::Relaxed);
+                calls.fetch_add(1, Ordering::Relaxed);
+            })
+        }
+        .unwrap();
+        let raw_tid = thread.tid();
+        assert_ne!(raw_tid, unsafe { c::getpid() });
+        thread.join().unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(tid.load(Ordering::Relaxed), raw_tid);
+    }
+}
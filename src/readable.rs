@@ -0,0 +1,202 @@
+use crate::sys::{
+    c::{self, EFD_CLOEXEC, POLLIN},
+    Errno,
+};
+use crate::{ChildStatus, PidFd};
+use std::{
+    future::Future,
+    os::unix::io::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+impl PidFd {
+    /// Returns a future that completes when the process has exited.
+    ///
+    /// This works with any async runtime. The first poll that finds the process still running
+    /// spawns an OS thread that blocks until the pidfd becomes readable and then wakes the task.
+    /// Every future spawns its own thread. Dropping the future stops it.
+    ///
+    /// Afterwards [`try_wait`](Self::try_wait) returns the status of a child without blocking. It
+    /// is the nonblocking wait that pairs with this future.
+    pub fn readable(&self) -> Readable<'_> {
+        Readable {
+            pidfd: self,
+            shared: None,
+        }
+    }
+
+    /// Waits for the child to exit without blocking the async runtime.
+    ///
+    /// Combines [`readable`](Self::readable) and [`try_wait`](Self::try_wait).
+    pub async fn wait_async(&self) -> Result<ChildStatus, Errno> {
+        loop {
+            self.readable().await?;
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+        }
+    }
+}
+
+/// Future returned by [`PidFd::readable`](crate::PidFd::readable).
+///
+/// Polling it while the process is running spawns an OS thread per future.
+#[derive(Debug)]
+pub struct Readable<'a> {
+    pidfd: &'a PidFd,
+    shared: Option<Arc<Shared>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    // Written to when the future is dropped so that the helper thread stops.
+    cancel: OwnedFd,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    result: Option<Result<(), Errno>>,
+    waker: Option<Waker>,
+}
+
+impl Future for Readable<'_> {
+    type Output = Result<(), Errno>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let shared = match &self.shared {
+            Some(shared) => shared.clone(),
+            None => {
+                match poll_readable(&[self.pidfd.as_raw_fd()], 0) {
+                    Ok(true) => return Poll::Ready(Ok(())),
+                    Ok(false) => (),
+                    Err(errno) => return Poll::Ready(Err(errno)),
+                }
+                let shared = match start_thread(self.pidfd) {
+                    Ok(shared) => shared,
+                    Err(errno) => return Poll::Ready(Err(errno)),
+                };
+                self.shared = Some(shared.clone());
+                shared
+            }
+        };
+        let mut state = shared.state.lock().unwrap();
+        match state.result {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Readable<'_> {
+    fn drop(&mut self) {
+        if let Some(shared) = &self.shared {
+            let value = 1u64;
+            unsafe {
+                c::write(
+                    shared.cancel.as_raw_fd(),
+                    &value as *const u64 as *const _,
+                    8,
+                )
+            };
+        }
+    }
+}
+
+fn start_thread(pidfd: &PidFd) -> Result<Arc<Shared>, Errno> {
+    // The thread can outlive the borrow of the pidfd so it polls a duplicate.
+    let pidfd = pidfd.as_fd().try_clone_to_owned().map_err(io_to_errno)?;
+    let cancel = unsafe { c::eventfd(0, EFD_CLOEXEC) };
+    if cancel == -1 {
+        return Err(Errno::default());
+    }
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State::default()),
+        cancel: unsafe { OwnedFd::from_raw_fd(cancel) },
+    });
+    let thread_shared = shared.clone();
+    thread::Builder::new()
+        .name("clone3-pidfd".to_string())
+        .spawn(move || {
+            let fds = [pidfd.as_raw_fd(), thread_shared.cancel.as_raw_fd()];
+            let result = poll_readable(&fds, -1).map(|_| ());
+            let mut state = thread_shared.state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        })
+        .map_err(io_to_errno)?;
+    Ok(shared)
+}
+
+/// Returns whether any of `fds` is readable within `timeout` milliseconds.
+fn poll_readable(fds: &[c::c_int], timeout: c::c_int) -> Result<bool, Errno> {
+    let mut pollfds = [c::pollfd {
+        fd: -1,
+        events: POLLIN,
+        revents: 0,
+    }; 2];
+    for (pollfd, fd) in pollfds.iter_mut().zip(fds) {
+        pollfd.fd = *fd;
+    }
+    loop {
+        match unsafe { c::poll(pollfds.as_mut_ptr(), fds.len() as _, timeout) } {
+            -1 if Errno::default().0 == c::EINTR => continue,
+            -1 => return Err(Errno::default()),
+            ready => return Ok(ready > 0),
+        }
+    }
+}
+
+fn io_to_errno(err: std::io::Error) -> Errno {
+    Errno(err.raw_os_error().unwrap_or(c::EIO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Clone3;
+    use std::task::Wake;
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn wait_async() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let pidfd = match unsafe { clone3.spawn() }.unwrap() {
+            None => {
+                thread::sleep(std::time::Duration::from_millis(50));
+                unsafe { c::_exit(7) }
+            }
+            Some(mut child) => child.take_pidfd().unwrap(),
+        };
+        assert_eq!(block_on(pidfd.wait_async()), Ok(ChildStatus::Exited(7)));
+    }
+}
@@ -0,0 +1,282 @@
+use crate::sys::{
+    c::{self, pid_t, ECHILD, O_CLOEXEC, SIGCHLD, SIGKILL},
+    Errno,
+};
+use crate::{
+    setup::check,
+    tid_spec::{max_levels, pid_namespace_levels},
+    wrapper::find_incompatible_flags,
+    Child, Clone3, Clone3Error, Flags, TidSpec,
+};
+use std::{
+    mem,
+    os::raw::{c_int, c_void},
+};
+
+/// A process to restore with its pids and children for checkpoint/restore tools.
+///
+/// [`restore`](Self::restore) creates the processes of the tree with
+/// [`set_tid`](crate::Clone3::set_tid) so that each one gets its pids in all pid namespace levels
+/// and is the child of the process it belongs to. A process that starts a new pid namespace sets
+/// `NEWPID` through [`flags`](Self::flags) and specifies its pid in the new namespace as the
+/// innermost level of its [`TidSpec`], usually 1. Its children then specify their pids relative to
+/// that namespace.
+#[derive(Clone, Debug, Default)]
+pub struct RestoreTree {
+    tids: TidSpec,
+    flags: Flags,
+    children: Vec<RestoreTree>,
+}
+
+impl RestoreTree {
+    pub fn new(tids: TidSpec) -> Self {
+        Self {
+            tids,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the flags used to create this process like `NEWPID`. The exit signal is always
+    /// `SIGCHLD`.
+    pub fn flags(&mut self, flags: Flags) -> &mut Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Adds a child created by this process. Children are created in the order they are added.
+    pub fn child(&mut self, child: RestoreTree) -> &mut Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn tids(&self) -> &TidSpec {
+        &self.tids
+    }
+
+    pub fn children(&self) -> &[RestoreTree] {
+        &self.children
+    }
+
+    /// Creates the processes of the tree and returns the root.
+    ///
+    /// Each process first creates its children depth first. Once its whole subtree exists it
+    /// calls `f` with its node and the pids of its children as seen in its own pid namespace and
+    /// exits with the returned code. `f` restores the actual state of the process and usually does
+    /// not return.
+    ///
+    /// If a process cannot be created the processes created so far are killed and the error is
+    /// returned as [`Clone3Error::ChildSetup`] with the step `restore`. Invalid flags or pid
+    /// specifications are returned before any process is created.
+    ///
+    /// # Safety
+    ///
+    /// Like [`Clone3::spawn`]. `f` runs in the created processes which do not allocate before
+    /// calling it.
+    pub unsafe fn restore<F>(&self, f: F) -> Result<Child, Clone3Error>
+    where
+        F: Fn(&RestoreTree, &[pid_t]) -> c_int,
+    {
+        self.validate(pid_namespace_levels())?;
+        // Allocated here because allocating in the children can deadlock.
+        let mut pids = Vec::with_capacity(self.max_children());
+        let (read, write) = pipe().map_err(Clone3Error::System)?;
+        let mut clone3 = self.clone3();
+        let child = match clone3.spawn() {
+            Ok(Some(child)) => child,
+            Ok(None) => {
+                c::close(read);
+                self.restore_in_child(write, &mut pids, &f)
+            }
+            Err(err) => {
+                c::close(read);
+                c::close(write);
+                return Err(err);
+            }
+        };
+        c::close(write);
+        let status = read_status(read);
+        c::close(read);
+        match status {
+            0 => Ok(child),
+            errno => {
+                let mut child = child;
+                let _ = child.kill(SIGKILL);
+                let _ = child.wait();
+                Err(Clone3Error::ChildSetup {
+                    step: "restore",
+                    errno: Errno(errno),
+                })
+            }
+        }
+    }
+
+    /// Validates the tree for a root created by a process in `levels` pid namespaces.
+    fn validate(&self, levels: Option<usize>) -> Result<(), Clone3Error> {
+        let flags = self.flags;
+        if let Some(reason) = find_incompatible_flags(flags) {
+            return Err(Clone3Error::IncompatibleFlags { flags, reason });
+        }
+        self.tids.validate(max_levels(levels, flags))?;
+        let levels = levels.map(|levels| levels + flags.contains(Flags::NEWPID) as usize);
+        self.children
+            .iter()
+            .try_for_each(|child| child.validate(levels))
+    }
+
+    fn max_children(&self) -> usize {
+        self.children
+            .iter()
+            .map(Self::max_children)
+            .fold(self.children.len(), usize::max)
+    }
+
+    fn clone3(&self) -> Clone3<'_> {
+        let mut clone3 = Clone3::default();
+        clone3.exit_signal(SIGCHLD as u64).set_tid(&self.tids);
+        clone3.flags = self.flags;
+        clone3
+    }
+
+    /// Creates the children of this process, reports the result to the parent through `report`
+    /// and calls `f`.
+    unsafe fn restore_in_child<F>(&self, report: c_int, pids: &mut Vec<pid_t>, f: &F) -> !
+    where
+        F: Fn(&RestoreTree, &[pid_t]) -> c_int,
+    {
+        pids.clear();
+        for child in &self.children {
+            if let Err(errno) = child.create(report, pids, f) {
+                for &pid in pids.iter() {
+                    c::kill(pid, SIGKILL);
+                }
+                write_status(report, errno.0);
+                c::_exit(127);
+            }
+        }
+        write_status(report, 0);
+        c::close(report);
+        c::_exit(f(self, pids))
+    }
+
+    /// Creates this process as a child of the calling process and waits until its subtree exists.
+    unsafe fn create<F>(
+        &self,
+        parent_report: c_int,
+        pids: &mut Vec<pid_t>,
+        f: &F,
+    ) -> Result<(), Errno>
+    where
+        F: Fn(&RestoreTree, &[pid_t]) -> c_int,
+    {
+        let (read, write) = pipe()?;
+        let pid = match self.clone3().call() {
+            Ok(0) => {
+                c::close(read);
+                c::close(parent_report);
+                self.restore_in_child(write, pids, f)
+            }
+            Ok(pid) => pid,
+            Err(errno) => {
+                c::close(read);
+                c::close(write);
+                return Err(errno);
+            }
+        };
+        c::close(write);
+        let status = read_status(read);
+        c::close(read);
+        if status != 0 {
+            c::waitpid(pid, std::ptr::null_mut(), 0);
+            return Err(Errno(status));
+        }
+        pids.push(pid);
+        Ok(())
+    }
+}
+
+fn pipe() -> Result<(c_int, c_int), Errno> {
+    let mut fds = [0; 2];
+    check(unsafe { c::pipe2(fds.as_mut_ptr(), O_CLOEXEC) })?;
+    Ok((fds[0], fds[1]))
+}
+
+/// Writes the errno of a failed restore or 0 on success.
+fn write_status(fd: c_int, errno: c_int) {
+    unsafe {
+        c::write(
+            fd,
+            &errno as *const c_int as *const c_void,
+            mem::size_of::<c_int>(),
+        )
+    };
+}
+
+/// Reads the status written by [`write_status`]. A child that exited without writing it failed
+/// with `ECHILD`.
+fn read_status(fd: c_int) -> c_int {
+    let mut status: c_int = 0;
+    let n = unsafe {
+        c::read(
+            fd,
+            &mut status as *mut c_int as *mut c_void,
+            mem::size_of::<c_int>(),
+        )
+    };
+    if n == mem::size_of::<c_int>() as isize {
+        status
+    } else {
+        ECHILD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::EEXIST;
+    use crate::{ChildStatus, Pid};
+
+    #[test]
+    fn restore_nested_tree() {
+        // Setting pids requires CAP_CHECKPOINT_RESTORE or CAP_SYS_ADMIN.
+        if unsafe { c::geteuid() } != 0 {
+            return;
+        }
+        let outer = (300..32768)
+            .rev()
+            .find(|&pid| !Pid::from_raw(pid).unwrap().exists())
+            .unwrap();
+        let mut leaf = RestoreTree::new(TidSpec::from(vec![9]));
+        let mut middle = RestoreTree::new(TidSpec::from(vec![5]));
+        middle.child(leaf.clone());
+        leaf = RestoreTree::new(TidSpec::from(vec![7]));
+        let mut root = RestoreTree::new(TidSpec::from(vec![1, outer]));
+        root.flags(Flags::NEWPID).child(middle).child(leaf);
+        let mut child = unsafe {
+            root.restore(|node, children| {
+                let mut ok = c::getpid() == node.tids().as_slice()[0];
+                for (&pid, expected) in children.iter().zip(node.children()) {
+                    let mut status = 0;
+                    c::waitpid(pid, &mut status, 0);
+                    ok &= pid == expected.tids().as_slice()[0] && status == 0;
+                }
+                ok &= children.len() == node.children().len();
+                !ok as c_int
+            })
+        }
+        .unwrap();
+        assert_eq!(child.pid().as_raw(), outer);
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(0)));
+        // The second child cannot get the pid of the first.
+        let mut root = RestoreTree::new(TidSpec::from(vec![1]));
+        root.flags(Flags::NEWPID)
+            .child(RestoreTree::new(TidSpec::from(vec![5])))
+            .child(RestoreTree::new(TidSpec::from(vec![5])));
+        match unsafe { root.restore(|_, _| 0) } {
+            Err(Clone3Error::ChildSetup {
+                step: "restore",
+                errno: Errno(EEXIST),
+            }) => (),
+            result => panic!("{:?}", result),
+        }
+    }
+}
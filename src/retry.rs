@@ -0,0 +1,99 @@
+use std::{thread, time::Duration};
+
+/// How a system call failing with `EAGAIN` is retried. See
+/// [`Clone3::retry_eagain`](crate::Clone3::retry_eagain).
+///
+/// clone3 fails with `EAGAIN` while the pids of the system, `RLIMIT_NPROC` or `pids.max` of the
+/// cgroup are exhausted which is often momentary when many short lived processes are spawned. The
+/// delay starts at `initial_delay` and doubles after each retry up to `max_delay`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt.
+    pub retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Retries `retries` times starting with a delay of `initial_delay` and a maximum delay of 1
+    /// second.
+    pub fn new(retries: u32, initial_delay: Duration) -> Self {
+        Self {
+            retries,
+            initial_delay,
+            max_delay: Duration::from_secs(1),
+        }
+    }
+
+    pub fn max_delay(&mut self, max_delay: Duration) -> &mut Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// The delay before retry `retry` counted from 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        2u32.checked_pow(retry)
+            .and_then(|factor| self.initial_delay.checked_mul(factor))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+impl Default for RetryPolicy {
+    /// 5 retries starting at 1 millisecond with a maximum of 100 milliseconds.
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Calls `call` again after sleeping while `is_eagain` holds for its result and `policy` allows
+/// it. Does not allocate.
+pub(crate) fn retry<T>(
+    policy: Option<RetryPolicy>,
+    mut call: impl FnMut() -> T,
+    is_eagain: impl Fn(&T) -> bool,
+) -> T {
+    let mut result = call();
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return result,
+    };
+    for retry in 0..policy.retries {
+        if !is_eagain(&result) {
+            break;
+        }
+        thread::sleep(policy.delay(retry));
+        result = call();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_child;
+    use crate::Clone3;
+    use std::cell::Cell;
+
+    #[test]
+    fn backoff() {
+        let mut policy = RetryPolicy::new(3, Duration::from_millis(1));
+        assert_eq!(policy.delay(0), Duration::from_millis(1));
+        assert_eq!(policy.delay(3), Duration::from_millis(8));
+        assert_eq!(policy.delay(40), Duration::from_secs(1));
+        policy.max_delay(Duration::from_millis(5));
+        assert_eq!(policy.delay(3), Duration::from_millis(5));
+        let calls = Cell::new(0);
+        let policy = RetryPolicy::new(3, Duration::ZERO);
+        let result = retry(Some(policy), || calls.replace(calls.get() + 1), |&n| n < 10);
+        assert_eq!((result, calls.get()), (3, 4));
+        let result = retry(Some(policy), || calls.replace(calls.get() + 1), |&n| n < 5);
+        assert_eq!((result, calls.get()), (5, 6));
+        let mut clone3 = Clone3::default();
+        clone3.retry_eagain(RetryPolicy::default());
+        assert_child(&mut clone3, || true);
+    }
+}
@@ -0,0 +1,1972 @@
+use crate::sys::{
+    c::{
+        self, cpu_set_t, gid_t, mode_t, pid_t, rlimit, sock_filter, sock_fprog, uid_t, SYS_capget,
+        SYS_capset, SYS_close_range, SYS_getdents64, SYS_ioprio_set, SYS_landlock_restrict_self,
+        SYS_sched_setattr, SYS_seccomp, SYS_set_mempolicy, CPU_SETSIZE, EBADF, ECHILD, EINTR,
+        EINVAL, ENOENT, ENOSYS, EPIPE, ESRCH, FD_CLOEXEC, F_DUPFD_CLOEXEC, F_SETFD, O_CLOEXEC,
+        O_DIRECTORY, O_NOCTTY, O_RDONLY, O_RDWR, O_WRONLY, PRIO_PROCESS, PR_CAPBSET_DROP,
+        PR_CAPBSET_READ, PR_CAP_AMBIENT, PR_CAP_AMBIENT_LOWER, PR_SET_DUMPABLE, PR_SET_NAME,
+        PR_SET_NO_NEW_PRIVS, PR_SET_PDEATHSIG, RLIMIT_CORE, SECCOMP_SET_MODE_FILTER, TIOCSCTTY,
+    },
+    Errno,
+};
+use crate::{
+    init,
+    mount::{Mount, Root},
+    namespace, net,
+    sys::{
+        sched_attr, SYS_setgroups, SYS_setresgid, SYS_setresuid, CLOSE_RANGE_CLOEXEC, TIOCGPTPEER,
+    },
+    CgroupFd, Flags, Namespace, ScopedCgroup,
+};
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fmt::{self, Debug, Formatter},
+    fs::File,
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    os::{
+        raw::{c_char, c_int, c_uint, c_ulong, c_ushort},
+        unix::{
+            ffi::OsStrExt,
+            io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        },
+    },
+    slice,
+};
+
+/// How to set up a standard stream of the child.
+#[derive(Debug, Default)]
+pub enum Stdio {
+    /// Inherit the stream of the parent or use the pty if [`pty`](crate::Clone3::pty) is set.
+    #[default]
+    Inherit,
+    /// Connect the stream to a new pipe. The parent end is available through the
+    /// [`Child`](crate::Child) handle or the `take_*` methods of [`Clone3`](crate::Clone3).
+    Piped,
+    /// Connect the stream to `/dev/null`.
+    Null,
+    /// Redirect the stream to an existing fd like an open file. The fd is duplicated for each
+    /// call.
+    Fd(OwnedFd),
+}
+
+impl From<OwnedFd> for Stdio {
+    fn from(fd: OwnedFd) -> Self {
+        Self::Fd(fd)
+    }
+}
+
+impl From<File> for Stdio {
+    fn from(file: File) -> Self {
+        Self::Fd(file.into())
+    }
+}
+
+/// A set of CPUs for [`cpu_affinity`](crate::Clone3::cpu_affinity).
+///
+/// CPUs are numbered from 0 to 1023.
+#[derive(Clone, Copy)]
+pub struct CpuSet(cpu_set_t);
+
+impl CpuSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self(unsafe { mem::zeroed() })
+    }
+
+    /// Adds `cpu` to the set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu` is greater than 1023.
+    pub fn set(&mut self, cpu: usize) -> &mut Self {
+        assert!(cpu < CPU_SETSIZE as usize, "cpu out of range");
+        unsafe { c::CPU_SET(cpu, &mut self.0) };
+        self
+    }
+
+    /// Removes `cpu` from the set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu` is greater than 1023.
+    pub fn clear(&mut self, cpu: usize) -> &mut Self {
+        assert!(cpu < CPU_SETSIZE as usize, "cpu out of range");
+        unsafe { c::CPU_CLR(cpu, &mut self.0) };
+        self
+    }
+
+    pub fn is_set(&self, cpu: usize) -> bool {
+        cpu < CPU_SETSIZE as usize && unsafe { c::CPU_ISSET(cpu, &self.0) }
+    }
+
+    /// The number of CPUs in the set.
+    pub fn count(&self) -> usize {
+        unsafe { c::CPU_COUNT(&self.0) as usize }
+    }
+
+    /// Iterates over the CPUs in the set in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..CPU_SETSIZE as usize).filter(|&cpu| self.is_set(cpu))
+    }
+}
+
+impl Default for CpuSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for CpuSet {
+    fn eq(&self, other: &Self) -> bool {
+        unsafe { c::CPU_EQUAL(&self.0, &other.0) }
+    }
+}
+
+impl Eq for CpuSet {}
+
+impl Debug for CpuSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<usize> for CpuSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(cpus: I) -> Self {
+        let mut set = Self::new();
+        for cpu in cpus {
+            set.set(cpu);
+        }
+        set
+    }
+}
+
+/// The I/O scheduling class for [`io_priority`](crate::Clone3::io_priority).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriorityClass {
+    /// Served before the other classes. Requires `CAP_SYS_ADMIN`.
+    RealTime = 1,
+    /// The default class.
+    BestEffort = 2,
+    /// Only served when no other process needs the disk.
+    Idle = 3,
+}
+
+impl IoPriorityClass {
+    /// The value passed to `ioprio_set` for `level` in this class.
+    pub(crate) fn value(self, level: u8) -> c_int {
+        ((self as c_int) << IOPRIO_CLASS_SHIFT) | level as c_int
+    }
+}
+
+const IOPRIO_CLASS_SHIFT: c_int = 13;
+const IOPRIO_WHO_PROCESS: c_int = 1;
+
+/// A range of ids in a user namespace for [`userns_map`](crate::Clone3::userns_map).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdMap {
+    /// The first id in the namespace of the child.
+    pub inside: u32,
+    /// The first id in the namespace of the parent that `inside` maps to.
+    pub outside: u32,
+    /// The number of consecutive ids that are mapped.
+    pub count: u32,
+}
+
+impl IdMap {
+    /// Formats the maps as written to `uid_map` and `gid_map`.
+    pub(crate) fn format(maps: &[IdMap]) -> Vec<u8> {
+        maps.iter()
+            .map(|map| format!("{} {} {}\n", map.inside, map.outside, map.count))
+            .collect::<String>()
+            .into_bytes()
+    }
+}
+
+/// The parent side of a [`barrier`](crate::Clone3::barrier).
+#[derive(Debug)]
+pub struct ParentBarrier {
+    wait: OwnedFd,
+    release: OwnedFd,
+}
+
+impl ParentBarrier {
+    /// Lets one call of [`ChildBarrier::wait_for_parent`] in the child return.
+    pub fn release_child(&self) -> Result<(), Errno> {
+        release(&self.release)
+    }
+
+    /// Waits until the child calls [`ChildBarrier::release_parent`].
+    ///
+    /// Returns `EPIPE` if the child closed its end without releasing the parent, for example by
+    /// exiting or executing a program.
+    pub fn wait_for_child(&self) -> Result<(), Errno> {
+        wait(&self.wait)
+    }
+}
+
+/// The child side of a [`barrier`](crate::Clone3::barrier).
+#[derive(Debug)]
+pub struct ChildBarrier {
+    wait: OwnedFd,
+    release: OwnedFd,
+}
+
+impl ChildBarrier {
+    /// Waits until the parent calls [`ParentBarrier::release_child`].
+    ///
+    /// Returns `EPIPE` if the parent closed its end without releasing the child.
+    pub fn wait_for_parent(&self) -> Result<(), Errno> {
+        wait(&self.wait)
+    }
+
+    /// Lets one call of [`ParentBarrier::wait_for_child`] in the parent return.
+    pub fn release_parent(&self) -> Result<(), Errno> {
+        release(&self.release)
+    }
+}
+
+fn release(fd: &OwnedFd) -> Result<(), Errno> {
+    loop {
+        match unsafe { c::write(fd.as_raw_fd(), [0u8].as_ptr() as *const _, 1) } {
+            -1 if Errno::default().0 == EINTR => continue,
+            -1 => return Err(Errno::default()),
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn wait(fd: &OwnedFd) -> Result<(), Errno> {
+    read_message::<1>(fd).map(|_| ()).ok_or(Errno(EPIPE))
+}
+
+/// Steps performed in the child after clone3 returns and before `call` returns 0 there.
+///
+/// The steps are applied in this order:
+/// 1. waiting for the parent to perform userns_map and veth
+/// 2. join_namespace
+/// 3. setsid
+/// 4. process_group
+/// 5. pty or controlling_terminal
+/// 6. stdio
+/// 7. pass_fds
+/// 8. name
+/// 9. rlimits
+/// 10. oom_score_adj
+/// 11. nice
+/// 12. cpu_affinity
+/// 13. sched_attr
+/// 14. mempolicy
+/// 15. personality
+/// 16. io_priority
+/// 17. hostname and domainname
+/// 18. loopback_up and setting the child end of veth up
+/// 19. mounts
+/// 20. pivot_root or chroot
+/// 21. mounting /proc for pid_init
+/// 22. capability bounding and ambient sets
+/// 23. groups, gid and uid
+/// 24. remaining capability sets
+/// 25. parent_death_signal
+/// 26. current_dir
+/// 27. umask
+/// 28. close-on-exec for close_fds and pass_fds
+/// 29. hooks
+/// 30. exec_label
+/// 31. non_dumpable
+/// 32. no_new_privs
+/// 33. landlock_ruleset
+/// 34. seccomp_filter
+/// 35. creating the process for the user's code for pid_init
+#[derive(Debug, Default)]
+pub(crate) struct ChildSetup {
+    // The contents of uid_map and gid_map formatted by the parent.
+    pub(crate) userns_map: Option<(Vec<u8>, Vec<u8>)>,
+    // At most one per type.
+    pub(crate) join_namespaces: Vec<(OwnedFd, Namespace)>,
+    pub(crate) setsid: bool,
+    pub(crate) process_group: Option<pid_t>,
+    pub(crate) pty: bool,
+    pub(crate) controlling_terminal: Option<OwnedFd>,
+    pub(crate) stdio: [Stdio; 3],
+    pub(crate) name: Option<CString>,
+    pub(crate) rlimits: Vec<(c_int, rlimit)>,
+    // Formatted as decimal by the parent.
+    pub(crate) oom_score_adj: Option<Vec<u8>>,
+    pub(crate) nice: Option<c_int>,
+    pub(crate) cpu_affinity: Option<CpuSet>,
+    pub(crate) sched_attr: Option<SchedAttr>,
+    pub(crate) mempolicy: Option<(c_int, Vec<c_ulong>)>,
+    pub(crate) personality: Option<c_ulong>,
+    pub(crate) io_priority: Option<c_int>,
+    pub(crate) hostname: Option<CString>,
+    pub(crate) domainname: Option<CString>,
+    pub(crate) loopback_up: bool,
+    // The names of the host and child end.
+    pub(crate) veth: Option<(CString, CString)>,
+    pub(crate) mounts: Vec<Mount>,
+    pub(crate) root: Option<Root>,
+    pub(crate) pid_init: bool,
+    pub(crate) groups: Option<Vec<gid_t>>,
+    pub(crate) gid: Option<gid_t>,
+    pub(crate) uid: Option<uid_t>,
+    pub(crate) capabilities: Option<u64>,
+    pub(crate) parent_death_signal: Option<c_int>,
+    pub(crate) current_dir: Option<CString>,
+    pub(crate) umask: Option<mode_t>,
+    pub(crate) close_fds: Option<RawFd>,
+    // Pairs of an fd in the parent and the fd it is installed as in the child.
+    pub(crate) pass_fds: Vec<(RawFd, RawFd)>,
+    pub(crate) hooks: Vec<Hook>,
+    pub(crate) exec_label: Option<(Lsm, Vec<u8>)>,
+    pub(crate) non_dumpable: bool,
+    pub(crate) no_new_privs: bool,
+    pub(crate) landlock_ruleset: Option<OwnedFd>,
+    pub(crate) seccomp_filter: Option<Vec<sock_filter>>,
+    pub(crate) barrier: bool,
+    // The parent ends of piped stdio and the pty after a call.
+    pub(crate) parent_stdio: [Option<OwnedFd>; 3],
+    pub(crate) pty_master: Option<OwnedFd>,
+    // The ends of the barrier kept by the parent after a call and by the child.
+    pub(crate) parent_barrier: Option<ParentBarrier>,
+    pub(crate) child_barrier: Option<ChildBarrier>,
+    // Moved into the `Child` by `spawn`.
+    pub(crate) scoped_cgroup: Option<ScopedCgroup>,
+    // Opened by `flag_into_cgroup_path` or the error reported by the next call.
+    pub(crate) cgroup_path: Option<Result<CgroupFd, Errno>>,
+    // Set if a string passed to the builder contains a nul byte.
+    pub(crate) nul_byte: bool,
+}
+
+/// Resources prepared by the parent before the system call.
+#[derive(Debug, Default)]
+pub(crate) struct Prepared {
+    // The fds to install as stdin, stdout and stderr in the child.
+    stdio: [Option<OwnedFd>; 3],
+    parent_stdio: [Option<OwnedFd>; 3],
+    pty_slave: Option<OwnedFd>,
+    pty_master: Option<OwnedFd>,
+    controlling_terminal: Option<OwnedFd>,
+    // Duplicates of the fds to pass above all targets so that installing one cannot replace
+    // another.
+    pass_fds: Vec<(OwnedFd, RawFd)>,
+    // The process that becomes the parent of the child.
+    parent: pid_t,
+    // Written to by the parent after the id maps and the veth pair are in place.
+    parent_sync: Option<(OwnedFd, OwnedFd)>,
+    parent_barrier: Option<ParentBarrier>,
+    child_barrier: Option<ChildBarrier>,
+    // The fds used by the child are duplicated above the targets of the passed fds.
+    join_namespaces: Vec<(OwnedFd, Namespace)>,
+    landlock_ruleset: Option<OwnedFd>,
+    // Whether pid_init mounts /proc which is only done in a new mount namespace.
+    mount_proc: bool,
+}
+
+/// A closure registered through [`Clone3::child_setup`](crate::Clone3::child_setup).
+pub(crate) struct Hook(pub(crate) Box<dyn FnMut() -> Result<(), Errno> + Send>);
+
+impl Debug for Hook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook")
+    }
+}
+
+/// `sched_attr` which does not implement `Debug`.
+#[derive(Clone, Copy)]
+pub(crate) struct SchedAttr(pub(crate) sched_attr);
+
+impl Debug for SchedAttr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("sched_attr")
+            .field("sched_policy", &self.0.sched_policy)
+            .field("sched_flags", &self.0.sched_flags)
+            .field("sched_nice", &self.0.sched_nice)
+            .field("sched_priority", &self.0.sched_priority)
+            .field("sched_runtime", &self.0.sched_runtime)
+            .field("sched_deadline", &self.0.sched_deadline)
+            .field("sched_period", &self.0.sched_period)
+            .finish()
+    }
+}
+
+/// The Linux security module of an [`exec_label`](ChildSetup::exec_label).
+#[derive(Debug)]
+pub(crate) enum Lsm {
+    SELinux,
+    AppArmor,
+}
+
+/// A failed step and its error.
+pub(crate) type StepError = (Step, Errno);
+
+/// Defines [`Step`] and the name of each step from a single list.
+macro_rules! steps {
+    ($($step:ident => $name:literal,)*) => {
+        /// A step of the child setup. Sent to the parent as its discriminant.
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub(crate) enum Step {
+            $($step,)*
+        }
+
+        impl Step {
+            const ALL: &'static [Self] = &[$(Self::$step,)*];
+
+            /// The name in [`Clone3Error::ChildSetup`](crate::Clone3Error::ChildSetup).
+            pub(crate) fn name(self) -> &'static str {
+                match self {
+                    $(Self::$step => $name,)*
+                }
+            }
+        }
+    };
+}
+
+steps! {
+    Stdio => "stdio",
+    Pty => "pty",
+    ChildSetup => "child_setup",
+    CurrentDir => "current_dir",
+    Umask => "umask",
+    Setsid => "setsid",
+    ProcessGroup => "process_group",
+    ControllingTerminal => "controlling_terminal",
+    Groups => "groups",
+    Gid => "gid",
+    Uid => "uid",
+    Capabilities => "capabilities",
+    SeccompFilter => "seccomp_filter",
+    LandlockRuleset => "landlock_ruleset",
+    NoNewPrivs => "no_new_privs",
+    ParentDeathSignal => "parent_death_signal",
+    Name => "name",
+    Rlimit => "rlimit",
+    OomScoreAdj => "oom_score_adj",
+    Nice => "nice",
+    CpuAffinity => "cpu_affinity",
+    SchedAttr => "sched_attr",
+    Mempolicy => "mempolicy",
+    Personality => "personality",
+    CloseFds => "close_fds",
+    PassFds => "pass_fds",
+    ExecLabel => "exec_label",
+    NonDumpable => "non_dumpable",
+    IoPriority => "io_priority",
+    UsernsMap => "userns_map",
+    Hostname => "hostname",
+    Domainname => "domainname",
+    Mounts => "mounts",
+    PivotRoot => "pivot_root",
+    Chroot => "chroot",
+    PidInit => "pid_init",
+    LoopbackUp => "loopback_up",
+    Veth => "veth",
+    JoinNamespace => "join_namespace",
+    Barrier => "barrier",
+    Execve => "execve",
+}
+
+impl Step {
+    fn from_index(index: c_int) -> Option<Self> {
+        let step = *Self::ALL.get(usize::try_from(index).ok()?)?;
+        (step as c_int == index).then_some(step)
+    }
+}
+
+impl ChildSetup {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.first_step().is_none()
+    }
+
+    /// The name of the first step that is configured.
+    pub(crate) fn first_step(&self) -> Option<Step> {
+        if self.userns_map.is_some() {
+            return Some(Step::UsernsMap);
+        }
+        if self.veth.is_some() {
+            return Some(Step::Veth);
+        }
+        if !self.join_namespaces.is_empty() {
+            return Some(Step::JoinNamespace);
+        }
+        if self.setsid {
+            return Some(Step::Setsid);
+        }
+        if self.process_group.is_some() {
+            return Some(Step::ProcessGroup);
+        }
+        if self.pty {
+            return Some(Step::Pty);
+        }
+        if self.controlling_terminal.is_some() {
+            return Some(Step::ControllingTerminal);
+        }
+        if self
+            .stdio
+            .iter()
+            .any(|stdio| !matches!(stdio, Stdio::Inherit))
+        {
+            return Some(Step::Stdio);
+        }
+        if !self.pass_fds.is_empty() {
+            return Some(Step::PassFds);
+        }
+        if self.name.is_some() {
+            return Some(Step::Name);
+        }
+        if !self.rlimits.is_empty() {
+            return Some(Step::Rlimit);
+        }
+        if self.oom_score_adj.is_some() {
+            return Some(Step::OomScoreAdj);
+        }
+        if self.nice.is_some() {
+            return Some(Step::Nice);
+        }
+        if self.cpu_affinity.is_some() {
+            return Some(Step::CpuAffinity);
+        }
+        if self.sched_attr.is_some() {
+            return Some(Step::SchedAttr);
+        }
+        if self.mempolicy.is_some() {
+            return Some(Step::Mempolicy);
+        }
+        if self.personality.is_some() {
+            return Some(Step::Personality);
+        }
+        if self.io_priority.is_some() {
+            return Some(Step::IoPriority);
+        }
+        if self.hostname.is_some() {
+            return Some(Step::Hostname);
+        }
+        if self.domainname.is_some() {
+            return Some(Step::Domainname);
+        }
+        if self.loopback_up {
+            return Some(Step::LoopbackUp);
+        }
+        if !self.mounts.is_empty() {
+            return Some(Step::Mounts);
+        }
+        if let Some(root) = &self.root {
+            return Some(root.step());
+        }
+        if self.pid_init {
+            return Some(Step::PidInit);
+        }
+        if self.groups.is_some() {
+            return Some(Step::Groups);
+        }
+        if self.gid.is_some() {
+            return Some(Step::Gid);
+        }
+        if self.uid.is_some() {
+            return Some(Step::Uid);
+        }
+        if self.capabilities.is_some() {
+            return Some(Step::Capabilities);
+        }
+        if self.parent_death_signal.is_some() {
+            return Some(Step::ParentDeathSignal);
+        }
+        if self.current_dir.is_some() {
+            return Some(Step::CurrentDir);
+        }
+        if self.umask.is_some() {
+            return Some(Step::Umask);
+        }
+        if self.close_fds.is_some() {
+            return Some(Step::CloseFds);
+        }
+        if !self.hooks.is_empty() {
+            return Some(Step::ChildSetup);
+        }
+        if self.exec_label.is_some() {
+            return Some(Step::ExecLabel);
+        }
+        if self.non_dumpable {
+            return Some(Step::NonDumpable);
+        }
+        if self.no_new_privs {
+            return Some(Step::NoNewPrivs);
+        }
+        if self.landlock_ruleset.is_some() {
+            return Some(Step::LandlockRuleset);
+        }
+        if self.seccomp_filter.is_some() {
+            return Some(Step::SeccompFilter);
+        }
+        if self.barrier {
+            return Some(Step::Barrier);
+        }
+        None
+    }
+
+    /// Returns a step that would also affect the parent because of a flag that shares the state it
+    /// changes.
+    pub(crate) fn find_conflict(&self, flags: Flags) -> Option<(Step, Flags)> {
+        let stdio = self
+            .stdio
+            .iter()
+            .any(|stdio| !matches!(stdio, Stdio::Inherit));
+        if self.userns_map.is_some() && flags.contains(Flags::FILES) {
+            return Some((Step::UsernsMap, Flags::FILES));
+        }
+        if self.barrier && flags.contains(Flags::FILES) {
+            return Some((Step::Barrier, Flags::FILES));
+        }
+        let shares_fs = self
+            .join_namespaces
+            .iter()
+            .any(|(_, namespace)| matches!(namespace, Namespace::Mount | Namespace::User));
+        if shares_fs && flags.contains(Flags::FS) {
+            return Some((Step::JoinNamespace, Flags::FS));
+        }
+        if self.pty && flags.contains(Flags::FILES) {
+            return Some((Step::Pty, Flags::FILES));
+        }
+        if self.controlling_terminal.is_some() && flags.contains(Flags::FILES) {
+            return Some((Step::ControllingTerminal, Flags::FILES));
+        }
+        if stdio && flags.contains(Flags::FILES) {
+            return Some((Step::Stdio, Flags::FILES));
+        }
+        if !self.pass_fds.is_empty() && flags.contains(Flags::FILES) {
+            return Some((Step::PassFds, Flags::FILES));
+        }
+        if self.close_fds.is_some() && flags.contains(Flags::FILES) {
+            return Some((Step::CloseFds, Flags::FILES));
+        }
+        if matches!(self.root, Some(Root::Chroot(_))) && flags.contains(Flags::FS) {
+            return Some((Step::Chroot, Flags::FS));
+        }
+        if self.current_dir.is_some() && flags.contains(Flags::FS) {
+            return Some((Step::CurrentDir, Flags::FS));
+        }
+        if self.umask.is_some() && flags.contains(Flags::FS) {
+            return Some((Step::Umask, Flags::FS));
+        }
+        None
+    }
+
+    /// Returns a step that would change the parent because a flag that gives the child its own
+    /// copy of the state it changes is not set.
+    pub(crate) fn find_missing_flag(&self, flags: Flags) -> Option<(Step, Flags)> {
+        if self.hostname.is_some() && !flags.contains(Flags::NEWUTS) {
+            return Some((Step::Hostname, Flags::NEWUTS));
+        }
+        if self.domainname.is_some() && !flags.contains(Flags::NEWUTS) {
+            return Some((Step::Domainname, Flags::NEWUTS));
+        }
+        if self.loopback_up && !flags.contains(Flags::NEWNET) {
+            return Some((Step::LoopbackUp, Flags::NEWNET));
+        }
+        if self.veth.is_some() && !flags.contains(Flags::NEWNET) {
+            return Some((Step::Veth, Flags::NEWNET));
+        }
+        if !self.mounts.is_empty() && !flags.contains(Flags::NEWNS) {
+            return Some((Step::Mounts, Flags::NEWNS));
+        }
+        if matches!(self.root, Some(Root::PivotRoot(_))) && !flags.contains(Flags::NEWNS) {
+            return Some((Step::PivotRoot, Flags::NEWNS));
+        }
+        if self.pid_init && !flags.contains(Flags::NEWPID) {
+            return Some((Step::PidInit, Flags::NEWPID));
+        }
+        None
+    }
+
+    /// The lowest fd that is not the target of [`pass_fds`](crate::Clone3::pass_fds).
+    ///
+    /// Fds the child uses after installing the passed fds must be at least this.
+    pub(crate) fn min_fd(&self) -> RawFd {
+        self.pass_fds
+            .iter()
+            .map(|&(_, target)| target + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Creates the resources the child needs so that it does not have to allocate.
+    pub(crate) fn prepare(&mut self, flags: Flags) -> Result<Prepared, Errno> {
+        let mut prepared = Prepared {
+            parent: if flags.contains(Flags::PARENT) {
+                unsafe { c::getppid() }
+            } else {
+                unsafe { c::getpid() }
+            },
+            mount_proc: self.pid_init && flags.contains(Flags::NEWNS),
+            ..Prepared::default()
+        };
+        if self.userns_map.is_some() || self.veth.is_some() {
+            prepared.parent_sync = Some(cloexec_pipe()?);
+        }
+        if self.barrier {
+            // Above the passed fds because the child keeps its ends.
+            let (child_wait, parent_release) = cloexec_pipe_above(self.min_fd())?;
+            let (parent_wait, child_release) = cloexec_pipe_above(self.min_fd())?;
+            prepared.parent_barrier = Some(ParentBarrier {
+                wait: parent_wait,
+                release: parent_release,
+            });
+            prepared.child_barrier = Some(ChildBarrier {
+                wait: child_wait,
+                release: child_release,
+            });
+        }
+        if self.pty {
+            let (master, slave) = open_pty()?;
+            prepared.pty_master = Some(master);
+            prepared.pty_slave = Some(slave);
+        }
+        for (fd, namespace) in &self.join_namespaces {
+            let fd = try_clone_above(fd, self.min_fd())?;
+            prepared.join_namespaces.push((fd, *namespace));
+        }
+        if let Some(tty) = &self.controlling_terminal {
+            prepared.controlling_terminal = Some(try_clone_above(tty, self.min_fd())?);
+        }
+        if let Some(ruleset) = &self.landlock_ruleset {
+            prepared.landlock_ruleset = Some(try_clone_above(ruleset, self.min_fd())?);
+        }
+        for &(fd, target) in &self.pass_fds {
+            let duplicate = check(unsafe { c::fcntl(fd, F_DUPFD_CLOEXEC, self.min_fd()) })?;
+            prepared
+                .pass_fds
+                .push((unsafe { OwnedFd::from_raw_fd(duplicate) }, target));
+        }
+        for (i, stdio) in self.stdio.iter().enumerate() {
+            match stdio {
+                Stdio::Inherit => {
+                    if let Some(slave) = &prepared.pty_slave {
+                        prepared.stdio[i] = Some(try_clone(slave)?);
+                    }
+                }
+                Stdio::Piped => {
+                    let (read, write) = cloexec_pipe()?;
+                    let (child, parent) = if i == 0 { (read, write) } else { (write, read) };
+                    prepared.stdio[i] = Some(child);
+                    prepared.parent_stdio[i] = Some(parent);
+                }
+                Stdio::Null => {
+                    let fd = check(unsafe { c::open(c"/dev/null".as_ptr(), O_RDWR | O_CLOEXEC) })?;
+                    prepared.stdio[i] = Some(unsafe { OwnedFd::from_raw_fd(fd) });
+                }
+                Stdio::Fd(fd) => prepared.stdio[i] = Some(try_clone(fd)?),
+            }
+        }
+        Ok(prepared)
+    }
+
+    /// Performs the steps that are done by the parent before waiting for the child.
+    ///
+    /// Setting the process group in both processes ensures it is set no matter which one runs
+    /// first. Errors are ignored because the child reports them. Errors writing the id maps or
+    /// creating the veth pair are returned after releasing the child which then exits.
+    pub(crate) fn apply_in_parent(
+        &self,
+        pid: pid_t,
+        prepared: &mut Prepared,
+    ) -> Result<(), StepError> {
+        if let Some(pgid) = self.process_group {
+            unsafe { c::setpgid(pid, pgid) };
+        }
+        if let Some((read, write)) = prepared.parent_sync.take() {
+            drop(read);
+            if let Some((uid_map, gid_map)) = &self.userns_map {
+                write_id_maps(pid, uid_map, gid_map, self.groups.is_none())
+                    .map_err(|errno| (Step::UsernsMap, errno))?;
+            }
+            if let Some((host, child)) = &self.veth {
+                net::create_veth(host, child, pid).map_err(|errno| (Step::Veth, errno))?;
+                unsafe { net::set_up(host) }.map_err(|errno| (Step::Veth, errno))?;
+            }
+            unsafe { c::write(write.as_raw_fd(), [0u8].as_ptr() as *const _, 1) };
+        }
+        Ok(())
+    }
+
+    /// Keeps the parent ends after a successful call.
+    pub(crate) fn finish(&mut self, prepared: Prepared) {
+        self.parent_stdio = prepared.parent_stdio;
+        self.pty_master = prepared.pty_master;
+        self.parent_barrier = prepared.parent_barrier;
+    }
+
+    /// Applies the steps in the child. Must not allocate.
+    pub(crate) unsafe fn apply(&mut self, prepared: Prepared) -> Result<(), StepError> {
+        // Dropping the vectors would free them. On an early return the child exits and the kernel
+        // closes the fds.
+        let mut prepared = ManuallyDrop::new(prepared);
+        // Before everything else so that the steps run with the mapped ids.
+        if let Some((read, write)) = prepared.parent_sync.take() {
+            drop(write);
+            // The parent closes the pipe without writing if it failed and reports its own error.
+            let step = if self.userns_map.is_some() {
+                Step::UsernsMap
+            } else {
+                Step::Veth
+            };
+            read_message::<1>(&read).ok_or((step, Errno(ECHILD)))?;
+        }
+        namespace::join(&prepared.join_namespaces).map_err(|errno| (Step::JoinNamespace, errno))?;
+        // Only a session leader without a controlling terminal can acquire one.
+        let terminal = match (&prepared.controlling_terminal, &prepared.pty_slave) {
+            (Some(tty), _) => Some((tty, Step::ControllingTerminal)),
+            (None, Some(slave)) => Some((slave, Step::Pty)),
+            (None, None) => None,
+        };
+        if self.setsid || terminal.is_some() {
+            check(c::setsid()).map_err(|errno| (Step::Setsid, errno))?;
+        }
+        if let Some(pgid) = self.process_group {
+            check(c::setpgid(0, pgid)).map_err(|errno| (Step::ProcessGroup, errno))?;
+        }
+        if let Some((tty, step)) = terminal {
+            check(c::ioctl(tty.as_raw_fd(), TIOCSCTTY, 0)).map_err(|errno| (step, errno))?;
+        }
+        apply_stdio(&prepared.stdio).map_err(|errno| (Step::Stdio, errno))?;
+        let original_parent = prepared.parent;
+        let mount_proc = prepared.mount_proc;
+        self.child_barrier = prepared.child_barrier.take();
+        let pass_fds = ManuallyDrop::new(mem::take(&mut prepared.pass_fds));
+        let landlock_ruleset = prepared.landlock_ruleset.take();
+        let join_namespaces = ManuallyDrop::new(mem::take(&mut prepared.join_namespaces));
+        for (fd, _) in join_namespaces.iter() {
+            c::close(fd.as_raw_fd());
+        }
+        // Dropping closes the parent ends and the original child ends. Before installing the
+        // passed fds because they might replace one of them. The vectors are empty so this does
+        // not free.
+        drop(ManuallyDrop::into_inner(prepared));
+        // dup2 clears close-on-exec on the target.
+        for (fd, target) in pass_fds.iter() {
+            check(c::dup2(fd.as_raw_fd(), *target)).map_err(|errno| (Step::PassFds, errno))?;
+        }
+        for (fd, _) in pass_fds.iter() {
+            c::close(fd.as_raw_fd());
+        }
+        if let Some(name) = &self.name {
+            check(c::prctl(PR_SET_NAME, name.as_ptr())).map_err(|errno| (Step::Name, errno))?;
+        }
+        // Before the credentials because raising a hard limit requires CAP_SYS_RESOURCE.
+        for (resource, limit) in &self.rlimits {
+            check(c::setrlimit(*resource as _, limit)).map_err(|errno| (Step::Rlimit, errno))?;
+        }
+        // Before the credentials because lowering the value requires CAP_SYS_RESOURCE.
+        if let Some(value) = &self.oom_score_adj {
+            write_file(c"/proc/self/oom_score_adj", value)
+                .map_err(|errno| (Step::OomScoreAdj, errno))?;
+        }
+        // Before the credentials because lowering the value requires CAP_SYS_NICE.
+        if let Some(level) = self.nice {
+            check(c::setpriority(PRIO_PROCESS as _, 0, level))
+                .map_err(|errno| (Step::Nice, errno))?;
+        }
+        if let Some(cpus) = &self.cpu_affinity {
+            check(c::sched_setaffinity(
+                0,
+                mem::size_of::<cpu_set_t>(),
+                &cpus.0,
+            ))
+            .map_err(|errno| (Step::CpuAffinity, errno))?;
+        }
+        // Before the credentials because real-time policies require CAP_SYS_NICE.
+        if let Some(SchedAttr(attr)) = &self.sched_attr {
+            check(c::syscall(SYS_sched_setattr, 0, attr, 0) as c_int)
+                .map_err(|errno| (Step::SchedAttr, errno))?;
+        }
+        if let Some((mode, nodemask)) = &self.mempolicy {
+            // The kernel ignores the last bit of `maxnode`.
+            let maxnode = nodemask.len() * c_ulong::BITS as usize + 1;
+            check(c::syscall(SYS_set_mempolicy, *mode, nodemask.as_ptr(), maxnode) as c_int)
+                .map_err(|errno| (Step::Mempolicy, errno))?;
+        }
+        if let Some(persona) = self.personality {
+            check(c::personality(persona)).map_err(|errno| (Step::Personality, errno))?;
+        }
+        // Before the credentials because the real-time class requires CAP_SYS_ADMIN.
+        if let Some(priority) = self.io_priority {
+            check(c::syscall(SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) as c_int)
+                .map_err(|errno| (Step::IoPriority, errno))?;
+        }
+        // Before the credentials because changing them requires CAP_SYS_ADMIN.
+        if let Some(hostname) = &self.hostname {
+            check(c::sethostname(hostname.as_ptr(), hostname.as_bytes().len()))
+                .map_err(|errno| (Step::Hostname, errno))?;
+        }
+        if let Some(domainname) = &self.domainname {
+            check(c::setdomainname(
+                domainname.as_ptr(),
+                domainname.as_bytes().len(),
+            ))
+            .map_err(|errno| (Step::Domainname, errno))?;
+        }
+        // Before the credentials because configuring interfaces requires CAP_NET_ADMIN.
+        if self.loopback_up {
+            net::set_up(c"lo").map_err(|errno| (Step::LoopbackUp, errno))?;
+        }
+        if let Some((_, child)) = &self.veth {
+            net::set_up(child).map_err(|errno| (Step::Veth, errno))?;
+        }
+        // Before the credentials because mounting requires CAP_SYS_ADMIN.
+        for mount in &self.mounts {
+            mount.apply().map_err(|errno| (Step::Mounts, errno))?;
+        }
+        if let Some(root) = &self.root {
+            root.apply().map_err(|errno| (root.step(), errno))?;
+        }
+        if mount_proc {
+            init::mount_proc().map_err(|errno| (Step::PidInit, errno))?;
+        }
+        // Changing the bounding set requires CAP_SETPCAP which is lost when changing the uid.
+        if let Some(keep) = self.capabilities {
+            drop_bounding_capabilities(keep).map_err(|errno| (Step::Capabilities, errno))?;
+        }
+        // The libc functions change the credentials of all threads which would signal the threads
+        // of the parent that do not exist in the child. The system calls only affect the calling
+        // thread which is the whole child. Groups and gid first because they need the privileges
+        // dropped with uid.
+        if let Some(groups) = &self.groups {
+            check(c::syscall(SYS_setgroups, groups.len(), groups.as_ptr()) as c_int)
+                .map_err(|errno| (Step::Groups, errno))?;
+        }
+        if let Some(gid) = self.gid {
+            check(c::syscall(SYS_setresgid, gid, gid, gid) as c_int)
+                .map_err(|errno| (Step::Gid, errno))?;
+        }
+        if let Some(uid) = self.uid {
+            check(c::syscall(SYS_setresuid, uid, uid, uid) as c_int)
+                .map_err(|errno| (Step::Uid, errno))?;
+        }
+        // After the uid so that the capabilities needed to change it are still available.
+        if let Some(keep) = self.capabilities {
+            limit_capabilities(keep).map_err(|errno| (Step::Capabilities, errno))?;
+        }
+        // After the credentials because changing them clears the signal.
+        if let Some(signal) = self.parent_death_signal {
+            check(c::prctl(PR_SET_PDEATHSIG, signal as c_ulong))
+                .map_err(|errno| (Step::ParentDeathSignal, errno))?;
+            // The parent might have exited before the signal was set. The pid of the parent is 0
+            // in a new pid namespace so that the check is skipped there.
+            let parent = c::getppid();
+            if parent != original_parent && parent != 0 {
+                c::kill(c::getpid(), signal);
+                return Err((Step::ParentDeathSignal, Errno(ESRCH)));
+            }
+        }
+        if let Some(dir) = &self.current_dir {
+            check(c::chdir(dir.as_ptr())).map_err(|errno| (Step::CurrentDir, errno))?;
+        }
+        if let Some(mask) = self.umask {
+            c::umask(mask);
+        }
+        if let Some(first) = self.close_fds {
+            set_cloexec_from(first).map_err(|errno| (Step::CloseFds, errno))?;
+        }
+        if !self.pass_fds.is_empty() {
+            set_cloexec_from(3).map_err(|errno| (Step::PassFds, errno))?;
+            for &(_, target) in &self.pass_fds {
+                check(c::fcntl(target, F_SETFD, 0)).map_err(|errno| (Step::PassFds, errno))?;
+            }
+        }
+        for hook in &mut self.hooks {
+            (hook.0)().map_err(|errno| (Step::ChildSetup, errno))?;
+        }
+        // Before Landlock and seccomp which could prevent writing to procfs.
+        if let Some((lsm, label)) = &self.exec_label {
+            let result = match lsm {
+                Lsm::SELinux => write_file(c"/proc/self/attr/exec", label),
+                // The AppArmor specific file was added in Linux 5.8.
+                Lsm::AppArmor => match write_file(c"/proc/self/attr/apparmor/exec", label) {
+                    Err(Errno(ENOENT)) => write_file(c"/proc/self/attr/exec", label),
+                    result => result,
+                },
+            };
+            result.map_err(|errno| (Step::ExecLabel, errno))?;
+        }
+        // After the steps that write to /proc/self which becomes owned by root.
+        if self.non_dumpable {
+            let no_core = rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            check(c::setrlimit(RLIMIT_CORE, &no_core))
+                .map_err(|errno| (Step::NonDumpable, errno))?;
+            check(c::prctl(PR_SET_DUMPABLE, 0, 0, 0, 0))
+                .map_err(|errno| (Step::NonDumpable, errno))?;
+        }
+        if self.no_new_privs {
+            check(c::prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))
+                .map_err(|errno| (Step::NoNewPrivs, errno))?;
+        }
+        if let Some(ruleset) = &landlock_ruleset {
+            check(c::syscall(SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) as c_int)
+                .map_err(|errno| (Step::LandlockRuleset, errno))?;
+        }
+        // Last so that the filter does not have to allow the system calls of the other steps.
+        if let Some(filter) = &self.seccomp_filter {
+            let program = sock_fprog {
+                len: filter.len() as c_ushort,
+                filter: filter.as_ptr() as *mut sock_filter,
+            };
+            check(c::syscall(SYS_seccomp, SECCOMP_SET_MODE_FILTER, 0, &program) as c_int)
+                .map_err(|errno| (Step::SeccompFilter, errno))?;
+        }
+        Ok(())
+    }
+}
+
+const CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+/// `struct __user_cap_header_struct`
+#[repr(C)]
+struct CapabilityHeader {
+    version: u32,
+    pid: c_int,
+}
+
+/// `struct __user_cap_data_struct`. Version 3 uses two of them for the lower and upper 32
+/// capabilities.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapabilityData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// The effective capabilities of the calling thread in which bit n stands for capability n.
+pub(crate) fn effective_capabilities() -> Result<u64, Errno> {
+    let mut header = CapabilityHeader {
+        version: CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapabilityData::default(); 2];
+    check(unsafe { c::syscall(SYS_capget, &mut header, data.as_mut_ptr()) } as c_int)?;
+    Ok(data[0].effective as u64 | (data[1].effective as u64) << 32)
+}
+
+/// Removes the capabilities not in `keep` from the bounding and ambient sets.
+unsafe fn drop_bounding_capabilities(keep: u64) -> Result<(), Errno> {
+    for capability in 0..64 {
+        if keep & (1 << capability) != 0 {
+            continue;
+        }
+        // Fails with EINVAL after the last capability supported by the kernel.
+        match c::prctl(PR_CAPBSET_READ, capability) {
+            -1 if Errno::default().0 == EINVAL => break,
+            -1 => return Err(Errno::default()),
+            // Dropping requires CAP_SETPCAP even if the capability is not in the set.
+            0 => (),
+            _ => {
+                check(c::prctl(PR_CAPBSET_DROP, capability))?;
+            }
+        }
+        // Fails with EINVAL before Linux 4.3 which has no ambient set.
+        match c::prctl(PR_CAP_AMBIENT, PR_CAP_AMBIENT_LOWER, capability, 0, 0) {
+            -1 if Errno::default().0 == EINVAL => (),
+            -1 => return Err(Errno::default()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Removes the capabilities not in `keep` from the effective, permitted and inheritable sets.
+unsafe fn limit_capabilities(keep: u64) -> Result<(), Errno> {
+    let mut header = CapabilityHeader {
+        version: CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapabilityData::default(); 2];
+    check(c::syscall(SYS_capget, &mut header, data.as_mut_ptr()) as c_int)?;
+    for (data, keep) in data.iter_mut().zip([keep as u32, (keep >> 32) as u32]) {
+        data.effective &= keep;
+        data.permitted &= keep;
+        data.inheritable &= keep;
+    }
+    check(c::syscall(SYS_capset, &mut header, data.as_ptr()) as c_int)?;
+    Ok(())
+}
+
+/// Writes `contents` to the existing file `path`.
+unsafe fn write_file(path: &CStr, contents: &[u8]) -> Result<(), Errno> {
+    let fd = OwnedFd::from_raw_fd(check(c::open(path.as_ptr(), O_WRONLY | O_CLOEXEC))?);
+    let written = c::write(
+        fd.as_raw_fd(),
+        contents.as_ptr() as *const _,
+        contents.len(),
+    );
+    if written == -1 {
+        return Err(Errno::default());
+    }
+    Ok(())
+}
+
+/// Writes the id maps of the user namespace of `pid`. Denies setgroups before writing the gid map
+/// if `deny_setgroups` which is required without `CAP_SETGID` in the parent namespace.
+fn write_id_maps(
+    pid: pid_t,
+    uid_map: &[u8],
+    gid_map: &[u8],
+    deny_setgroups: bool,
+) -> Result<(), Errno> {
+    let path = |file: &str| CString::new(format!("/proc/{}/{}", pid, file)).unwrap();
+    unsafe {
+        if !uid_map.is_empty() {
+            write_file(&path("uid_map"), uid_map)?;
+        }
+        if !gid_map.is_empty() {
+            if deny_setgroups {
+                write_file(&path("setgroups"), b"deny")?;
+            }
+            write_file(&path("gid_map"), gid_map)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets close-on-exec on all fds from `first` upwards.
+unsafe fn set_cloexec_from(first: RawFd) -> Result<(), Errno> {
+    let flags = CLOSE_RANGE_CLOEXEC as c_uint;
+    if c::syscall(SYS_close_range, first as c_uint, c_uint::MAX, flags) == 0 {
+        return Ok(());
+    }
+    match Errno::default().0 {
+        // close_range was added in Linux 5.9 and CLOSE_RANGE_CLOEXEC in 5.11.
+        ENOSYS | EINVAL => set_cloexec_from_proc(first),
+        errno => Err(Errno(errno)),
+    }
+}
+
+/// Like [`set_cloexec_from`] but through the entries of `/proc/self/fd`.
+unsafe fn set_cloexec_from_proc(first: RawFd) -> Result<(), Errno> {
+    let dir = check(c::open(
+        c"/proc/self/fd".as_ptr(),
+        O_RDONLY | O_DIRECTORY | O_CLOEXEC,
+    ))?;
+    let dir = OwnedFd::from_raw_fd(dir);
+    // u64 for the alignment of `struct linux_dirent64`.
+    let mut buffer = [0u64; 512];
+    loop {
+        let n = c::syscall(
+            SYS_getdents64,
+            dir.as_raw_fd(),
+            buffer.as_mut_ptr(),
+            mem::size_of_val(&buffer),
+        );
+        if n == -1 {
+            return Err(Errno::default());
+        }
+        if n == 0 {
+            return Ok(());
+        }
+        let bytes = slice::from_raw_parts(buffer.as_ptr() as *const u8, n as usize);
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let entry = &bytes[offset..];
+            let length = u16::from_ne_bytes([entry[16], entry[17]]) as usize;
+            let name = CStr::from_ptr(entry[19..].as_ptr() as *const c_char);
+            offset += length;
+            // Skips "." and "..".
+            let fd = match parse_fd(name.to_bytes()) {
+                Some(fd) if fd >= first && fd != dir.as_raw_fd() => fd,
+                _ => continue,
+            };
+            // The fd might have been closed since reading the directory.
+            if c::fcntl(fd, F_SETFD, FD_CLOEXEC) == -1 && Errno::default().0 != EBADF {
+                return Err(Errno::default());
+            }
+        }
+    }
+}
+
+fn parse_fd(name: &[u8]) -> Option<RawFd> {
+    if name.is_empty() {
+        return None;
+    }
+    name.iter().try_fold(0 as RawFd, |fd, &digit| {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        fd.checked_mul(10)?.checked_add((digit - b'0') as RawFd)
+    })
+}
+
+/// Installs `stdio` as fds 0, 1 and 2.
+///
+/// The fds are first duplicated above 2 so that installing one cannot close another that is still
+/// needed, for example when stdout should become stdin.
+unsafe fn apply_stdio(stdio: &[Option<OwnedFd>; 3]) -> Result<(), Errno> {
+    let mut duplicates: [RawFd; 3] = [-1; 3];
+    let result = (|| {
+        for (duplicate, fd) in duplicates.iter_mut().zip(stdio) {
+            if let Some(fd) = fd {
+                *duplicate = check(c::fcntl(fd.as_raw_fd(), F_DUPFD_CLOEXEC, 3))?;
+            }
+        }
+        for (target, duplicate) in duplicates.iter().enumerate() {
+            if *duplicate != -1 {
+                // Unlike the duplicate the new fd is not close-on-exec.
+                check(c::dup2(*duplicate, target as c_int))?;
+            }
+        }
+        Ok(())
+    })();
+    for duplicate in duplicates.iter().filter(|fd| **fd != -1) {
+        c::close(*duplicate);
+    }
+    result
+}
+
+/// The error pipe through which the child reports a failed step to the parent.
+#[derive(Debug)]
+pub(crate) struct ErrorPipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl ErrorPipe {
+    /// Creates the pipe with fds of at least `min_fd`.
+    pub(crate) fn new(min_fd: RawFd) -> Result<Self, Errno> {
+        let (read, write) = cloexec_pipe_above(min_fd)?;
+        Ok(Self { read, write })
+    }
+
+    /// Sends the error to the parent. Called in the child.
+    pub(crate) unsafe fn report(&self, (step, errno): StepError) {
+        let message = [step as c_int, errno.0];
+        c::write(
+            self.write.as_raw_fd(),
+            message.as_ptr() as *const _,
+            mem::size_of_val(&message),
+        );
+    }
+
+    /// Closes the write end in the child after the steps succeeded.
+    pub(crate) unsafe fn close_in_child(self) {
+        drop(self);
+    }
+
+    /// Waits until the child closes the pipe and returns the reported error if any.
+    pub(crate) fn receive(self) -> Option<(Option<Step>, Errno)> {
+        drop(self.write);
+        let message = read_message::<8>(&self.read)?;
+        let index = c_int::from_ne_bytes(message[..4].try_into().unwrap());
+        let errno = c_int::from_ne_bytes(message[4..].try_into().unwrap());
+        Some((Step::from_index(index), Errno(errno)))
+    }
+}
+
+/// Either the setup owned by a [`Clone3`](crate::Clone3) or the one borrowed from an
+/// [`OwnedClone3`](crate::OwnedClone3).
+// Boxing the owned setup would allocate for every `Clone3` even if no setup is configured.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub(crate) enum SetupRef<'a> {
+    Owned(ChildSetup),
+    Borrowed(&'a mut ChildSetup),
+}
+
+impl Default for SetupRef<'_> {
+    fn default() -> Self {
+        Self::Owned(ChildSetup::default())
+    }
+}
+
+impl Deref for SetupRef<'_> {
+    type Target = ChildSetup;
+
+    fn deref(&self) -> &ChildSetup {
+        match self {
+            Self::Owned(setup) => setup,
+            Self::Borrowed(setup) => setup,
+        }
+    }
+}
+
+impl DerefMut for SetupRef<'_> {
+    fn deref_mut(&mut self) -> &mut ChildSetup {
+        match self {
+            Self::Owned(setup) => setup,
+            Self::Borrowed(setup) => setup,
+        }
+    }
+}
+
+/// Converts a string for use in the child. Records a nul byte in `nul_byte`.
+pub(crate) fn to_cstring(string: &OsStr, nul_byte: &mut bool) -> CString {
+    CString::new(string.as_bytes()).unwrap_or_else(|_| {
+        *nul_byte = true;
+        CString::default()
+    })
+}
+
+/// Opens a new pseudo terminal and returns its master and slave.
+fn open_pty() -> Result<(OwnedFd, OwnedFd), Errno> {
+    let master = check(unsafe { c::posix_openpt(O_RDWR | O_NOCTTY | O_CLOEXEC) })?;
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    check(unsafe { c::grantpt(master.as_raw_fd()) })?;
+    check(unsafe { c::unlockpt(master.as_raw_fd()) })?;
+    // Opening the slave through the master avoids looking up its path. Linux 4.13.
+    let slave = check(unsafe {
+        c::ioctl(
+            master.as_raw_fd(),
+            TIOCGPTPEER,
+            O_RDWR | O_NOCTTY | O_CLOEXEC,
+        )
+    })?;
+    Ok((master, unsafe { OwnedFd::from_raw_fd(slave) }))
+}
+
+pub(crate) fn try_clone(fd: &OwnedFd) -> Result<OwnedFd, Errno> {
+    fd.try_clone()
+        .map_err(|err| Errno(err.raw_os_error().unwrap_or(EBADF)))
+}
+
+/// Like [`try_clone`] but with an fd of at least `min_fd`.
+fn try_clone_above(fd: &OwnedFd, min_fd: RawFd) -> Result<OwnedFd, Errno> {
+    let duplicate = check(unsafe { c::fcntl(fd.as_raw_fd(), F_DUPFD_CLOEXEC, min_fd) })?;
+    Ok(unsafe { OwnedFd::from_raw_fd(duplicate) })
+}
+
+pub(crate) fn cloexec_pipe() -> Result<(OwnedFd, OwnedFd), Errno> {
+    let mut fds = [0; 2];
+    if unsafe { c::pipe2(fds.as_mut_ptr(), O_CLOEXEC) } == -1 {
+        return Err(Errno::default());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Like [`cloexec_pipe`] but with fds of at least `min_fd`.
+pub(crate) fn cloexec_pipe_above(min_fd: RawFd) -> Result<(OwnedFd, OwnedFd), Errno> {
+    let above = |fd: OwnedFd| -> Result<OwnedFd, Errno> {
+        if fd.as_raw_fd() >= min_fd {
+            return Ok(fd);
+        }
+        let duplicate = check(unsafe { c::fcntl(fd.as_raw_fd(), F_DUPFD_CLOEXEC, min_fd) })?;
+        Ok(unsafe { OwnedFd::from_raw_fd(duplicate) })
+    };
+    let (read, write) = cloexec_pipe()?;
+    Ok((above(read)?, above(write)?))
+}
+
+/// Reads a message written by a failing child. Returns `None` when the pipe is closed without
+/// data because the child succeeded.
+pub(crate) fn read_message<const N: usize>(pipe: &OwnedFd) -> Option<[u8; N]> {
+    let mut bytes = [0u8; N];
+    let mut read = 0;
+    while read < N {
+        let n = unsafe {
+            c::read(
+                pipe.as_raw_fd(),
+                bytes[read..].as_mut_ptr() as *mut _,
+                N - read,
+            )
+        };
+        match n {
+            -1 if Errno::default().0 == EINTR => continue,
+            -1 | 0 => break,
+            n => read += n as usize,
+        }
+    }
+    (read == N).then_some(bytes)
+}
+
+pub(crate) fn check(return_value: c_int) -> Result<c_int, Errno> {
+    if return_value == -1 {
+        return Err(Errno::default());
+    }
+    Ok(return_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_child;
+    use crate::{ChildStatus, Clone3, Clone3Error};
+    use std::io::{Read, Seek, Write};
+
+    /// Asserts that the child setup of `clone3` fails in `step` with `errno`.
+    fn assert_setup_error(clone3: &mut Clone3<'_>, step: Step, errno: c_int) {
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::ChildSetup {
+                step: name,
+                errno: Errno(actual),
+            }) if name == step.name() && actual == errno => (),
+            Ok(None) => unsafe { c::_exit(0) },
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn step_indices() {
+        for (index, &step) in Step::ALL.iter().enumerate() {
+            assert_eq!(step as usize, index);
+            assert_eq!(Step::from_index(step as c_int), Some(step));
+        }
+        assert_eq!(Step::from_index(-1), None);
+        assert_eq!(Step::from_index(Step::ALL.len() as c_int), None);
+    }
+
+    #[test]
+    fn redirect_to_file_and_null() {
+        let mut file =
+            File::from(unsafe { OwnedFd::from_raw_fd(c::memfd_create(c"stdout".as_ptr(), 0)) });
+        let mut clone3 = Clone3::default();
+        clone3
+            .stdin(Stdio::Null)
+            .stdout(file.try_clone().unwrap().into());
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                // Reading from /dev/null returns end of file.
+                let mut byte = 0u8;
+                let n = c::read(0, &mut byte as *mut u8 as *mut _, 1);
+                c::write(1, b"ok".as_ptr() as *const _, 2);
+                c::_exit(n as c_int)
+            },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(0)));
+        let mut output = String::new();
+        file.rewind().unwrap();
+        file.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "ok");
+    }
+
+    #[test]
+    fn child_setup_error() {
+        let mut clone3 = Clone3::default();
+        unsafe {
+            clone3
+                .child_setup(|| Ok(()))
+                .child_setup(|| Err(Errno(c::EPERM)))
+                .child_setup(|| c::_exit(1))
+        };
+        match unsafe { clone3.spawn() } {
+            Ok(None) => unsafe { c::_exit(2) },
+            Err(Clone3Error::ChildSetup {
+                step: "child_setup",
+                errno: Errno(c::EPERM),
+            }) => (),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn current_dir_and_umask() {
+        let mut clone3 = Clone3::default();
+        clone3.current_dir("/").umask(0o27);
+        assert_child(&mut clone3, || unsafe {
+            let mut dir = [0u8; 2];
+            c::getcwd(dir.as_mut_ptr() as *mut _, dir.len());
+            let umask = c::umask(0);
+            dir == *b"/\0" && umask == 0o27
+        });
+        clone3.current_dir("/clone3-does-not-exist");
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::ChildSetup {
+                step: "current_dir",
+                errno: Errno(c::ENOENT),
+            }) => (),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn userns_map() {
+        let (uid, gid) = unsafe { (c::geteuid(), c::getegid()) };
+        let mut clone3 = Clone3::default();
+        clone3.flag_newuser().userns_map(
+            &[IdMap {
+                inside: 0,
+                outside: uid,
+                count: 1,
+            }],
+            &[IdMap {
+                inside: 0,
+                outside: gid,
+                count: 1,
+            }],
+        );
+        assert_child(&mut clone3, || unsafe {
+            c::getuid() == 0 && c::getgid() == 0
+        });
+        clone3.userns_map(
+            &[IdMap {
+                inside: 0,
+                outside: uid,
+                count: 0,
+            }],
+            &[],
+        );
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::ChildSetup {
+                step: "userns_map",
+                errno: Errno(EINVAL),
+            }) => (),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn barrier() {
+        let mut clone3 = Clone3::default();
+        clone3.barrier();
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                let barrier = clone3.take_child_barrier().unwrap();
+                let ok = barrier.wait_for_parent().is_ok() && barrier.release_parent().is_ok();
+                c::_exit(ok as c_int)
+            },
+            Some(child) => child,
+        };
+        let barrier = child.take_barrier().unwrap();
+        barrier.release_child().unwrap();
+        barrier.wait_for_child().unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+        assert_eq!(barrier.wait_for_child(), Err(Errno(EPIPE)));
+    }
+
+    #[test]
+    fn credentials() {
+        let mut clone3 = Clone3::default();
+        // Ids above 65535 do not fit the legacy 16 bit system calls.
+        clone3.groups(&[65534, 100_000]).gid(100_000).uid(100_000);
+        if unsafe { c::geteuid() } != 0 {
+            return assert_setup_error(&mut clone3, Step::Groups, c::EPERM);
+        }
+        assert_child(&mut clone3, || unsafe {
+            let mut groups = [0; 3];
+            let n = c::getgroups(3, groups.as_mut_ptr());
+            let ids = [c::getuid(), c::geteuid(), c::getgid(), c::getegid()];
+            let groups = n == 2 && groups[..2] == [65534, 100_000];
+            groups && ids == [100_000; 4]
+        });
+    }
+
+    #[test]
+    fn capabilities() {
+        // CAP_CHOWN
+        let keep = 1 << 0;
+        let mut clone3 = Clone3::default();
+        clone3.capabilities(keep);
+        // Dropping from the bounding set requires CAP_SETPCAP.
+        if unsafe { c::geteuid() } != 0 {
+            return assert_setup_error(&mut clone3, Step::Capabilities, c::EPERM);
+        }
+        assert_child(&mut clone3, || unsafe {
+            let bounding = c::prctl(PR_CAPBSET_READ, 0) == 1 && c::prctl(PR_CAPBSET_READ, 1) == 0;
+            let mut header = CapabilityHeader {
+                version: CAPABILITY_VERSION_3,
+                pid: 0,
+            };
+            let mut data = [CapabilityData::default(); 2];
+            c::syscall(SYS_capget, &mut header, data.as_mut_ptr());
+            let sets = data[0].effective == 1 && data[0].permitted == 1 && data[1].permitted == 0;
+            bounding && sets
+        });
+    }
+
+    #[test]
+    fn seccomp_filter() {
+        // Fail getppid with EPERM and allow everything else.
+        let filter = unsafe {
+            [
+                c::BPF_STMT((c::BPF_LD | c::BPF_W | c::BPF_ABS) as u16, 0),
+                c::BPF_JUMP(
+                    (c::BPF_JMP | c::BPF_JEQ | c::BPF_K) as u16,
+                    c::SYS_getppid as u32,
+                    0,
+                    1,
+                ),
+                c::BPF_STMT(
+                    (c::BPF_RET | c::BPF_K) as u16,
+                    c::SECCOMP_RET_ERRNO | c::EPERM as u32,
+                ),
+                c::BPF_STMT((c::BPF_RET | c::BPF_K) as u16, c::SECCOMP_RET_ALLOW),
+            ]
+        };
+        let mut clone3 = Clone3::default();
+        clone3.seccomp_filter(&filter);
+        // Without no_new_privs installing a filter requires CAP_SYS_ADMIN.
+        if unsafe { c::geteuid() } != 0 {
+            return assert_setup_error(&mut clone3, Step::SeccompFilter, c::EACCES);
+        }
+        assert_child(&mut clone3, || unsafe {
+            c::syscall(c::SYS_getppid) == -1 && Errno::default().0 == c::EPERM
+        });
+    }
+
+    #[test]
+    fn landlock_ruleset() {
+        // LANDLOCK_ACCESS_FS_READ_FILE without rules that allow it.
+        let handled_access_fs: u64 = 1 << 2;
+        let ruleset = unsafe {
+            c::syscall(
+                c::SYS_landlock_create_ruleset,
+                &handled_access_fs,
+                mem::size_of::<u64>(),
+                0,
+            )
+        };
+        // Landlock is not available or disabled.
+        if ruleset == -1 {
+            return;
+        }
+        let ruleset = ruleset as c_int;
+        let null = File::open("/dev/null").unwrap();
+        let mut clone3 = Clone3::default();
+        // Passing an fd to the number of the ruleset must not replace the one the child uses.
+        clone3
+            .landlock_ruleset(unsafe { OwnedFd::from_raw_fd(ruleset) })
+            .pass_fds([(null.as_raw_fd(), ruleset)]);
+        // Without no_new_privs restricting the child requires CAP_SYS_ADMIN.
+        if unsafe { c::geteuid() } != 0 {
+            return assert_setup_error(&mut clone3, Step::LandlockRuleset, c::EPERM);
+        }
+        assert_child(&mut clone3, || unsafe {
+            let denied = c::open(c"/dev/null".as_ptr(), c::O_RDONLY) == -1
+                && Errno::default().0 == c::EACCES;
+            let mut byte = 0u8;
+            let passed = c::read(ruleset, &mut byte as *mut u8 as *mut _, 1) == 0;
+            denied && passed
+        });
+    }
+
+    #[test]
+    fn no_new_privs() {
+        let mut clone3 = Clone3::default();
+        clone3.no_new_privs();
+        assert_child(&mut clone3, || unsafe {
+            c::prctl(c::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) == 1
+        });
+    }
+
+    #[test]
+    fn parent_death_signal() {
+        let mut clone3 = Clone3::default();
+        clone3.parent_death_signal(c::SIGKILL);
+        assert_child(&mut clone3, || unsafe {
+            let mut signal: c_int = 0;
+            c::prctl(c::PR_GET_PDEATHSIG, &mut signal);
+            signal == c::SIGKILL
+        });
+    }
+
+    #[test]
+    fn name() {
+        let mut clone3 = Clone3::default();
+        clone3.name("clone3-worker");
+        assert_child(&mut clone3, || unsafe {
+            let mut name = [0u8; 16];
+            c::prctl(c::PR_GET_NAME, name.as_mut_ptr());
+            name.starts_with(b"clone3-worker\0")
+        });
+    }
+
+    #[test]
+    fn rlimit() {
+        let mut clone3 = Clone3::default();
+        clone3
+            .rlimit(c::RLIMIT_NOFILE as c_int, 64, 128)
+            .rlimit(c::RLIMIT_CORE as c_int, 0, 0);
+        assert_child(&mut clone3, || unsafe {
+            let mut nofile: rlimit = mem::zeroed();
+            let mut core: rlimit = mem::zeroed();
+            c::getrlimit(c::RLIMIT_NOFILE, &mut nofile);
+            c::getrlimit(c::RLIMIT_CORE, &mut core);
+            (nofile.rlim_cur, nofile.rlim_max, core.rlim_max) == (64, 128, 0)
+        });
+    }
+
+    #[test]
+    fn oom_score_adj() {
+        let mut clone3 = Clone3::default();
+        clone3.oom_score_adj(500);
+        assert_child(&mut clone3, || unsafe {
+            let fd = c::open(
+                c"/proc/self/oom_score_adj".as_ptr(),
+                c::O_RDONLY | O_CLOEXEC,
+            );
+            let mut value = [0u8; 8];
+            let n = c::read(fd, value.as_mut_ptr() as *mut _, value.len());
+            n == 4 && value.starts_with(b"500\n")
+        });
+    }
+
+    #[test]
+    fn nice() {
+        let mut clone3 = Clone3::default();
+        clone3.nice(5);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe { c::_exit(c::getpriority(PRIO_PROCESS as _, 0)) },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(5)));
+    }
+
+    #[test]
+    fn cpu_affinity() {
+        let mut allowed: cpu_set_t = unsafe { mem::zeroed() };
+        unsafe { c::sched_getaffinity(0, mem::size_of::<cpu_set_t>(), &mut allowed) };
+        let cpu = CpuSet(allowed).iter().next().unwrap();
+        let cpus: CpuSet = [cpu].into_iter().collect();
+        let mut clone3 = Clone3::default();
+        clone3.cpu_affinity(&cpus);
+        assert_child(&mut clone3, || unsafe {
+            let mut affinity = CpuSet::new();
+            c::sched_getaffinity(0, mem::size_of::<cpu_set_t>(), &mut affinity.0);
+            affinity == cpus
+        });
+    }
+
+    #[test]
+    fn sched_attr() {
+        let mut attr: sched_attr = unsafe { mem::zeroed() };
+        attr.sched_policy = c::SCHED_BATCH as u32;
+        let mut clone3 = Clone3::default();
+        clone3.sched_attr(&attr);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe { c::_exit(c::sched_getscheduler(0)) },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(c::SCHED_BATCH)));
+    }
+
+    #[test]
+    fn mempolicy() {
+        let mut clone3 = Clone3::default();
+        clone3.mempolicy(c::MPOL_BIND, &[1]);
+        let mut child = match unsafe { clone3.spawn() } {
+            Ok(None) => unsafe {
+                let mut mode: c_int = -1;
+                c::syscall(c::SYS_get_mempolicy, &mut mode, 0, 0, 0, 0);
+                c::_exit(mode)
+            },
+            Ok(Some(child)) => child,
+            // The kernel was built without NUMA support.
+            Err(Clone3Error::ChildSetup {
+                step: "mempolicy",
+                errno: Errno(c::ENOSYS),
+            }) => return,
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(c::MPOL_BIND)));
+    }
+
+    #[test]
+    fn personality() {
+        let mut clone3 = Clone3::default();
+        clone3.personality(c::ADDR_NO_RANDOMIZE as c_ulong);
+        assert_child(&mut clone3, || unsafe {
+            // 0xffffffff only queries the persona.
+            let persona = c::personality(0xffffffff);
+            persona & c::ADDR_NO_RANDOMIZE != 0
+        });
+    }
+
+    #[test]
+    fn close_fds() {
+        // Not close-on-exec in the parent.
+        let fd = unsafe { c::open(c"/dev/null".as_ptr(), O_RDWR) };
+        let mut clone3 = Clone3::default();
+        clone3.close_fds(3);
+        assert_child(&mut clone3, || unsafe {
+            let cloexec = c::fcntl(fd, c::F_GETFD) == FD_CLOEXEC;
+            let stderr = c::fcntl(2, c::F_GETFD) == 0;
+            cloexec && stderr
+        });
+        assert_eq!(unsafe { c::fcntl(fd, c::F_GETFD) }, 0);
+        unsafe { c::close(fd) };
+    }
+
+    #[test]
+    fn close_fds_proc() {
+        let fd = unsafe { c::open(c"/dev/null".as_ptr(), O_RDWR) };
+        let mut child = match unsafe { Clone3::default().spawn() }.unwrap() {
+            None => unsafe {
+                let result = set_cloexec_from_proc(fd);
+                let cloexec = c::fcntl(fd, c::F_GETFD) == FD_CLOEXEC;
+                c::_exit((result.is_ok() && cloexec) as c_int)
+            },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+        unsafe { c::close(fd) };
+    }
+
+    #[test]
+    fn pass_fds() {
+        let null = File::open("/dev/null").unwrap();
+        let zero = File::open("/dev/zero").unwrap();
+        let (null_fd, zero_fd) = (null.as_raw_fd(), zero.as_raw_fd());
+        // Not close-on-exec in the parent.
+        let other = unsafe { c::open(c"/dev/null".as_ptr(), O_RDWR) };
+        let mut clone3 = Clone3::default();
+        // Swap the fds.
+        clone3.pass_fds([(null_fd, zero_fd), (zero_fd, null_fd)]);
+        assert_child(&mut clone3, || unsafe {
+            let mut byte = 1u8;
+            let zero = c::read(null_fd, &mut byte as *mut u8 as *mut _, 1) == 1 && byte == 0;
+            let null = c::read(zero_fd, &mut byte as *mut u8 as *mut _, 1) == 0;
+            let inherited = c::fcntl(null_fd, c::F_GETFD) == 0;
+            let other = c::fcntl(other, c::F_GETFD) == FD_CLOEXEC;
+            zero && null && inherited && other
+        });
+        unsafe { c::close(other) };
+    }
+
+    #[test]
+    fn exec_label() {
+        let mut clone3 = Clone3::default();
+        clone3.selinux_exec_context("system_u:system_r:unconfined_t:s0");
+        // Without SELinux the kernel rejects the context or ignores it.
+        match unsafe { clone3.spawn() } {
+            Ok(None) => unsafe { c::_exit(0) },
+            Ok(Some(mut child)) => assert_eq!(child.wait(), Ok(ChildStatus::Exited(0))),
+            Err(Clone3Error::ChildSetup {
+                step: "exec_label",
+                errno: Errno(c::EINVAL),
+            }) => (),
+            Err(err) => panic!("{}", err),
+        }
+        clone3.apparmor_exec_profile("unconfined\0");
+        assert!(matches!(
+            unsafe { clone3.spawn() },
+            Err(Clone3Error::NulByte)
+        ));
+    }
+
+    #[test]
+    fn non_dumpable() {
+        let mut clone3 = Clone3::default();
+        clone3.non_dumpable();
+        assert_child(&mut clone3, || unsafe {
+            let mut core: rlimit = mem::zeroed();
+            c::getrlimit(RLIMIT_CORE, &mut core);
+            let dumpable = c::prctl(c::PR_GET_DUMPABLE, 0, 0, 0, 0);
+            dumpable == 0 && core.rlim_max == 0
+        });
+    }
+
+    #[test]
+    fn io_priority() {
+        let mut clone3 = Clone3::default();
+        clone3.io_priority(IoPriorityClass::BestEffort, 7);
+        assert_child(&mut clone3, || unsafe {
+            let priority = c::syscall(c::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0) as c_int;
+            priority == IoPriorityClass::BestEffort.value(7)
+        });
+    }
+
+    #[test]
+    fn hostname() {
+        let mut clone3 = Clone3::default();
+        clone3.hostname("clone3-test").domainname("clone3-domain");
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::SetupRequiresFlag {
+                setup: "hostname",
+                flag: Flags::NEWUTS,
+            }) => (),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+        // The new user namespace grants CAP_SYS_ADMIN over the new UTS namespace.
+        clone3.flag_newuser().flag_newuts();
+        assert_child(&mut clone3, || unsafe {
+            let mut name: c::utsname = mem::zeroed();
+            c::uname(&mut name);
+            let hostname = CStr::from_ptr(name.nodename.as_ptr());
+            let domainname = CStr::from_ptr(name.domainname.as_ptr());
+            hostname == c"clone3-test" && domainname == c"clone3-domain"
+        });
+    }
+
+    #[test]
+    fn setsid() {
+        let mut clone3 = Clone3::default();
+        clone3.setsid();
+        assert_child(&mut clone3, || unsafe { c::getsid(0) == c::getpid() });
+    }
+
+    #[test]
+    fn process_group() {
+        let mut clone3 = Clone3::default();
+        clone3.process_group(0);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe { c::_exit((c::getpgid(0) == c::getpid()) as c_int) },
+            Some(child) => child,
+        };
+        let pid = child.pid().as_raw();
+        assert_eq!(unsafe { c::getpgid(pid) }, pid);
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+    }
+
+    #[test]
+    fn pty() {
+        let mut clone3 = Clone3::default();
+        clone3.pty();
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                let tty = c::isatty(0) == 1 && c::isatty(1) == 1 && c::isatty(2) == 1;
+                let leader = c::getsid(0) == c::getpid();
+                c::_exit(if tty && leader { 0 } else { 1 })
+            },
+            Some(child) => child,
+        };
+        let master = child.take_pty_master().unwrap();
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(0)));
+        drop(master);
+    }
+
+    #[test]
+    fn controlling_terminal() {
+        let (master, slave) = open_pty().unwrap();
+        let mut clone3 = Clone3::default();
+        clone3.controlling_terminal(slave);
+        assert_child(&mut clone3, || unsafe {
+            // Opening /dev/tty only succeeds with a controlling terminal.
+            c::open(c"/dev/tty".as_ptr(), O_RDWR | O_CLOEXEC) >= 0
+        });
+        drop(master);
+        let null = File::open("/dev/null").unwrap();
+        clone3.controlling_terminal(null.into());
+        match unsafe { clone3.spawn() } {
+            Err(Clone3Error::ChildSetup {
+                step: "controlling_terminal",
+                errno: Errno(c::ENOTTY),
+            }) => (),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn piped_stdio() {
+        let mut clone3 = Clone3::default();
+        clone3.stdin(Stdio::Piped).stdout(Stdio::Piped);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                // Echo one byte from stdin to stdout.
+                let mut byte = 0u8;
+                c::read(0, &mut byte as *mut u8 as *mut _, 1);
+                c::write(1, &byte as *const u8 as *const _, 1);
+                c::_exit(0)
+            },
+            Some(child) => child,
+        };
+        child.take_stdin().unwrap().write_all(b"x").unwrap();
+        let mut output = Vec::new();
+        child
+            .take_stdout()
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, b"x");
+        assert!(child.take_stderr().is_none());
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(0)));
+    }
+}
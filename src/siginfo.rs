@@ -0,0 +1,154 @@
+use crate::sys::c::{
+    pid_t, siginfo_t, signalfd_siginfo, uid_t, CLD_CONTINUED, CLD_DUMPED, CLD_EXITED, CLD_KILLED,
+    CLD_STOPPED, CLD_TRAPPED, SIGCHLD,
+};
+use crate::ChildStatus;
+use std::{
+    fmt::{self, Debug, Formatter},
+    mem::MaybeUninit,
+    os::raw::{c_int, c_long},
+};
+
+/// Wrapper around `siginfo_t` with safe accessors.
+///
+/// Construct it with [`SigInfo::default`] which zeroes it and pass [`as_mut_ptr`](Self::as_mut_ptr)
+/// to functions like `waitid` or `sigwaitinfo`. Values read from a signalfd can be converted with
+/// [`from_signalfd_siginfo`](Self::from_signalfd_siginfo).
+///
+/// The pid, uid and status accessors read a union whose meaning depends on the signal and
+/// [`si_code`](Self::si_code). Reading them is always memory safe but the values are only
+/// meaningful for `SIGCHLD` and signals sent by processes.
+#[repr(transparent)]
+pub struct SigInfo(siginfo_t);
+
+/// The layout of `siginfo_t` for `SIGCHLD` as defined in `/usr/include/asm-generic/siginfo.h`.
+#[repr(C)]
+struct SigChld {
+    signo: c_int,
+    #[cfg(not(any(target_arch = "mips", target_arch = "mips64")))]
+    errno: c_int,
+    code: c_int,
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    errno: c_int,
+    // The union in siginfo_t is aligned like these fields which adds padding on 64 bit targets.
+    fields: SigChldFields,
+}
+
+#[repr(C)]
+struct SigChldFields {
+    pid: pid_t,
+    uid: uid_t,
+    status: c_int,
+    utime: c_long,
+    stime: c_long,
+}
+
+impl SigInfo {
+    /// Converts the result of reading from a signalfd.
+    ///
+    /// Only the fields common to all signals and the fields of signals sent by processes and
+    /// `SIGCHLD` are converted.
+    pub fn from_signalfd_siginfo(info: &signalfd_siginfo) -> Self {
+        let mut siginfo = Self::default();
+        let fields = siginfo.as_mut_ptr() as *mut SigChld;
+        unsafe {
+            (*fields).signo = info.ssi_signo as c_int;
+            (*fields).errno = info.ssi_errno;
+            (*fields).code = info.ssi_code;
+            (*fields).fields.pid = info.ssi_pid as pid_t;
+            (*fields).fields.uid = info.ssi_uid;
+            (*fields).fields.status = info.ssi_status;
+        }
+        siginfo
+    }
+
+    pub fn as_ptr(&self) -> *const siginfo_t {
+        &self.0
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut siginfo_t {
+        &mut self.0
+    }
+
+    pub fn si_signo(&self) -> c_int {
+        self.0.si_signo
+    }
+
+    pub fn si_errno(&self) -> c_int {
+        self.0.si_errno
+    }
+
+    pub fn si_code(&self) -> c_int {
+        self.0.si_code
+    }
+
+    pub fn si_pid(&self) -> pid_t {
+        unsafe { self.0.si_pid() }
+    }
+
+    pub fn si_uid(&self) -> uid_t {
+        unsafe { self.0.si_uid() }
+    }
+
+    pub fn si_status(&self) -> c_int {
+        unsafe { self.0.si_status() }
+    }
+
+    /// Interprets a `SIGCHLD` as reported by `waitid` or a signal handler.
+    ///
+    /// Returns `None` for other signals and for a zeroed value which `waitid` leaves behind with
+    /// `WNOHANG` if no child has changed state.
+    pub fn child_status(&self) -> Option<ChildStatus> {
+        if self.si_signo() != SIGCHLD || self.si_pid() == 0 {
+            return None;
+        }
+        let status = self.si_status();
+        match self.si_code() {
+            CLD_EXITED => Some(ChildStatus::Exited(status)),
+            CLD_KILLED => Some(ChildStatus::Signaled(status, false)),
+            CLD_DUMPED => Some(ChildStatus::Signaled(status, true)),
+            CLD_STOPPED | CLD_TRAPPED => Some(ChildStatus::Stopped(status)),
+            CLD_CONTINUED => Some(ChildStatus::Continued),
+            _ => None,
+        }
+    }
+}
+
+impl Default for SigInfo {
+    fn default() -> Self {
+        Self(unsafe { MaybeUninit::zeroed().assume_init() })
+    }
+}
+
+impl Debug for SigInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigInfo")
+            .field("si_signo", &self.si_signo())
+            .field("si_errno", &self.si_errno())
+            .field("si_code", &self.si_code())
+            .field("si_pid", &self.si_pid())
+            .field("si_uid", &self.si_uid())
+            .field("si_status", &self.si_status())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+
+    #[test]
+    fn from_signalfd_siginfo() {
+        let mut info: signalfd_siginfo = unsafe { mem::zeroed() };
+        info.ssi_signo = SIGCHLD as u32;
+        info.ssi_code = CLD_EXITED;
+        info.ssi_pid = 7;
+        info.ssi_uid = 8;
+        info.ssi_status = 9;
+        let siginfo = SigInfo::from_signalfd_siginfo(&info);
+        assert_eq!(siginfo.si_pid(), 7);
+        assert_eq!(siginfo.si_uid(), 8);
+        assert_eq!(siginfo.child_status(), Some(ChildStatus::Exited(9)));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::sys::c::{
+    SIGALRM, SIGCHLD, SIGCONT, SIGHUP, SIGINT, SIGKILL, SIGPIPE, SIGQUIT, SIGSTOP, SIGTERM,
+    SIGUSR1, SIGUSR2,
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    os::raw::c_int,
+};
+
+/// The largest signal number, `_NSIG` in the kernel.
+#[cfg(not(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "mips32r6",
+    target_arch = "mips64r6"
+)))]
+pub(crate) const MAX_SIGNAL: c_int = 64;
+#[cfg(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "mips32r6",
+    target_arch = "mips64r6"
+))]
+pub(crate) const MAX_SIGNAL: c_int = 128;
+
+/// A valid signal number.
+///
+/// Converts into the `u64` taken by [`exit_signal`](crate::Clone3::exit_signal). An exit signal
+/// of 0 which sends no signal is the default and has no `Signal`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Signal(c_int);
+
+impl Signal {
+    pub const HUP: Self = Self(SIGHUP);
+    pub const INT: Self = Self(SIGINT);
+    pub const QUIT: Self = Self(SIGQUIT);
+    pub const KILL: Self = Self(SIGKILL);
+    pub const USR1: Self = Self(SIGUSR1);
+    pub const USR2: Self = Self(SIGUSR2);
+    pub const PIPE: Self = Self(SIGPIPE);
+    pub const ALRM: Self = Self(SIGALRM);
+    pub const TERM: Self = Self(SIGTERM);
+    pub const CHLD: Self = Self(SIGCHLD);
+    pub const CONT: Self = Self(SIGCONT);
+    pub const STOP: Self = Self(SIGSTOP);
+
+    /// Returns `None` if `signal` is not between 1 and the largest signal number of the kernel
+    /// including real time signals. That is 64 except on MIPS where it is 128.
+    pub fn new(signal: c_int) -> Option<Self> {
+        (1..=MAX_SIGNAL).contains(&signal).then_some(Self(signal))
+    }
+
+    pub fn as_raw(self) -> c_int {
+        self.0
+    }
+}
+
+impl From<Signal> for c_int {
+    fn from(signal: Signal) -> Self {
+        signal.0
+    }
+}
+
+impl From<Signal> for u64 {
+    fn from(signal: Signal) -> Self {
+        signal.0 as u64
+    }
+}
+
+impl Display for Signal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c;
+    use crate::{ChildStatus, Clone3};
+
+    #[test]
+    fn exit_signal_chld() {
+        assert_eq!(Signal::new(0), None);
+        assert_eq!(Signal::new(MAX_SIGNAL + 1), None);
+        assert!(Signal::new(MAX_SIGNAL).is_some());
+        assert!(c::SIGRTMAX() <= MAX_SIGNAL);
+        assert_eq!(Signal::new(SIGCHLD), Some(Signal::CHLD));
+        let mut clone3 = Clone3::default();
+        clone3.exit_signal(Signal::CHLD.into());
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe { c::_exit(3) },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(3)));
+    }
+}
@@ -0,0 +1,401 @@
+use crate::sys::{
+    c::{
+        self, rlimit, _SC_PAGESIZE, ENOMEM, ENOSYS, MADV_FREE, MADV_HUGEPAGE, MAP_ANONYMOUS,
+        MAP_FAILED, MAP_FIXED, MAP_HUGETLB, MAP_NORESERVE, MAP_PRIVATE, MAP_STACK, PROT_NONE,
+        PROT_READ, PROT_WRITE, RLIMIT_STACK, RLIM_INFINITY,
+    },
+    Errno,
+};
+use std::{
+    fs,
+    mem::MaybeUninit,
+    os::raw::{c_int, c_void},
+    ptr::{self, NonNull},
+    slice,
+    sync::{Mutex, PoisonError},
+};
+
+/// A stack for a child mapped with `mmap` below a `PROT_NONE` guard page.
+///
+/// A child that overflows the stack faults on the guard page instead of overwriting the memory
+/// below it, which matters with `VM` where that memory belongs to the parent. The memory is
+/// zeroed when mapped, starts and ends on page boundaries and is only backed by physical memory
+/// once used. [`StackPool`] recycles stacks.
+///
+/// Pass it to [`stack`](crate::Clone3::stack) or [`flag_vm`](crate::Clone3::flag_vm) through
+/// [`as_mut_slice`](Self::as_mut_slice) or to
+/// [`OwnedClone3::stack_mapped`](crate::OwnedClone3::stack_mapped). The memory is unmapped when
+/// dropped.
+#[derive(Debug)]
+pub struct CloneStack {
+    // The start of the mapping which is the guard page.
+    mapping: NonNull<u8>,
+    mapping_len: usize,
+    guard_len: usize,
+    // The size of the pages backing the stack.
+    page_len: usize,
+    huge_pages: Option<HugePages>,
+}
+
+/// How a [`CloneStack`] is backed by huge pages to reduce TLB misses of deep stacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HugePages {
+    /// Advises the kernel to use transparent huge pages with `madvise(MADV_HUGEPAGE)`.
+    ///
+    /// Only the parts of the stack aligned to the huge page size can be backed by huge pages. Has
+    /// no effect if transparent huge pages are disabled in
+    /// `/sys/kernel/mm/transparent_hugepage/enabled`.
+    Transparent,
+    /// Maps the stack with `MAP_HUGETLB` from the huge pages reserved in
+    /// `/proc/sys/vm/nr_hugepages`.
+    ///
+    /// The size is rounded up to the default huge page size. The guard page is a regular page.
+    /// Mapping fails with `ENOMEM` if not enough huge pages are free.
+    Explicit,
+}
+
+// The stack is plain memory owned by this struct.
+unsafe impl Send for CloneStack {}
+unsafe impl Sync for CloneStack {}
+
+impl CloneStack {
+    /// Maps a stack of `size` bytes rounded up to the page size.
+    pub fn new(size: usize) -> Result<Self, Errno> {
+        let page = page_size();
+        let mapping_len = round_up(size, page)
+            .checked_add(page)
+            .ok_or(Errno(ENOMEM))?;
+        let mapping = map(
+            ptr::null_mut(),
+            mapping_len,
+            PROT_READ | PROT_WRITE,
+            MAP_STACK | MAP_NORESERVE,
+        )?;
+        let stack = Self {
+            mapping,
+            mapping_len,
+            guard_len: page,
+            page_len: page,
+            huge_pages: None,
+        };
+        // Stacks grow down on all architectures supported by Rust.
+        if unsafe { c::mprotect(mapping.as_ptr() as *mut c_void, page, PROT_NONE) } == -1 {
+            return Err(Errno::default());
+        }
+        Ok(stack)
+    }
+
+    /// Maps a stack of `size` bytes backed by huge pages.
+    ///
+    /// Fails with `EINVAL` or `ENOSYS` if the kernel does not support the kind of huge pages.
+    pub fn with_huge_pages(size: usize, huge_pages: HugePages) -> Result<Self, Errno> {
+        match huge_pages {
+            HugePages::Transparent => {
+                let mut stack = Self::new(size)?;
+                stack.huge_pages = Some(huge_pages);
+                let memory = stack.as_uninit_slice_mut();
+                let (memory, len) = (memory.as_mut_ptr() as *mut c_void, memory.len());
+                if unsafe { c::madvise(memory, len, MADV_HUGEPAGE) } == -1 {
+                    return Err(Errno::default());
+                }
+                Ok(stack)
+            }
+            HugePages::Explicit => Self::map_hugetlb(size),
+        }
+    }
+
+    fn map_hugetlb(size: usize) -> Result<Self, Errno> {
+        let page = page_size();
+        let huge = huge_page_size()?;
+        let len = round_up(size, huge);
+        // Reserve enough address space to place the guard page below a stack aligned to the huge
+        // page size and unmap the rest afterwards.
+        let reserved_len = len.checked_add(huge).ok_or(Errno(ENOMEM))?;
+        let reserved = map(ptr::null_mut(), reserved_len, PROT_NONE, MAP_NORESERVE)?;
+        let reserved = reserved.as_ptr() as usize;
+        let start = round_up(reserved + page, huge);
+        let unmap = |from: usize, to: usize| unsafe { c::munmap(from as *mut c_void, to - from) };
+        // Without MAP_NORESERVE huge pages are reserved now instead of failing with SIGBUS when
+        // the child touches the stack.
+        if let Err(errno) = map(
+            start as *mut c_void,
+            len,
+            PROT_READ | PROT_WRITE,
+            MAP_FIXED | MAP_HUGETLB | MAP_STACK,
+        ) {
+            unmap(reserved, reserved + reserved_len);
+            return Err(errno);
+        }
+        if start - page > reserved {
+            unmap(reserved, start - page);
+        }
+        if start + len < reserved + reserved_len {
+            unmap(start + len, reserved + reserved_len);
+        }
+        Ok(Self {
+            mapping: NonNull::new((start - page) as *mut u8).unwrap(),
+            mapping_len: page + len,
+            guard_len: page,
+            page_len: huge,
+            huge_pages: Some(HugePages::Explicit),
+        })
+    }
+
+    /// The usable size of the stack without the guard page.
+    pub fn len(&self) -> usize {
+        self.mapping_len - self.guard_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The lowest address of the usable stack just above the guard page.
+    pub fn as_ptr(&self) -> *const u8 {
+        unsafe { self.mapping.as_ptr().add(self.guard_len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_ptr() as *mut u8, self.len()) }
+    }
+
+    pub fn as_uninit_slice_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        unsafe { slice::from_raw_parts_mut(self.as_ptr() as *mut MaybeUninit<u8>, self.len()) }
+    }
+
+    /// How the stack is backed by huge pages.
+    pub fn huge_pages(&self) -> Option<HugePages> {
+        self.huge_pages
+    }
+}
+
+impl Drop for CloneStack {
+    fn drop(&mut self) {
+        unsafe { c::munmap(self.mapping.as_ptr() as *mut c_void, self.mapping_len) };
+    }
+}
+
+/// Recycles [`CloneStack`]s of one size to avoid mapping and faulting in a new stack for every
+/// child.
+///
+/// A stack must only be returned with [`put`](Self::put) once no child runs on it anymore, for
+/// example after waiting for a `VM` child.
+#[derive(Debug)]
+pub struct StackPool {
+    stack_size: usize,
+    max_idle: usize,
+    free_memory: bool,
+    huge_pages: Option<HugePages>,
+    idle: Mutex<Vec<CloneStack>>,
+}
+
+impl StackPool {
+    /// Creates a pool of stacks of `stack_size` bytes that keeps up to `max_idle` unused stacks.
+    pub fn new(stack_size: usize, max_idle: usize) -> Self {
+        Self {
+            stack_size,
+            max_idle,
+            free_memory: false,
+            huge_pages: None,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Lets the kernel reclaim the memory of idle stacks with `madvise(MADV_FREE)`.
+    ///
+    /// The memory of a stack is kept until the system is under memory pressure and reused without
+    /// faults if it has not been reclaimed. The contents of recycled stacks are undefined either
+    /// way.
+    pub fn free_memory(&mut self, free_memory: bool) -> &mut Self {
+        self.free_memory = free_memory;
+        self
+    }
+
+    /// Maps new stacks with [`CloneStack::with_huge_pages`].
+    pub fn huge_pages(&mut self, huge_pages: Option<HugePages>) -> &mut Self {
+        self.huge_pages = huge_pages;
+        self
+    }
+
+    /// Takes an idle stack or maps a new one.
+    pub fn get(&self) -> Result<CloneStack, Errno> {
+        match self
+            .idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop()
+        {
+            Some(stack) => Ok(stack),
+            None => match self.huge_pages {
+                Some(huge_pages) => CloneStack::with_huge_pages(self.stack_size, huge_pages),
+                None => CloneStack::new(self.stack_size),
+            },
+        }
+    }
+
+    /// Returns a stack to the pool. It is unmapped if the pool is full or the stack was not
+    /// created by the pool.
+    pub fn put(&self, mut stack: CloneStack) {
+        if stack.huge_pages != self.huge_pages
+            || stack.len() != round_up(self.stack_size, stack.page_len)
+        {
+            return;
+        }
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        if idle.len() >= self.max_idle {
+            return;
+        }
+        if self.free_memory {
+            let memory = stack.as_uninit_slice_mut();
+            unsafe { c::madvise(memory.as_mut_ptr() as *mut c_void, memory.len(), MADV_FREE) };
+        }
+        idle.push(stack);
+    }
+
+    /// The number of idle stacks.
+    pub fn idle(&self) -> usize {
+        self.idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+}
+
+/// The stack size returned by [`default_stack_size`] if `RLIMIT_STACK` is unlimited or smaller.
+/// 2 MiB like the default stack size of Rust threads.
+pub const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// A stack size for children that run arbitrary code.
+///
+/// This is the soft limit of `RLIMIT_STACK` which is the size of the stack of the main thread,
+/// usually 8 MiB, but at least [`DEFAULT_STACK_SIZE`]. A [`CloneStack`] of this size only uses
+/// physical memory for the part the child touches.
+pub fn default_stack_size() -> usize {
+    let mut limit = rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { c::getrlimit(RLIMIT_STACK, &mut limit) } == -1 || limit.rlim_cur == RLIM_INFINITY {
+        return DEFAULT_STACK_SIZE;
+    }
+    usize::try_from(limit.rlim_cur).map_or(DEFAULT_STACK_SIZE, |size| size.max(DEFAULT_STACK_SIZE))
+}
+
+pub(crate) fn page_size() -> usize {
+    unsafe { c::sysconf(_SC_PAGESIZE) as usize }
+}
+
+/// The default huge page size from `/proc/meminfo`.
+fn huge_page_size() -> Result<usize, Errno> {
+    let meminfo = fs::read_to_string("/proc/meminfo").map_err(|_| Errno(ENOSYS))?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Hugepagesize:"))
+        .and_then(|size| size.trim().strip_suffix(" kB")?.parse::<usize>().ok())
+        .map(|kib| kib * 1024)
+        .ok_or(Errno(ENOSYS))
+}
+
+/// Rounds `size` up to a multiple of `page` or to `usize::MAX` on overflow.
+fn round_up(size: usize, page: usize) -> usize {
+    size.checked_add(page - 1)
+        .map_or(usize::MAX, |size| size & !(page - 1))
+}
+
+/// Maps anonymous private memory.
+fn map(address: *mut c_void, len: usize, prot: c_int, flags: c_int) -> Result<NonNull<u8>, Errno> {
+    let flags = flags | MAP_PRIVATE | MAP_ANONYMOUS;
+    let mapping = unsafe { c::mmap(address, len, prot, flags, -1, 0) };
+    if mapping == MAP_FAILED {
+        return Err(Errno::default());
+    }
+    Ok(NonNull::new(mapping as *mut u8).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::SIGSEGV;
+    use crate::{ChildStatus, Clone3, MIN_STACK_SIZE, STACK_ALIGN};
+
+    #[test]
+    fn guard_page() {
+        let mut stack = CloneStack::new(10_000).unwrap();
+        assert_eq!(stack.len() % page_size(), 0);
+        assert!(stack.len() >= 10_000);
+        assert!(stack.as_mut_slice().iter().all(|&byte| byte == 0));
+        let top = stack.as_ptr() as usize + stack.len();
+        assert_eq!(top % STACK_ALIGN, 0);
+        let mut child = match unsafe { Clone3::fork_like().spawn() }.unwrap() {
+            None => unsafe {
+                let below = (stack.as_ptr() as *mut u8).sub(1);
+                below.write_volatile(1);
+                c::_exit(0)
+            },
+            Some(child) => child,
+        };
+        assert!(matches!(
+            child.wait(),
+            Ok(ChildStatus::Signaled(SIGSEGV, _))
+        ));
+    }
+
+    #[test]
+    fn pool_recycles_stacks() {
+        let mut pool = StackPool::new(MIN_STACK_SIZE, 1);
+        pool.free_memory(true);
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        let address = first.as_ptr();
+        pool.put(first);
+        pool.put(second);
+        assert_eq!(pool.idle(), 1);
+        pool.put(CloneStack::new(2 * MIN_STACK_SIZE).unwrap());
+        assert_eq!(pool.idle(), 1);
+        let mut stack = pool.get().unwrap();
+        assert_eq!(stack.as_ptr(), address);
+        assert_eq!(pool.idle(), 0);
+        stack.as_mut_slice()[0] = 1;
+    }
+
+    #[test]
+    fn default_size() {
+        let size = default_stack_size();
+        assert!(size >= DEFAULT_STACK_SIZE);
+        let mut limit = rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        unsafe { c::getrlimit(RLIMIT_STACK, &mut limit) };
+        if limit.rlim_cur != RLIM_INFINITY && limit.rlim_cur as usize > DEFAULT_STACK_SIZE {
+            assert_eq!(size, limit.rlim_cur as usize);
+        }
+    }
+
+    #[test]
+    fn huge_page_stacks() {
+        match CloneStack::with_huge_pages(MIN_STACK_SIZE, HugePages::Transparent) {
+            Ok(mut stack) => {
+                assert_eq!(stack.huge_pages(), Some(HugePages::Transparent));
+                assert_eq!(stack.len(), MIN_STACK_SIZE);
+                stack.as_mut_slice()[0] = 1;
+            }
+            Err(errno) => assert_eq!(errno, Errno(c::EINVAL)),
+        }
+        // Most systems do not reserve huge pages.
+        let mut stack = match CloneStack::with_huge_pages(1, HugePages::Explicit) {
+            Ok(stack) => stack,
+            Err(errno) => return assert!([ENOMEM, ENOSYS].contains(&errno.0)),
+        };
+        let huge = huge_page_size().unwrap();
+        assert_eq!(stack.len(), huge);
+        assert_eq!(stack.as_ptr() as usize % huge, 0);
+        assert!(stack.as_mut_slice().iter().all(|&byte| byte == 0));
+        let mut pool = StackPool::new(1, 1);
+        pool.huge_pages(Some(HugePages::Explicit));
+        pool.put(CloneStack::new(huge).unwrap());
+        assert_eq!(pool.idle(), 0);
+        pool.put(stack);
+        assert_eq!(pool.idle(), 1);
+    }
+}
@@ -0,0 +1,229 @@
+//! The libc bindings of the selected backend and definitions that differ between the libc of
+//! Linux and bionic on Android or that the libc crate lacks for some targets.
+
+use c::ENOSYS;
+#[cfg(any(not(feature = "uapi"), target_os = "android"))]
+use std::os::raw::c_int;
+use std::os::raw::{c_long, c_void};
+#[cfg(not(feature = "uapi"))]
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io,
+};
+#[cfg(target_os = "android")]
+use std::{
+    os::raw::{c_char, c_uint},
+    ptr,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+#[cfg(all(not(feature = "uapi"), not(feature = "libc")))]
+compile_error!("either the uapi or the libc feature must be enabled");
+
+#[cfg(feature = "uapi")]
+pub use uapi::Errno;
+#[cfg(feature = "uapi")]
+pub(crate) use uapi::{c, set_errno};
+
+#[cfg(not(feature = "uapi"))]
+pub(crate) use libc as c;
+
+/// An errno value.
+///
+/// The same type as `uapi::Errno` which is used instead with the `uapi` feature. The `Default`
+/// implementation returns the current value of errno.
+#[cfg(not(feature = "uapi"))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Errno(pub c_int);
+
+#[cfg(not(feature = "uapi"))]
+impl Error for Errno {}
+
+#[cfg(not(feature = "uapi"))]
+impl Display for Errno {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(not(feature = "uapi"))]
+impl From<Errno> for io::Error {
+    fn from(errno: Errno) -> Self {
+        Self::from_raw_os_error(errno.0)
+    }
+}
+
+#[cfg(all(not(feature = "uapi"), feature = "rustix"))]
+impl From<rustix::io::Errno> for Errno {
+    fn from(errno: rustix::io::Errno) -> Self {
+        Self(errno.raw_os_error())
+    }
+}
+
+#[cfg(all(not(feature = "uapi"), feature = "rustix"))]
+impl From<Errno> for rustix::io::Errno {
+    fn from(errno: Errno) -> Self {
+        Self::from_raw_os_error(errno.0)
+    }
+}
+
+#[cfg(not(feature = "uapi"))]
+impl Default for Errno {
+    fn default() -> Self {
+        Self(unsafe { *errno_location() })
+    }
+}
+
+#[cfg(not(feature = "uapi"))]
+pub(crate) fn set_errno(errno: c_int) {
+    unsafe { *errno_location() = errno };
+}
+
+#[cfg(all(not(feature = "uapi"), target_os = "linux"))]
+unsafe fn errno_location() -> *mut c_int {
+    c::__errno_location()
+}
+
+#[cfg(all(not(feature = "uapi"), target_os = "android"))]
+unsafe fn errno_location() -> *mut c_int {
+    c::__errno()
+}
+
+/// Offset of the system call numbers which are the same on all architectures for system calls added
+/// since Linux 5.1 except for the offset.
+#[cfg(target_arch = "mips")]
+const SYSCALL_BASE: c_long = 4000;
+#[cfg(all(target_arch = "mips64", target_pointer_width = "64"))]
+const SYSCALL_BASE: c_long = 5000;
+#[cfg(all(target_arch = "mips64", target_pointer_width = "32"))]
+const SYSCALL_BASE: c_long = 6000;
+// __X32_SYSCALL_BIT
+#[cfg(all(target_arch = "x86_64", target_pointer_width = "32"))]
+const SYSCALL_BASE: c_long = 0x40000000;
+#[cfg(not(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    all(target_arch = "x86_64", target_pointer_width = "32")
+)))]
+const SYSCALL_BASE: c_long = 0;
+
+// Defined here because the libc crate lags behind for musl and newer architectures.
+#[allow(non_upper_case_globals)]
+pub(crate) const SYS_pidfd_send_signal: c_long = SYSCALL_BASE + 424;
+// With rustix only the asm system calls use it because rustix opens the pidfds.
+#[allow(non_upper_case_globals)]
+#[cfg_attr(feature = "rustix", allow(dead_code))]
+pub(crate) const SYS_pidfd_open: c_long = SYSCALL_BASE + 434;
+#[allow(non_upper_case_globals)]
+pub(crate) const SYS_clone3: c_long = SYSCALL_BASE + 435;
+
+// On x86, arm and sparc the system calls without the 32 suffix take 16 bit ids.
+#[cfg(not(any(target_arch = "x86", target_arch = "arm", target_arch = "sparc")))]
+pub(crate) use c::{SYS_setgroups, SYS_setresgid, SYS_setresuid};
+#[cfg(any(target_arch = "x86", target_arch = "arm", target_arch = "sparc"))]
+pub(crate) use c::{
+    SYS_setgroups32 as SYS_setgroups, SYS_setresgid32 as SYS_setresgid,
+    SYS_setresuid32 as SYS_setresuid,
+};
+
+#[cfg(target_os = "linux")]
+pub(crate) use c::{
+    sched_attr, sigwaitinfo, AT_EACCESS, CLOSE_RANGE_CLOEXEC, NS_GET_USERNS, TIOCGPTPEER,
+};
+
+/// The first Android version whose seccomp policy for apps allows clone3. Older versions kill the
+/// process with `SIGSYS` instead of failing the system call.
+#[cfg(target_os = "android")]
+const CLONE3_MIN_API_LEVEL: c_int = 31;
+
+#[cfg(target_os = "android")]
+pub(crate) const AT_EACCESS: c_int = 0x200;
+#[cfg(target_os = "android")]
+pub(crate) const CLOSE_RANGE_CLOEXEC: c_uint = 1 << 2;
+// _IO(0xb7, 0x1)
+#[cfg(target_os = "android")]
+pub(crate) const NS_GET_USERNS: c::Ioctl = 0xb701;
+// The same on all architectures supported by Android.
+#[cfg(target_os = "android")]
+pub(crate) const TIOCGPTPEER: c::Ioctl = 0x5441;
+
+/// Argument of the `sched_setattr` system call as defined in `/usr/include/linux/sched/types.h`.
+/// The libc crate only defines it for Linux.
+#[cfg(target_os = "android")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct sched_attr {
+    pub size: u32,
+    pub sched_policy: u32,
+    pub sched_flags: u64,
+    pub sched_nice: i32,
+    pub sched_priority: u32,
+    pub sched_runtime: u64,
+    pub sched_deadline: u64,
+    pub sched_period: u64,
+}
+
+/// bionic only has `sigwaitinfo` since API level 23 and the libc crate does not bind it.
+#[cfg(target_os = "android")]
+pub(crate) unsafe fn sigwaitinfo(set: *const c::sigset_t, info: *mut c::siginfo_t) -> c_int {
+    c::sigtimedwait(set, info, ptr::null())
+}
+
+/// Whether clone3 may be called. Always true on Linux.
+#[cfg(target_os = "linux")]
+pub(crate) fn clone3_allowed() -> bool {
+    true
+}
+
+/// Whether clone3 may be called. On Android this depends on the API level of the device which is
+/// read once. Does not allocate.
+#[cfg(target_os = "android")]
+pub(crate) fn clone3_allowed() -> bool {
+    static API_LEVEL: AtomicI32 = AtomicI32::new(0);
+    let mut level = API_LEVEL.load(Ordering::Relaxed);
+    if level == 0 {
+        level = api_level().unwrap_or(-1);
+        API_LEVEL.store(level, Ordering::Relaxed);
+    }
+    level >= CLONE3_MIN_API_LEVEL
+}
+
+/// The API level of the device from the system property `ro.build.version.sdk`.
+#[cfg(target_os = "android")]
+fn api_level() -> Option<c_int> {
+    // PROP_VALUE_MAX
+    let mut value = [0 as c_char; 92];
+    let len =
+        unsafe { c::__system_property_get(c"ro.build.version.sdk".as_ptr(), value.as_mut_ptr()) };
+    let value = &value[..usize::try_from(len).ok()?];
+    let mut level: c_int = 0;
+    for &digit in value {
+        let digit = (digit as u8 as char).to_digit(10)?;
+        level = level.checked_mul(10)?.checked_add(digit as c_int)?;
+    }
+    (!value.is_empty()).then_some(level)
+}
+
+/// The raw clone3 system call. Fails with `ENOSYS` without performing it if it is not
+/// [allowed](clone3_allowed).
+pub(crate) unsafe fn clone3(args: *const c_void, size: usize) -> c_long {
+    if !clone3_allowed() {
+        set_errno(ENOSYS);
+        return -1;
+    }
+    c::syscall(SYS_clone3, args, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syscall_numbers_match_libc() {
+        assert_eq!(SYS_pidfd_send_signal, c::SYS_pidfd_send_signal);
+        assert_eq!(SYS_pidfd_open, c::SYS_pidfd_open);
+        assert_eq!(SYS_clone3, c::SYS_clone3);
+    }
+}
@@ -0,0 +1,23 @@
+//! Helpers shared by the tests of several modules.
+
+use crate::sys::c;
+use crate::{ChildStatus, Clone3};
+use std::os::raw::c_int;
+
+/// Spawns a child with `clone3` that exits with whether `check` returned true and asserts that it
+/// did.
+///
+/// `check` runs in the child so it must not allocate or panic.
+#[track_caller]
+pub(crate) fn assert_child(clone3: &mut Clone3<'_>, check: impl FnOnce() -> bool) {
+    let mut child = match unsafe { clone3.spawn() }.unwrap() {
+        None => unsafe { c::_exit(check() as c_int) },
+        Some(child) => child,
+    };
+    assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+}
+
+#[test]
+fn exit_status() {
+    assert_child(&mut Clone3::default(), || true);
+}
@@ -0,0 +1,328 @@
+use crate::sys::{
+    c::{self, pid_t, EINTR, O_CLOEXEC, O_RDONLY},
+    Errno,
+};
+use crate::{
+    setup::{check, effective_capabilities},
+    sys::NS_GET_USERNS,
+    Clone3Error, Flags,
+};
+use std::{
+    error::Error,
+    ffi::CStr,
+    fmt::{self, Display, Formatter},
+    mem,
+    os::{
+        raw::c_int,
+        unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+};
+
+/// The largest number of levels of a [`TidSpec`], `MAX_PID_NS_LEVEL` in the kernel.
+pub const MAX_PID_NS_LEVEL: usize = 32;
+
+pub(crate) const CAP_SYS_ADMIN: u32 = 21;
+pub(crate) const CAP_CHECKPOINT_RESTORE: u32 = 40;
+// The inode number of the initial user namespace, `PROC_USER_INIT_INO` in the kernel.
+const USER_INIT_INO: u64 = 0xEFFFFFFD;
+
+/// The pids of a child in its pid namespace and the namespaces above it for
+/// [`set_tid`](crate::Clone3::set_tid).
+///
+/// Levels are added from the innermost namespace, the one the child is created in, to the
+/// outermost. With `NEWPID` the innermost namespace is the new one where the child usually gets
+/// pid 1. Levels that are not added are chosen by the kernel. Outer levels can also be left to the
+/// kernel with [`any`](Self::any) but only if no level outside of them is specified because the
+/// kernel takes a contiguous array. Every specified pid must be positive.
+///
+/// Setting a pid requires `CAP_SYS_ADMIN` or `CAP_CHECKPOINT_RESTORE` in the user namespace
+/// owning the pid namespace of the level. [`Clone3::try_call`](crate::Clone3::try_call) rejects
+/// invalid specifications with [`Clone3Error::InvalidSetTid`](crate::Clone3Error::InvalidSetTid)
+/// naming the level.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TidSpec {
+    // Innermost first. 0 leaves the level to the kernel.
+    tids: Vec<pid_t>,
+}
+
+impl TidSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Specifies `tid` as the pid in the next outer level.
+    pub fn tid(&mut self, tid: pid_t) -> &mut Self {
+        self.tids.push(tid);
+        self
+    }
+
+    /// Leaves the pid in the next outer level to the kernel.
+    pub fn any(&mut self) -> &mut Self {
+        self.tids.push(0);
+        self
+    }
+
+    /// The number of levels passed to the kernel up to the outermost specified one.
+    pub fn len(&self) -> usize {
+        self.tids
+            .iter()
+            .rposition(|&tid| tid != 0)
+            .map_or(0, |level| level + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The pids passed to the kernel, innermost first.
+    pub fn as_slice(&self) -> &[pid_t] {
+        &self.tids[..self.len()]
+    }
+
+    /// Checks that the caller may set the pids of this specification for a child created with
+    /// `flags`. Diagnoses the `EPERM` the kernel returns otherwise.
+    ///
+    /// The kernel requires `CAP_CHECKPOINT_RESTORE` or `CAP_SYS_ADMIN` in the user namespace owning
+    /// the pid namespace of each specified level. The effective capabilities of the caller apply
+    /// to its user namespace and, in the initial user namespace, to all others. The new pid
+    /// namespace of `NEWPID` is owned by the current user namespace or, with `NEWUSER`, by the new
+    /// one in which the caller always has all capabilities. The pid namespaces above the one of
+    /// the caller cannot be inspected and are assumed to be owned by an ancestor user namespace.
+    pub fn check_capabilities(&self, flags: Flags) -> Result<(), MissingCapability> {
+        let capable = effective_capabilities().is_ok_and(|capabilities| {
+            capabilities & (1 << CAP_SYS_ADMIN | 1 << CAP_CHECKPOINT_RESTORE) != 0
+        });
+        let initial = namespace_inode(c"/proc/self/ns/user") == Ok(USER_INIT_INO);
+        let new_levels = flags.contains(Flags::NEWPID) as usize;
+        for level in 0..self.len() {
+            let current = match level.checked_sub(new_levels) {
+                None if flags.contains(Flags::NEWUSER) => continue,
+                None => true,
+                Some(0) => pid_namespace_owned_by_current(),
+                Some(_) => false,
+            };
+            if capable && (current || initial) {
+                continue;
+            }
+            return Err(MissingCapability {
+                level,
+                user_namespace: if current {
+                    "the current user namespace"
+                } else {
+                    "an ancestor user namespace"
+                },
+            });
+        }
+        Ok(())
+    }
+
+    /// The largest number of levels for a child created with `flags` by the calling process.
+    ///
+    /// This is the number of pid namespaces the child is in, one more than the caller with
+    /// `NEWPID`, but at most [`MAX_PID_NS_LEVEL`]. The namespaces of the caller are counted in the
+    /// `NSpid` line of `/proc/self/status`. If it cannot be read only the maximum applies.
+    pub fn max_levels(flags: Flags) -> usize {
+        max_levels(pid_namespace_levels(), flags)
+    }
+
+    /// Checks the pids and the number of levels against `max_levels`.
+    pub(crate) fn validate(&self, max_levels: usize) -> Result<(), Clone3Error> {
+        if let Some((level, reason)) = self.find_invalid() {
+            return Err(Clone3Error::InvalidSetTid { level, reason });
+        }
+        if self.len() > max_levels {
+            return Err(Clone3Error::SetTidTooLong {
+                levels: self.len(),
+                max: max_levels,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the innermost invalid level and the reason it is invalid.
+    pub(crate) fn find_invalid(&self) -> Option<(usize, &'static str)> {
+        self.as_slice()
+            .iter()
+            .enumerate()
+            .find_map(|(level, &tid)| match tid {
+                0 => Some((level, "left to the kernel below a specified outer level")),
+                c_int::MIN..=-1 => Some((level, "not a positive pid")),
+                _ => None,
+            })
+    }
+}
+
+impl From<&[pid_t]> for TidSpec {
+    /// The pids innermost first.
+    fn from(tids: &[pid_t]) -> Self {
+        Self {
+            tids: tids.to_vec(),
+        }
+    }
+}
+
+impl From<Vec<pid_t>> for TidSpec {
+    /// The pids innermost first.
+    fn from(tids: Vec<pid_t>) -> Self {
+        Self { tids }
+    }
+}
+
+/// The capabilities required by a level of a [`TidSpec`] are missing. Returned by
+/// [`TidSpec::check_capabilities`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MissingCapability {
+    /// The level counted from the innermost pid namespace.
+    pub level: usize,
+    /// The user namespace in which the capabilities are missing.
+    pub user_namespace: &'static str,
+}
+
+impl Display for MissingCapability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "set_tid level {} requires CAP_CHECKPOINT_RESTORE or CAP_SYS_ADMIN in {}",
+            self.level, self.user_namespace
+        )
+    }
+}
+
+impl Error for MissingCapability {}
+
+fn namespace_inode(path: &CStr) -> Result<u64, Errno> {
+    let fd = check(unsafe { c::open(path.as_ptr(), O_RDONLY | O_CLOEXEC) })?;
+    inode(&unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn inode(fd: &OwnedFd) -> Result<u64, Errno> {
+    let mut stat: c::stat = unsafe { mem::zeroed() };
+    check(unsafe { c::fstat(fd.as_raw_fd(), &mut stat) })?;
+    Ok(stat.st_ino)
+}
+
+/// The number of pid namespaces of a child created with `flags` by a process in `levels` pid
+/// namespaces.
+pub(crate) fn max_levels(levels: Option<usize>, flags: Flags) -> usize {
+    levels.map_or(MAX_PID_NS_LEVEL, |levels| {
+        (levels + flags.contains(Flags::NEWPID) as usize).min(MAX_PID_NS_LEVEL)
+    })
+}
+
+/// The number of pid namespaces the caller is in. Does not allocate because it is used before the
+/// system call.
+pub(crate) fn pid_namespace_levels() -> Option<usize> {
+    let fd = check(unsafe { c::open(c"/proc/self/status".as_ptr(), O_RDONLY | O_CLOEXEC) }).ok()?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut status = [0u8; 4096];
+    let mut len = 0;
+    while len < status.len() {
+        let n = unsafe {
+            c::read(
+                fd.as_raw_fd(),
+                status[len..].as_mut_ptr() as *mut _,
+                status.len() - len,
+            )
+        };
+        match n {
+            -1 if Errno::default().0 == EINTR => continue,
+            -1 => return None,
+            0 => break,
+            n => len += n as usize,
+        }
+    }
+    let status = &status[..len];
+    let key = b"\nNSpid:";
+    let start = status.windows(key.len()).position(|window| window == key)? + key.len();
+    let line = status[start..].split(|&byte| byte == b'\n').next()?;
+    let levels = line
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|pid| !pid.is_empty())
+        .count();
+    (levels > 0).then_some(levels)
+}
+
+/// Whether the pid namespace of the caller is owned by its user namespace. Assumed if the kernel
+/// does not support querying the owner.
+fn pid_namespace_owned_by_current() -> bool {
+    let owner = (|| {
+        let pid = check(unsafe { c::open(c"/proc/self/ns/pid".as_ptr(), O_RDONLY | O_CLOEXEC) })?;
+        let pid = unsafe { OwnedFd::from_raw_fd(pid) };
+        let owner = check(unsafe { c::ioctl(pid.as_raw_fd(), NS_GET_USERNS) })?;
+        inode(&unsafe { OwnedFd::from_raw_fd(owner) })
+    })();
+    match (owner, namespace_inode(c"/proc/self/ns/user")) {
+        (Ok(owner), Ok(current)) => owner == current,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_child;
+    use crate::{Clone3, Clone3Error};
+
+    #[test]
+    fn levels() {
+        let mut spec = TidSpec::new();
+        spec.tid(1).tid(1000).any().any();
+        assert_eq!(spec.as_slice(), &[1, 1000]);
+        assert_eq!(spec.find_invalid(), None);
+        assert_eq!(TidSpec::from(vec![5, 0, 7]).find_invalid().unwrap().0, 1);
+        let spec = TidSpec::from(&[3, -1][..]);
+        assert_eq!(spec.find_invalid().unwrap().0, 1);
+        let mut clone3 = Clone3::default();
+        clone3.set_tid(&spec);
+        match unsafe { clone3.try_call() } {
+            Err(Clone3Error::InvalidSetTid { level: 1, .. }) => (),
+            result => panic!("{:?}", result),
+        }
+        let mut clone3 = Clone3::default();
+        let spec = TidSpec::from(vec![1]);
+        clone3.set_tid(&spec);
+        let cl_args = clone3.as_clone_args();
+        assert_eq!(cl_args.set_tid, spec.as_slice().as_ptr() as u64);
+        assert_eq!(cl_args.set_tid_size, 1);
+    }
+
+    #[test]
+    fn too_many_levels() {
+        let levels = pid_namespace_levels().unwrap();
+        assert_eq!(TidSpec::max_levels(Flags::NEWPID), levels + 1);
+        let spec = TidSpec::from(vec![1; levels + 2]);
+        let mut clone3 = Clone3::default();
+        clone3.set_tid(&spec).flag_newpid();
+        match unsafe { clone3.try_call() } {
+            Err(Clone3Error::SetTidTooLong { levels: l, max }) => {
+                assert_eq!((l, max), (levels + 2, levels + 1))
+            }
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(unsafe { clone3.call() }, Err(Errno(c::EINVAL)));
+    }
+
+    #[test]
+    fn capabilities() {
+        let own = TidSpec::from(vec![1]);
+        let nested = TidSpec::from(vec![1, 1]);
+        let new_namespaces = Flags::NEWUSER | Flags::NEWPID;
+        // The new pid namespace is owned by the new user namespace.
+        assert_eq!(
+            TidSpec::from(vec![1]).check_capabilities(new_namespaces),
+            Ok(())
+        );
+        if unsafe { c::geteuid() } == 0 {
+            assert_eq!(own.check_capabilities(Flags::empty()), Ok(()));
+            assert_eq!(nested.check_capabilities(new_namespaces), Ok(()));
+        }
+        let mut clone3 = Clone3::default();
+        clone3.capabilities(0);
+        assert_child(&mut clone3, || {
+            let missing = own.check_capabilities(Flags::empty());
+            let missing_nested = nested.check_capabilities(new_namespaces);
+            missing.map_err(|missing| missing.level) == Err(0)
+                && missing_nested.map_err(|missing| missing.level) == Err(1)
+        });
+    }
+}
@@ -0,0 +1,120 @@
+#[cfg(target_arch = "x86")]
+use std::arch::asm;
+
+// Large enough for the thread control block fields that compilers access at fixed offsets like
+// the stack protector canary at fs:0x28 on x86_64.
+const BLOCK_WORDS: usize = 64;
+
+/// A minimal thread local storage block for a child created with
+/// [`flag_settls`](crate::Clone3::flag_settls).
+///
+/// [`tls`](Self::tls) returns the value the kernel expects for `SETTLS` on the current
+/// architecture:
+///
+/// - x86_64: the fs base which points to the thread control block. Its first word points to
+///   itself.
+/// - aarch64: the value of `tpidr_el0` which points to the thread control block. The TLS area
+///   follows it.
+/// - x86: a pointer to a `user_desc` for the gs segment of the current thread with the thread
+///   control block as the base address.
+///
+/// The block is 512 zeroed bytes around the thread control block. It does not contain the thread
+/// locals of libc or Rust so the child must not use thread locals, `errno` or functions of libc
+/// that depend on them. The block must not be dropped before the child exits.
+#[derive(Debug)]
+pub struct TlsBlock {
+    memory: Box<[usize]>,
+    #[cfg(target_arch = "x86")]
+    desc: Box<UserDesc>,
+}
+
+/// `struct user_desc` from `asm/ldt.h`.
+#[cfg(target_arch = "x86")]
+#[derive(Debug)]
+#[repr(C)]
+struct UserDesc {
+    entry_number: u32,
+    base_addr: u32,
+    limit: u32,
+    // seg_32bit, contents, read_exec_only, limit_in_pages, seg_not_present and useable.
+    flags: u32,
+}
+
+impl TlsBlock {
+    pub fn new() -> Self {
+        #[cfg_attr(target_arch = "aarch64", allow(unused_mut))]
+        let mut memory = vec![0usize; BLOCK_WORDS].into_boxed_slice();
+        // x86 and x86_64 use TLS variant II where the TLS area is below the thread control block
+        // which starts with a pointer to itself. aarch64 uses variant I where the TLS area follows
+        // the thread control block which starts with the dynamic thread vector that stays null.
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        {
+            let tcb = &mut memory[BLOCK_WORDS / 2];
+            *tcb = tcb as *mut usize as usize;
+        }
+        Self {
+            #[cfg(target_arch = "x86")]
+            desc: Box::new(UserDesc {
+                entry_number: current_gs_entry(),
+                base_addr: memory[BLOCK_WORDS / 2] as u32,
+                limit: 0xfffff,
+                flags: 0b101_0001,
+            }),
+            memory,
+        }
+    }
+
+    /// The value to pass to [`flag_settls`](crate::Clone3::flag_settls).
+    pub fn tls(&self) -> u64 {
+        #[cfg(target_arch = "x86")]
+        return &*self.desc as *const UserDesc as u64;
+        #[cfg(target_arch = "x86_64")]
+        return &self.memory[BLOCK_WORDS / 2] as *const usize as u64;
+        #[cfg(target_arch = "aarch64")]
+        return self.memory.as_ptr() as u64;
+    }
+}
+
+impl Default for TlsBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The GDT entry of the gs segment which the kernel updates for the child.
+#[cfg(target_arch = "x86")]
+fn current_gs_entry() -> u32 {
+    let gs: u32;
+    unsafe { asm!("mov {:e}, gs", out(reg) gs) };
+    gs >> 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::{self, SYS_exit_group};
+    use crate::{ChildStatus, Clone3};
+    use std::{arch::asm, os::raw::c_long};
+
+    #[test]
+    fn thread_pointer() {
+        let block = TlsBlock::new();
+        let tls = block.tls();
+        let mut clone3 = Clone3::default();
+        clone3.flag_settls(tls);
+        let mut child = match unsafe { clone3.spawn() }.unwrap() {
+            None => unsafe {
+                let tp: u64;
+                #[cfg(target_arch = "x86_64")]
+                asm!("mov {}, fs:0", out(reg) tp);
+                #[cfg(target_arch = "aarch64")]
+                asm!("mrs {}, tpidr_el0", out(reg) tp);
+                // libc functions may use the thread locals of libc.
+                c::syscall(SYS_exit_group, (tp == tls) as c_long);
+                unreachable!()
+            },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+    }
+}
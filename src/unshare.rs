@@ -0,0 +1,62 @@
+use crate::sys::{
+    c::{self, EINVAL},
+    Errno,
+};
+use crate::Flags;
+use std::os::raw::c_int;
+
+/// The flags accepted by [`unshare`].
+pub const UNSHARE_FLAGS: Flags = Flags::from_bits_truncate(
+    Flags::FILES.bits()
+        | Flags::FS.bits()
+        | Flags::NEWCGROUP.bits()
+        | Flags::NEWIPC.bits()
+        | Flags::NEWNET.bits()
+        | Flags::NEWNS.bits()
+        | Flags::NEWPID.bits()
+        | Flags::NEWTIME.bits()
+        | Flags::NEWUSER.bits()
+        | Flags::NEWUTS.bits()
+        | Flags::SYSVSEM.bits(),
+);
+
+/// Performs the unshare system call which gives the calling thread its own copy of the state
+/// selected by `flags` like a clone3 call with the same flags would for a child.
+///
+/// `FILES`, `FS` and `SYSVSEM` stop sharing the respective state with other processes. The `NEW*`
+/// flags move the caller into new namespaces except for `NEWPID` and `NEWTIME` which only apply
+/// to the children created afterwards. `NEWUSER` implies `FS` and fails in multi threaded
+/// processes.
+///
+/// Returns `EINVAL` without performing the system call if `flags` contains flags other than
+/// [`UNSHARE_FLAGS`].
+pub fn unshare(flags: Flags) -> Result<(), Errno> {
+    if !UNSHARE_FLAGS.contains(flags) {
+        return Err(Errno(EINVAL));
+    }
+    if unsafe { c::unshare(flags.bits() as c_int) } == -1 {
+        return Err(Errno::default());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChildStatus, Clone3};
+
+    #[test]
+    fn unshare_uts_namespace() {
+        assert_eq!(unshare(Flags::PIDFD), Err(Errno(EINVAL)));
+        let mut child = match unsafe { Clone3::default().spawn() }.unwrap() {
+            None => unsafe {
+                let unshared = unshare(Flags::NEWUSER | Flags::NEWUTS).is_ok();
+                let name = b"clone3-unshared";
+                let renamed = c::sethostname(name.as_ptr() as *const _, name.len()) == 0;
+                c::_exit((unshared && renamed) as c_int)
+            },
+            Some(child) => child,
+        };
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(1)));
+    }
+}
@@ -0,0 +1,163 @@
+//! Stand-ins for targets other than Linux and Android.
+
+use crate::Flags;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io,
+    marker::PhantomData,
+};
+
+/// The error of all calls on targets other than Linux and Android.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Unsupported;
+
+impl Display for Unsupported {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "clone3 is only available on Linux and Android")
+    }
+}
+
+impl Error for Unsupported {}
+
+impl From<Unsupported> for io::Error {
+    fn from(err: Unsupported) -> Self {
+        Self::new(io::ErrorKind::Unsupported, err)
+    }
+}
+
+/// A child created by [`Clone3::spawn`]. Never exists on targets other than Linux and Android.
+#[derive(Debug)]
+pub enum Child {}
+
+impl Child {
+    pub fn pid(&self) -> i32 {
+        match *self {}
+    }
+}
+
+/// The builder of the Linux version of this crate with the flags and the system call. The system
+/// call fails with [`Unsupported`].
+#[derive(Debug, Default)]
+pub struct Clone3<'a> {
+    flags: Flags,
+    exit_signal: u64,
+    // Keeps the lifetime of the Linux version which borrows the arguments.
+    _arguments: PhantomData<&'a mut ()>,
+}
+
+impl Clone3<'_> {
+    pub fn fork_like() -> Self {
+        Self {
+            // SIGCHLD on Linux.
+            exit_signal: 17,
+            ..Self::default()
+        }
+    }
+
+    pub fn flag_clear_sighand(&mut self) -> &mut Self {
+        self.flags.set(Flags::CLEAR_SIGHAND, true);
+        self
+    }
+
+    pub fn flag_files(&mut self) -> &mut Self {
+        self.flags.set(Flags::FILES, true);
+        self
+    }
+
+    pub fn flag_fs(&mut self) -> &mut Self {
+        self.flags.set(Flags::FS, true);
+        self
+    }
+
+    pub fn flag_io(&mut self) -> &mut Self {
+        self.flags.set(Flags::IO, true);
+        self
+    }
+
+    pub fn flag_newcgroup(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWCGROUP, true);
+        self
+    }
+
+    pub fn flag_newipc(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWIPC, true);
+        self
+    }
+
+    pub fn flag_newnet(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWNET, true);
+        self
+    }
+
+    pub fn flag_newns(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWNS, true);
+        self
+    }
+
+    pub fn flag_newpid(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWPID, true);
+        self
+    }
+
+    pub fn flag_newtime(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWTIME, true);
+        self
+    }
+
+    pub fn flag_newuser(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWUSER, true);
+        self
+    }
+
+    pub fn flag_newuts(&mut self) -> &mut Self {
+        self.flags.set(Flags::NEWUTS, true);
+        self
+    }
+
+    pub fn flag_parent(&mut self) -> &mut Self {
+        self.flags.set(Flags::PARENT, true);
+        self
+    }
+
+    pub fn flag_ptrace(&mut self) -> &mut Self {
+        self.flags.set(Flags::PTRACE, true);
+        self
+    }
+
+    pub fn flag_sysvsem(&mut self) -> &mut Self {
+        self.flags.set(Flags::SYSVSEM, true);
+        self
+    }
+
+    pub fn flag_untraced(&mut self) -> &mut Self {
+        self.flags.set(Flags::UNTRACED, true);
+        self
+    }
+
+    pub fn flag_vfork(&mut self) -> &mut Self {
+        self.flags.set(Flags::VFORK, true);
+        self
+    }
+
+    pub fn exit_signal(&mut self, exit_signal: u64) -> &mut Self {
+        self.exit_signal = exit_signal;
+        self
+    }
+
+    pub unsafe fn call(&mut self) -> Result<i32, Unsupported> {
+        Err(Unsupported)
+    }
+
+    pub unsafe fn try_call(&mut self) -> Result<i32, Unsupported> {
+        Err(Unsupported)
+    }
+
+    pub unsafe fn call_io(&mut self) -> io::Result<i32> {
+        Err(Unsupported.into())
+    }
+
+    pub unsafe fn spawn(&mut self) -> Result<Option<Child>, Unsupported> {
+        Err(Unsupported)
+    }
+}
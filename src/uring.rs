@@ -0,0 +1,399 @@
+use crate::sys::{
+    c::{
+        self, id_t, idtype_t, pid_t, syscall, SYS_io_uring_enter, SYS_io_uring_setup, __WALL,
+        EINTR, MAP_FAILED, MAP_POPULATE, MAP_SHARED, PROT_READ, PROT_WRITE, P_PID, P_PIDFD,
+        WEXITED,
+    },
+    Errno,
+};
+use crate::{ChildStatus, SigInfo};
+use std::{
+    collections::HashMap,
+    mem,
+    os::{
+        raw::{c_int, c_uint, c_void},
+        unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    },
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+const IORING_OP_WAITID: u8 = 50;
+const IORING_ENTER_GETEVENTS: c_uint = 1;
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+
+/// Waits for many children at once through io_uring.
+///
+/// Each wait is an `IORING_OP_WAITID` request which requires Linux 6.7. Waiting by pid needs
+/// neither a thread nor an fd per child which makes this suitable for thousands of concurrent
+/// children.
+///
+/// Submit waits with [`submit_wait_pid`](Self::submit_wait_pid) or
+/// [`submit_wait_pidfd`](Self::submit_wait_pidfd) and collect them with
+/// [`next_completion`](Self::next_completion).
+#[derive(Debug)]
+pub struct ChildWaiter {
+    fd: OwnedFd,
+    sq: Ring,
+    cq: Ring,
+    sqes: Mapping,
+    sq_entries: u32,
+    // The kernel writes to these until the request completes so they are boxed.
+    pending: HashMap<u64, Box<SigInfo>>,
+}
+
+/// A finished wait returned by [`ChildWaiter::next_completion`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Completion {
+    /// The value passed when submitting the wait.
+    pub user_data: u64,
+    /// How the child exited or why waiting for it failed.
+    pub status: Result<ChildStatus, Errno>,
+}
+
+#[derive(Debug)]
+struct Mapping {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct Ring {
+    // Unmapped on drop.
+    _mapping: Mapping,
+    head: *const AtomicU32,
+    tail: *const AtomicU32,
+    mask: u32,
+    // The index array for the submission queue and the completion entries for the completion
+    // queue.
+    entries: *mut c_void,
+}
+
+/// `struct io_uring_params` as defined in `/usr/include/linux/io_uring.h`.
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: RingOffsets,
+    cq_off: RingOffsets,
+}
+
+/// `struct io_sqring_offsets` and `struct io_cqring_offsets` which share their layout.
+#[repr(C)]
+#[derive(Default)]
+struct RingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    // `flags` for the submission and `overflow` for the completion queue.
+    flags_or_overflow: u32,
+    // `dropped` for the submission and `cqes` for the completion queue.
+    dropped_or_cqes: u32,
+    // `array` for the submission and `flags` for the completion queue.
+    array_or_flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_uring_sqe` limited to the fields used by `IORING_OP_WAITID`.
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    addr2: u64,
+    addr: u64,
+    len: u32,
+    waitid_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    file_index: u32,
+    addr3: u64,
+    pad2: u64,
+}
+
+/// `struct io_uring_cqe`.
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+impl ChildWaiter {
+    /// Creates an io_uring that can submit `entries` waits at once.
+    ///
+    /// More waits than `entries` can be pending because every submission is passed to the kernel
+    /// immediately.
+    pub fn new(entries: u32) -> Result<Self, Errno> {
+        let mut params = Params::default();
+        let fd = unsafe { syscall(SYS_io_uring_setup, entries, &mut params as *mut Params) };
+        if fd == -1 {
+            return Err(Errno::default());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as c_int) };
+        let sq_len = params.sq_off.array_or_flags as usize
+            + params.sq_entries as usize * mem::size_of::<u32>();
+        let cq_len = params.cq_off.dropped_or_cqes as usize
+            + params.cq_entries as usize * mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * mem::size_of::<Sqe>();
+        let sq = Ring::new(
+            Mapping::new(&fd, sq_len, IORING_OFF_SQ_RING)?,
+            &params.sq_off,
+            params.sq_off.array_or_flags,
+        );
+        let cq = Ring::new(
+            Mapping::new(&fd, cq_len, IORING_OFF_CQ_RING)?,
+            &params.cq_off,
+            params.cq_off.dropped_or_cqes,
+        );
+        let sqes = Mapping::new(&fd, sqes_len, IORING_OFF_SQES)?;
+        Ok(Self {
+            fd,
+            sq,
+            cq,
+            sqes,
+            sq_entries: params.sq_entries,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Submits a wait for the child `pid` to exit. `user_data` identifies the completion.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a wait with the same `user_data` is pending.
+    pub fn submit_wait_pid(&mut self, pid: pid_t, user_data: u64) -> Result<(), Errno> {
+        self.submit(P_PID, pid as id_t, user_data)
+    }
+
+    /// Submits a wait for the child referred to by `pidfd` to exit. `user_data` identifies the
+    /// completion.
+    ///
+    /// The pidfd must stay open until the completion has been returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a wait with the same `user_data` is pending.
+    pub fn submit_wait_pidfd(
+        &mut self,
+        pidfd: BorrowedFd<'_>,
+        user_data: u64,
+    ) -> Result<(), Errno> {
+        self.submit(P_PIDFD, pidfd.as_raw_fd() as id_t, user_data)
+    }
+
+    /// The number of submitted waits that have not been returned by
+    /// [`next_completion`](Self::next_completion).
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Blocks until a submitted wait completes and returns its `user_data` and result.
+    ///
+    /// Returns `None` if no waits are pending.
+    pub fn next_completion(&mut self) -> Option<Result<Completion, Errno>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        loop {
+            if let Some(completion) = self.pop_completion() {
+                return Some(Ok(completion));
+            }
+            if let Err(errno) = self.enter(0, 1) {
+                if errno.0 != EINTR {
+                    return Some(Err(errno));
+                }
+            }
+        }
+    }
+
+    fn submit(&mut self, idtype: idtype_t, id: id_t, user_data: u64) -> Result<(), Errno> {
+        assert!(
+            !self.pending.contains_key(&user_data),
+            "wait with user_data {} is already pending",
+            user_data
+        );
+        let mut siginfo = Box::new(SigInfo::default());
+        let tail = unsafe { &*self.sq.tail }.load(Ordering::Relaxed);
+        let head = unsafe { &*self.sq.head }.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) >= self.sq_entries {
+            // Earlier submissions are always consumed by `enter` so this only happens if the
+            // kernel failed to consume them.
+            return Err(Errno(c::EBUSY));
+        }
+        let index = tail & self.sq.mask;
+        let sqe = Sqe {
+            opcode: IORING_OP_WAITID,
+            fd: id as i32,
+            addr2: siginfo.as_mut_ptr() as u64,
+            len: idtype,
+            user_data,
+            file_index: (WEXITED | __WALL) as u32,
+            ..Default::default()
+        };
+        unsafe {
+            ptr::write((self.sqes.ptr as *mut Sqe).add(index as usize), sqe);
+            *(self.sq.entries as *mut u32).add(index as usize) = index;
+            (*self.sq.tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.pending.insert(user_data, siginfo);
+        loop {
+            match self.enter(1, 0) {
+                Err(Errno(EINTR)) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    fn pop_completion(&mut self) -> Option<Completion> {
+        let head = unsafe { &*self.cq.head }.load(Ordering::Relaxed);
+        let tail = unsafe { &*self.cq.tail }.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let cqe = unsafe {
+            ptr::read((self.cq.entries as *const Cqe).add((head & self.cq.mask) as usize))
+        };
+        unsafe { &*self.cq.head }.store(head.wrapping_add(1), Ordering::Release);
+        let siginfo = self
+            .pending
+            .remove(&cqe.user_data)
+            .expect("completion for unknown user_data");
+        let result = if cqe.res < 0 {
+            Err(Errno(-cqe.res))
+        } else {
+            Ok(siginfo
+                .child_status()
+                .unwrap_or_else(|| unreachable!("unexpected {:?} from waitid", siginfo)))
+        };
+        Some(Completion {
+            user_data: cqe.user_data,
+            status: result,
+        })
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32) -> Result<(), Errno> {
+        let flags = if min_complete > 0 {
+            IORING_ENTER_GETEVENTS
+        } else {
+            0
+        };
+        let return_value = unsafe {
+            syscall(
+                SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                to_submit,
+                min_complete,
+                flags,
+                ptr::null::<c_void>(),
+                0usize,
+            )
+        };
+        if return_value == -1 {
+            return Err(Errno::default());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ChildWaiter {
+    fn drop(&mut self) {
+        // Closing the ring cancels the pending requests asynchronously so the kernel could still
+        // write to their siginfo. Leak them instead of risking a use after free.
+        for (_, siginfo) in self.pending.drain() {
+            mem::forget(siginfo);
+        }
+    }
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize, offset: i64) -> Result<Self, Errno> {
+        let ptr = unsafe {
+            c::mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(Errno::default());
+        }
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { c::munmap(self.ptr, self.len) };
+    }
+}
+
+impl Ring {
+    fn new(mapping: Mapping, offsets: &RingOffsets, entries: u32) -> Self {
+        let base = mapping.ptr as *mut u8;
+        unsafe {
+            Self {
+                head: base.add(offsets.head as usize) as *const AtomicU32,
+                tail: base.add(offsets.tail as usize) as *const AtomicU32,
+                mask: *(base.add(offsets.ring_mask as usize) as *const u32),
+                entries: base.add(entries as usize) as *mut c_void,
+                _mapping: mapping,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Clone3;
+
+    #[test]
+    fn wait_for_many_children() {
+        let mut waiter = match ChildWaiter::new(4) {
+            Ok(waiter) => waiter,
+            // io_uring is commonly disabled in containers.
+            Err(Errno(c::ENOSYS)) | Err(Errno(c::EPERM)) => return,
+            Err(errno) => panic!("{:?}", errno),
+        };
+        let mut pids = HashMap::new();
+        for code in 0..8 {
+            let pid = match unsafe { Clone3::default().call() }.unwrap() {
+                0 => unsafe { c::_exit(code) },
+                pid => pid,
+            };
+            match waiter.submit_wait_pid(pid, code as u64) {
+                // IORING_OP_WAITID requires Linux 6.7.
+                Err(Errno(c::EINVAL)) => return,
+                result => result.unwrap(),
+            }
+            pids.insert(code as u64, pid);
+        }
+        while let Some(completion) = waiter.next_completion() {
+            let Completion { user_data, status } = completion.unwrap();
+            match status {
+                Err(Errno(c::EINVAL)) => return,
+                status => assert_eq!(status, Ok(ChildStatus::Exited(user_data as c_int))),
+            }
+            pids.remove(&user_data).unwrap();
+        }
+        assert!(pids.is_empty());
+    }
+}
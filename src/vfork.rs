@@ -0,0 +1,281 @@
+use crate::sys::{
+    c::{self, sigaction, sigset_t, E2BIG, EAGAIN, ENOSYS, SIG_DFL, SIG_IGN, SIG_SETMASK},
+    Errno,
+};
+use crate::{
+    raw::pid_from_raw_result,
+    retry::retry,
+    setup::Step,
+    sys::SYS_clone3,
+    wrapper::{
+        classify_error, find_incompatible_flags, find_invalid_exit_signal, find_smaller_size,
+    },
+    Child, Clone3, Clone3Error, CloneArgs, Flags, IncompatibleFlags, TidSpec,
+};
+use std::{
+    arch::asm,
+    ffi::CStr,
+    mem,
+    os::raw::{c_char, c_int, c_long, c_void},
+    ptr,
+};
+
+// Only execve and its error path run on this stack.
+const STACK_SIZE: usize = 64 * 1024;
+
+/// Arguments for the child shared with the parent through `VM`.
+#[repr(C)]
+struct Exec {
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+    // The signal mask to restore before execve.
+    mask: sigset_t,
+    max_signal: c_int,
+    errno: c_int,
+}
+
+impl<'a> Clone3<'a> {
+    /// Creates a child with `VM` and `VFORK` that executes `path`.
+    ///
+    /// This is the fastest way to spawn a program because the address space of the parent is not
+    /// copied. The child runs on a dedicated stack and only calls execve or exits. The caller is
+    /// suspended until then. Other flags and fields are applied as usual except for the stack
+    /// which is replaced. Child setup like [`stdin`](Self::stdin) cannot run on the dedicated
+    /// stack and results in
+    /// [`Clone3Error::SetupConflictsWithFlag`](crate::Clone3Error::SetupConflictsWithFlag).
+    ///
+    /// If execve fails the child writes the error to the shared memory and exits. The child is
+    /// reaped and the error returned as
+    /// [`Clone3Error::ChildSetup`](crate::Clone3Error::ChildSetup).
+    ///
+    /// `SIGHAND` is rejected with
+    /// [`Clone3Error::IncompatibleFlags`](crate::Clone3Error::IncompatibleFlags) because the child
+    /// has to reset the signal handlers it would share with the parent. A
+    /// smaller `clone_args` size is retried on `E2BIG` like in [`try_call`](Self::try_call) but
+    /// [`legacy_fallback`](Self::legacy_fallback) is ignored because starting the child on the
+    /// dedicated stack requires clone3.
+    ///
+    /// Only available on x86_64 and aarch64 because the child has to be started on the new stack
+    /// in assembly.
+    pub unsafe fn vfork_exec(
+        &mut self,
+        path: &CStr,
+        argv: &[&CStr],
+        envp: &[&CStr],
+    ) -> Result<Child, Clone3Error> {
+        let flags = self.flags | Flags::VM | Flags::VFORK;
+        if let Some(reason) = find_incompatible_flags(flags) {
+            return Err(Clone3Error::IncompatibleFlags { flags, reason });
+        }
+        if flags.contains(Flags::SIGHAND) {
+            return Err(Clone3Error::IncompatibleFlags {
+                flags,
+                reason: IncompatibleFlags::MutuallyExclusive {
+                    flag: Flags::SIGHAND,
+                    conflicting: Flags::VFORK,
+                },
+            });
+        }
+        if let Some(reason) = find_invalid_exit_signal(flags, self.exit_signal) {
+            return Err(Clone3Error::InvalidExitSignal {
+                exit_signal: self.exit_signal,
+                reason,
+            });
+        }
+        if let Some(set_tid) = self.set_tid {
+            set_tid.validate(TidSpec::max_levels(flags))?;
+        }
+        if let Some(setup) = self.setup.first_step() {
+            return Err(Clone3Error::SetupConflictsWithFlag {
+                setup: setup.name(),
+                flag: Flags::VM,
+            });
+        }
+        let argv = null_terminated(argv);
+        let envp = null_terminated(envp);
+        let mut exec = Exec {
+            path: path.as_ptr(),
+            argv: argv.as_ptr(),
+            envp: envp.as_ptr(),
+            mask: mem::zeroed(),
+            max_signal: c::SIGRTMAX(),
+            errno: 0,
+        };
+        // u128 keeps the top of the stack 16 byte aligned as required by both architectures.
+        let mut stack = vec![0u128; STACK_SIZE / 16];
+        let mut cl_args = self.as_clone_args();
+        cl_args.flags = flags.bits();
+        cl_args.stack = stack.as_mut_ptr() as u64;
+        cl_args.stack_size = STACK_SIZE as u64;
+        let size = self.effective_clone_args_size();
+        let required = cl_args.min_size();
+        if size < required {
+            return Err(Clone3Error::CloneArgsSizeTooSmall { size, required });
+        }
+        if let Some(reason) = cl_args.find_invalid() {
+            return Err(Clone3Error::InvalidCloneArgs(reason));
+        }
+        // Signal handlers of the parent must not run in the child because it shares the memory
+        // but not the stack. Block all signals until the child has reset the handlers.
+        let mut all: sigset_t = mem::zeroed();
+        c::sigfillset(&mut all);
+        c::pthread_sigmask(SIG_SETMASK, &all, &mut exec.mask);
+        let exec_ptr = &mut exec as *mut Exec as *mut c_void;
+        let mut return_value = retry(
+            self.eagain_retry,
+            || clone3_with_entry(&cl_args, size, child_main, exec_ptr),
+            |&return_value| return_value == -(EAGAIN as c_long),
+        );
+        if return_value == -(E2BIG as c_long) && self.clone_args_size.is_none() {
+            if let Some(smaller) = crate::max_clone_args_size()
+                .ok()
+                .and_then(|supported| find_smaller_size(&cl_args, size, supported))
+            {
+                return_value = retry(
+                    self.eagain_retry,
+                    || clone3_with_entry(&cl_args, smaller, child_main, exec_ptr),
+                    |&return_value| return_value == -(EAGAIN as c_long),
+                );
+            }
+        }
+        c::pthread_sigmask(SIG_SETMASK, &exec.mask, ptr::null_mut());
+        let result = pid_from_raw_result(return_value);
+        self.store_owned_pidfd(result.is_ok());
+        let pid = result.map_err(|errno| classify_error(&cl_args, errno))?;
+        let pidfd = self.pidfd_owned.as_mut().and_then(|pidfd| pidfd.take());
+        let mut child = Child::new(pid, pidfd);
+        // Written by the child before it exited.
+        let errno = ptr::read_volatile(&exec.errno);
+        if errno != 0 {
+            let _ = child.wait();
+            return Err(Clone3Error::ChildSetup {
+                step: Step::Execve.name(),
+                errno: Errno(errno),
+            });
+        }
+        Ok(child)
+    }
+}
+
+fn null_terminated(strings: &[&CStr]) -> Vec<*const c_char> {
+    strings
+        .iter()
+        .map(|string| string.as_ptr())
+        .chain(Some(ptr::null()))
+        .collect()
+}
+
+unsafe extern "C" fn child_main(exec: *mut c_void) -> ! {
+    let exec = exec as *mut Exec;
+    // The handlers are not shared with the parent because SIGHAND is rejected.
+    for signal in 1..=(*exec).max_signal {
+        let mut action: sigaction = mem::zeroed();
+        if c::sigaction(signal, ptr::null(), &mut action) == 0
+            && action.sa_sigaction != SIG_DFL
+            && action.sa_sigaction != SIG_IGN
+        {
+            action.sa_sigaction = SIG_DFL;
+            c::sigaction(signal, &action, ptr::null_mut());
+        }
+    }
+    c::sigprocmask(SIG_SETMASK, &(*exec).mask, ptr::null_mut());
+    c::execve((*exec).path, (*exec).argv, (*exec).envp);
+    ptr::write_volatile(&mut (*exec).errno, Errno::default().0);
+    c::_exit(127)
+}
+
+/// Performs the clone3 system call and calls `entry(arg)` in the child on the stack from
+/// `cl_args`.
+///
+/// The child cannot return from the system call like with [`clone3_system_call`] because its stack
+/// does not contain the frames of the caller. Returns the raw result of the system call which is
+/// the negated errno on failure.
+///
+/// [`clone3_system_call`]: crate::clone3_system_call
+pub(crate) unsafe fn clone3_with_entry(
+    cl_args: &CloneArgs,
+    size: usize,
+    entry: unsafe extern "C" fn(*mut c_void) -> !,
+    arg: *mut c_void,
+) -> c_long {
+    if !crate::sys::clone3_allowed() {
+        return -(ENOSYS as c_long);
+    }
+    let return_value: c_long;
+    #[cfg(target_arch = "x86_64")]
+    asm!(
+        "syscall",
+        "test rax, rax",
+        "jnz 2f",
+        "mov rdi, {arg}",
+        "call {entry}",
+        "ud2",
+        "2:",
+        arg = in(reg) arg,
+        entry = in(reg) entry,
+        inlateout("rax") SYS_clone3 => return_value,
+        in("rdi") cl_args as *const CloneArgs,
+        in("rsi") size,
+        // Not late so that the inputs used by the child are not placed in them.
+        out("rcx") _,
+        out("r11") _,
+    );
+    #[cfg(target_arch = "aarch64")]
+    asm!(
+        "svc 0",
+        "cbnz x0, 2f",
+        "mov x0, {arg}",
+        "blr {entry}",
+        "brk 1",
+        "2:",
+        arg = in(reg) arg,
+        entry = in(reg) entry,
+        inlateout("x0") cl_args as *const CloneArgs => return_value,
+        in("x1") size,
+        in("x8") SYS_clone3,
+    );
+    return_value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::ENOENT;
+    use crate::ChildStatus;
+
+    #[test]
+    fn exec_and_exit_code() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
+        clone3.flag_pidfd(&mut pidfd);
+        let path = c"/bin/sh";
+        let argv = [path, c"-c", c"exit 5"];
+        let mut child = unsafe { clone3.vfork_exec(path, &argv, &[]) }.unwrap();
+        assert!(child.pidfd().is_some());
+        assert_eq!(child.wait(), Ok(ChildStatus::Exited(5)));
+    }
+
+    #[test]
+    fn exec_failure() {
+        let path = c"/clone3-does-not-exist";
+        match unsafe { Clone3::default().vfork_exec(path, &[path], &[]) } {
+            Err(Clone3Error::ChildSetup {
+                step: "execve",
+                errno: Errno(ENOENT),
+            }) => (),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn sighand_rejected() {
+        let path = c"/bin/true";
+        let mut clone3 = Clone3::default();
+        clone3.flag_sighand();
+        match unsafe { clone3.vfork_exec(path, &[path], &[]) } {
+            Err(Clone3Error::IncompatibleFlags { .. }) => (),
+            result => panic!("{:?}", result),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::sys::{
+    c::{
+        self, id_t, idtype_t, pid_t, rusage, syscall, timeval, SYS_waitid, P_PID, P_PIDFD,
+        WCONTINUED, WEXITED, WNOHANG, WNOWAIT, WSTOPPED,
+    },
+    Errno,
+};
+use crate::SigInfo;
+use std::{
+    mem::MaybeUninit,
+    os::{
+        raw::c_int,
+        unix::io::{AsRawFd, BorrowedFd},
+    },
+    ptr,
+    time::Duration,
+};
+
+bitflags::bitflags! {
+    /// Options for [`wait_pid`](crate::wait_pid) and [`wait_pidfd`](crate::wait_pidfd).
+    ///
+    /// Exited children are always reported.
+    #[derive(Default)]
+    pub struct WaitOptions: c_int {
+        /// Return immediately if no child has changed state.
+        const NOHANG = WNOHANG;
+        /// Also report children stopped by a signal.
+        const STOPPED = WSTOPPED;
+        /// Also report stopped children that were resumed by `SIGCONT`.
+        const CONTINUED = WCONTINUED;
+        /// Leave the child in a waitable state so that it can be waited for again.
+        const NOWAIT = WNOWAIT;
+    }
+}
+
+/// How a child changed state as reported by `waitid`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChildStatus {
+    /// The child exited with the contained exit code.
+    Exited(c_int),
+    /// The child was killed by the contained signal. The boolean is true if it dumped core.
+    Signaled(c_int, bool),
+    /// The child was stopped by the contained signal.
+    Stopped(c_int),
+    /// The child was resumed by `SIGCONT`.
+    Continued,
+}
+
+impl ChildStatus {
+    /// Decodes a status in the format returned by `waitpid`.
+    pub(crate) fn from_wait_status(status: c_int) -> Self {
+        let signal = status & 0x7f;
+        if signal == 0 {
+            Self::Exited((status >> 8) & 0xff)
+        } else {
+            Self::Signaled(signal, status & 0x80 != 0)
+        }
+    }
+}
+
+/// Resources used by a child as reported by the kernel in `struct rusage`.
+///
+/// Only the fields maintained by Linux are included.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ResourceUsage {
+    /// Time spent executing in user mode.
+    pub user_time: Duration,
+    /// Time spent executing in kernel mode.
+    pub system_time: Duration,
+    /// Maximum resident set size in kilobytes.
+    pub max_rss: u64,
+    /// Page faults serviced without any I/O activity.
+    pub minor_faults: u64,
+    /// Page faults serviced that required I/O activity.
+    pub major_faults: u64,
+    /// Number of times the file system had to perform input.
+    pub block_input_operations: u64,
+    /// Number of times the file system had to perform output.
+    pub block_output_operations: u64,
+    /// Context switches because the process waited for a resource.
+    pub voluntary_context_switches: u64,
+    /// Context switches because the process was preempted.
+    pub involuntary_context_switches: u64,
+}
+
+impl From<&rusage> for ResourceUsage {
+    fn from(usage: &rusage) -> Self {
+        fn duration(time: &timeval) -> Duration {
+            Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+        }
+        Self {
+            user_time: duration(&usage.ru_utime),
+            system_time: duration(&usage.ru_stime),
+            max_rss: usage.ru_maxrss as u64,
+            minor_faults: usage.ru_minflt as u64,
+            major_faults: usage.ru_majflt as u64,
+            block_input_operations: usage.ru_inblock as u64,
+            block_output_operations: usage.ru_oublock as u64,
+            voluntary_context_switches: usage.ru_nvcsw as u64,
+            involuntary_context_switches: usage.ru_nivcsw as u64,
+        }
+    }
+}
+
+/// Waits for the child `pid` to change state.
+///
+/// Returns `None` if [`NOHANG`](WaitOptions::NOHANG) is set and the child has not changed state.
+/// Children are found regardless of their exit signal so there is no need for `__WCLONE`.
+pub fn wait_pid(pid: pid_t, options: WaitOptions) -> Result<Option<ChildStatus>, Errno> {
+    waitid(P_PID, pid as id_t, options)
+}
+
+/// Waits for the child referred to by `pidfd` to change state.
+///
+/// Like [`wait_pid`](crate::wait_pid) but cannot be confused by pid reuse.
+pub fn wait_pidfd(
+    pidfd: BorrowedFd<'_>,
+    options: WaitOptions,
+) -> Result<Option<ChildStatus>, Errno> {
+    waitid(P_PIDFD, pidfd.as_raw_fd() as id_t, options)
+}
+
+/// Like [`wait_pid`](crate::wait_pid) but also returns the resources used by the child.
+///
+/// The usage is that of the child and its waited for descendants. Unlike
+/// `getrusage(RUSAGE_CHILDREN)` it is not mixed up with other children.
+pub fn wait_pid_with_rusage(
+    pid: pid_t,
+    options: WaitOptions,
+) -> Result<Option<(ChildStatus, ResourceUsage)>, Errno> {
+    waitid_with_rusage(P_PID, pid as id_t, options)
+}
+
+/// Like [`wait_pidfd`](crate::wait_pidfd) but also returns the resources used by the child.
+///
+/// See [`wait_pid_with_rusage`](crate::wait_pid_with_rusage).
+pub fn wait_pidfd_with_rusage(
+    pidfd: BorrowedFd<'_>,
+    options: WaitOptions,
+) -> Result<Option<(ChildStatus, ResourceUsage)>, Errno> {
+    waitid_with_rusage(P_PIDFD, pidfd.as_raw_fd() as id_t, options)
+}
+
+fn waitid_with_rusage(
+    idtype: idtype_t,
+    id: id_t,
+    options: WaitOptions,
+) -> Result<Option<(ChildStatus, ResourceUsage)>, Errno> {
+    let mut usage = MaybeUninit::<rusage>::zeroed();
+    let status = unsafe { waitid_raw(idtype, id, options, usage.as_mut_ptr()) }?;
+    let usage = unsafe { usage.assume_init() };
+    Ok(status.map(|status| (status, ResourceUsage::from(&usage))))
+}
+
+pub(crate) fn waitid(
+    idtype: idtype_t,
+    id: id_t,
+    options: WaitOptions,
+) -> Result<Option<ChildStatus>, Errno> {
+    unsafe { waitid_raw(idtype, id, options, ptr::null_mut()) }
+}
+
+/// Calls the waitid system call with `__WALL` and `WEXITED` added to `options`.
+///
+/// Unlike the libc function the system call can also fill in `usage` if it is not null.
+unsafe fn waitid_raw(
+    idtype: idtype_t,
+    id: id_t,
+    options: WaitOptions,
+    usage: *mut rusage,
+) -> Result<Option<ChildStatus>, Errno> {
+    // POSIX does not guarantee that waitid clears si_pid with WNOHANG if no child has exited.
+    let mut siginfo = SigInfo::default();
+    let return_value = syscall(
+        SYS_waitid,
+        idtype,
+        id,
+        siginfo.as_mut_ptr(),
+        options.bits() | WEXITED | c::__WALL,
+        usage,
+    );
+    if return_value == -1 {
+        return Err(Errno::default());
+    }
+    if siginfo.si_pid() == 0 {
+        return Ok(None);
+    }
+    match siginfo.child_status() {
+        Some(status) => Ok(Some(status)),
+        None => unreachable!("unexpected {:?} from waitid", siginfo),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::c::{SIGCONT, SIGKILL, SIGSTOP};
+    use crate::Clone3;
+
+    #[test]
+    fn stop_and_continue() {
+        let pid = match unsafe { Clone3::default().call() }.unwrap() {
+            0 => loop {
+                unsafe { c::pause() };
+            },
+            pid => pid,
+        };
+        assert_eq!(wait_pid(pid, WaitOptions::NOHANG), Ok(None));
+        unsafe { c::kill(pid, SIGSTOP) };
+        assert_eq!(
+            wait_pid(pid, WaitOptions::STOPPED),
+            Ok(Some(ChildStatus::Stopped(SIGSTOP)))
+        );
+        unsafe { c::kill(pid, SIGCONT) };
+        assert_eq!(
+            wait_pid(pid, WaitOptions::CONTINUED),
+            Ok(Some(ChildStatus::Continued))
+        );
+        unsafe { c::kill(pid, SIGKILL) };
+        assert_eq!(
+            wait_pid(pid, WaitOptions::empty()),
+            Ok(Some(ChildStatus::Signaled(SIGKILL, false)))
+        );
+    }
+
+    #[test]
+    fn rusage() {
+        let pid = match unsafe { Clone3::default().call() }.unwrap() {
+            0 => unsafe { c::_exit(0) },
+            pid => pid,
+        };
+        let (status, usage) = wait_pid_with_rusage(pid, WaitOptions::empty())
+            .unwrap()
+            .unwrap();
+        assert_eq!(status, ChildStatus::Exited(0));
+        assert!(usage.max_rss > 0);
+    }
+}
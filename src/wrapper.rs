@@ -1,47 +1,121 @@
-use crate::{CloneArgs, Flags};
-#[cfg(feature = "linux_5-7")]
-use std::os::unix::io::AsRawFd;
+use crate::sys::{
+    c::{
+        self, gid_t, mode_t, pid_t, rlim_t, rlimit, sock_filter, uid_t, E2BIG, EAGAIN, EINVAL,
+        ENOSYS, EPERM, SIGCHLD,
+    },
+    Errno,
+};
+use crate::{
+    init,
+    mount::Root,
+    raw::{clone3_result, pid_from_libc_result},
+    retry::retry,
+    setup::{to_cstring, ErrorPipe, Hook, Lsm, Prepared, SchedAttr, SetupRef, Step},
+    sys::sched_attr,
+    CgroupFd, Child, ChildBarrier, ClearTidHandle, Clone3Error, CloneArgs, CpuSet, Flags, IdMap,
+    IncompatibleFlags, IoPriorityClass, MountPlan, Namespace, ParentBarrier, RetryPolicy,
+    ScopedCgroup, Stdio, TidSpec, WaitOptions, MIN_STACK_SIZE, STACK_ALIGN,
+};
 use std::{
-    convert::TryInto,
-    os::{raw::c_long, unix::io::RawFd},
+    ffi::{OsStr, OsString},
+    fs::File,
+    io,
+    mem::{self, MaybeUninit},
+    os::{
+        raw::{c_int, c_long, c_ulong},
+        unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    },
+    path::Path,
+    ptr::NonNull,
 };
-use uapi::{c::pid_t, Errno};
 
 /// High level wrapper around the clone3 system call.
 ///
-/// Construct it with `Clone3::default()` which sets no flags and no exit signal. Use builder
-/// methods to customize the underlying [`CloneArgs`](crate::CloneArgs). Perform the system call
-/// with [`call`](Self::call).
+/// Construct it with `Clone3::default()` which sets no flags and no exit signal or with a preset
+/// like [`fork_like`](Self::fork_like). Use builder methods to customize the underlying
+/// [`CloneArgs`](crate::CloneArgs). Perform the system call with [`call`](Self::call).
 #[derive(Default)]
 pub struct Clone3<'a> {
-    flags: Flags,
-    pidfd: Option<&'a mut RawFd>,
-    child_tid: Option<&'a mut pid_t>,
-    parent_tid: Option<&'a mut pid_t>,
-    exit_signal: u64,
-    stack: Option<&'a mut [u8]>,
-    tls: Option<u64>,
-    #[cfg(feature = "linux_5-5")]
-    set_tid: Option<&'a [pid_t]>,
-    #[cfg(feature = "linux_5-7")]
-    cgroup: Option<&'a dyn AsRawFd>,
+    pub(crate) flags: Flags,
+    pub(crate) pidfd: Option<&'a mut RawFd>,
+    pub(crate) pidfd_owned: Option<&'a mut Option<OwnedFd>>,
+    // The kernel writes the pidfd here when `pidfd_owned` is used.
+    pub(crate) pidfd_slot: RawFd,
+    pub(crate) child_tid: Option<&'a mut pid_t>,
+    pub(crate) parent_tid: Option<&'a mut pid_t>,
+    pub(crate) cleartid: Option<&'a ClearTidHandle>,
+    pub(crate) exit_signal: u64,
+    pub(crate) stack: Option<RawStack>,
+    pub(crate) tls: Option<u64>,
+    pub(crate) set_tid: Option<&'a TidSpec>,
+    pub(crate) cgroup: Option<BorrowedFd<'a>>,
+    pub(crate) clone_args_size: Option<usize>,
+    pub(crate) legacy_fallback: bool,
+    pub(crate) eagain_retry: Option<RetryPolicy>,
+    pub(crate) setup: SetupRef<'a>,
 }
 
 impl<'a> Clone3<'a> {
+    /// A starting point for sandboxes that creates the child in new user, mount, pid, network,
+    /// ipc, uts and cgroup namespaces and sets `CLEAR_SIGHAND` if the kernel supports it.
+    ///
+    /// The kernel creates the user namespace first and makes it the owner of the others so no
+    /// privileges are needed. The child starts as the overflow user without capabilities in the
+    /// parent's namespaces. A typical sandbox continues with the following steps which the child
+    /// setup applies in this order:
+    ///
+    /// 1. [`userns_map`](Self::userns_map) to map the caller to root in the sandbox.
+    /// 2. [`loopback_up`](Self::loopback_up) if the sandbox needs local networking.
+    /// 3. [`mounts`](Self::mounts) starting with private propagation followed by
+    ///    [`pivot_root`](Self::pivot_root) into the new root file system.
+    /// 4. [`pid_init`](Self::pid_init) so that orphaned processes are reaped.
+    /// 5. [`capabilities`](Self::capabilities), then [`no_new_privs`](Self::no_new_privs) and
+    ///    [`seccomp_filter`](Self::seccomp_filter) last.
+    pub fn sandboxed() -> Self {
+        Self {
+            flags: sandbox_flags(),
+            ..Self::default()
+        }
+    }
+
+    /// Like `fork()`: no flags and `SIGCHLD` as the exit signal.
+    ///
+    /// Unlike with `Clone3::default()` the child can be waited for without `__WCLONE` by
+    /// `waitpid` and tools that expect a regular child process.
+    pub fn fork_like() -> Self {
+        Self {
+            exit_signal: SIGCHLD as u64,
+            ..Self::default()
+        }
+    }
+
     pub fn flag_child_cleartid(&mut self, child_tid: &'a mut pid_t) -> &mut Self {
         self.flags.set(Flags::CHILD_CLEARTID, true);
         self.child_tid = Some(child_tid);
+        self.cleartid = None;
+        self
+    }
+
+    /// Sets `CHILD_CLEARTID` and `PARENT_SETTID` both with the word of `handle` so that the exit
+    /// of the child can be waited for with [`ClearTidHandle::join`]. Replaces the locations set
+    /// by [`flag_child_cleartid`](Self::flag_child_cleartid) and
+    /// [`flag_parent_settid`](Self::flag_parent_settid).
+    pub fn flag_child_cleartid_handle(&mut self, handle: &'a ClearTidHandle) -> &mut Self {
+        self.flags
+            .set(Flags::CHILD_CLEARTID | Flags::PARENT_SETTID, true);
+        self.child_tid = None;
+        self.parent_tid = None;
+        self.cleartid = Some(handle);
         self
     }
 
-    #[cfg(feature = "linux_5-5")]
     pub fn flag_child_settid(&mut self, child_tid: &'a mut pid_t) -> &mut Self {
         self.flags.set(Flags::CHILD_SETTID, true);
         self.child_tid = Some(child_tid);
+        self.cleartid = None;
         self
     }
 
-    #[cfg(feature = "linux_5-5")]
     pub fn flag_clear_sighand(&mut self) -> &mut Self {
         self.flags.set(Flags::CLEAR_SIGHAND, true);
         self
@@ -57,10 +131,31 @@ impl<'a> Clone3<'a> {
         self
     }
 
-    #[cfg(feature = "linux_5-7")]
-    pub fn flag_into_cgroup(&mut self, cgroup: &'a dyn AsRawFd) -> &mut Self {
+    pub fn flag_into_cgroup(&mut self, cgroup: BorrowedFd<'a>) -> &mut Self {
         self.flags.set(Flags::INTO_CGROUP, true);
         self.cgroup = Some(cgroup);
+        self.setup.cgroup_path = None;
+        self
+    }
+
+    /// Like [`flag_into_cgroup`](Self::flag_into_cgroup) with the cgroup2 directory at `path`.
+    ///
+    /// The directory is opened and checked with [`CgroupFd::open`] right away. If that fails the
+    /// error is returned as [`Clone3Error::System`] by the next call.
+    pub fn flag_into_cgroup_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.flags.set(Flags::INTO_CGROUP, true);
+        self.cgroup = None;
+        self.setup.cgroup_path = Some(CgroupFd::open(path));
+        self
+    }
+
+    /// Like [`flag_into_cgroup_path`](Self::flag_into_cgroup_path) with a cgroup managed by the
+    /// `cgroups-rs` crate. See [`CgroupFd::from_cgroups_rs`].
+    #[cfg(feature = "cgroups-rs")]
+    pub fn flag_into_cgroups_rs(&mut self, cgroup: &cgroups_rs::Cgroup) -> &mut Self {
+        self.flags.set(Flags::INTO_CGROUP, true);
+        self.cgroup = None;
+        self.setup.cgroup_path = Some(CgroupFd::from_cgroups_rs(cgroup));
         self
     }
 
@@ -69,6 +164,16 @@ impl<'a> Clone3<'a> {
         self
     }
 
+    /// Creates the child in `cgroup` like [`flag_into_cgroup`](Self::flag_into_cgroup) and moves
+    /// it into the [`Child`] returned by the next successful [`spawn`](Self::spawn) which removes
+    /// it when dropped. Later calls do not use it.
+    ///
+    /// Takes precedence over [`flag_into_cgroup`](Self::flag_into_cgroup).
+    pub fn scoped_cgroup(&mut self, cgroup: ScopedCgroup) -> &mut Self {
+        self.setup.scoped_cgroup = Some(cgroup);
+        self
+    }
+
     pub fn flag_newcgroup(&mut self) -> &mut Self {
         self.flags.set(Flags::NEWCGROUP, true);
         self
@@ -117,12 +222,29 @@ impl<'a> Clone3<'a> {
     pub fn flag_parent_settid(&mut self, parent_tid: &'a mut pid_t) -> &mut Self {
         self.flags.set(Flags::PARENT_SETTID, true);
         self.parent_tid = Some(parent_tid);
+        self.cleartid = None;
+        self
+    }
+
+    /// Sets `PIDFD` and stores the pidfd of the child in `pidfd` after the system call.
+    ///
+    /// Only the call methods like [`call`](Self::call) fill in `pidfd`. Use
+    /// [`flag_pidfd_raw`](Self::flag_pidfd_raw) when performing the system call manually through
+    /// [`as_clone_args`](Self::as_clone_args).
+    pub fn flag_pidfd(&mut self, pidfd: &'a mut Option<OwnedFd>) -> &mut Self {
+        self.flags.set(Flags::PIDFD, true);
+        self.pidfd = None;
+        self.pidfd_owned = Some(pidfd);
         self
     }
 
-    pub fn flag_pidfd(&mut self, pidfd: &'a mut RawFd) -> &mut Self {
+    /// Sets `PIDFD` and lets the kernel write the pidfd of the child to `pidfd`.
+    ///
+    /// The caller is responsible for closing the pidfd.
+    pub fn flag_pidfd_raw(&mut self, pidfd: &'a mut RawFd) -> &mut Self {
         self.flags.set(Flags::PIDFD, true);
         self.pidfd = Some(pidfd);
+        self.pidfd_owned = None;
         self
     }
 
@@ -131,6 +253,8 @@ impl<'a> Clone3<'a> {
         self
     }
 
+    /// Sets the thread pointer of the child to the architecture specific value `tls`.
+    /// [`TlsBlock::tls`](crate::TlsBlock::tls) creates one.
     pub fn flag_settls(&mut self, tls: u64) -> &mut Self {
         self.flags.set(Flags::SETTLS, true);
         self.tls = Some(tls);
@@ -168,28 +292,585 @@ impl<'a> Clone3<'a> {
         self
     }
 
+    /// Sets the signal sent to the parent when the child exits. Use [`Signal`](crate::Signal) for
+    /// a checked value like `Signal::CHLD.into()`.
     pub fn exit_signal(&mut self, exit_signal: u64) -> &mut Self {
         self.exit_signal = exit_signal;
         self
     }
 
+    /// Sets the memory the child uses as its stack.
+    ///
+    /// The kernel starts the child with the stack pointer at the end of `stack`. The end is
+    /// aligned down to [`STACK_ALIGN`](crate::STACK_ALIGN) by passing a smaller size to the kernel.
+    /// Stacks smaller than [`MIN_STACK_SIZE`](crate::MIN_STACK_SIZE) after aligning are rejected
+    /// with [`Clone3Error::StackTooSmall`].
     pub fn stack(&mut self, stack: &'a mut [u8]) -> &mut Self {
-        self.stack = Some(stack);
+        // Only the child writes to the stack and it only writes initialized bytes.
+        let stack = unsafe { &mut *(stack as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        self.stack_uninit(stack)
+    }
+
+    /// Like [`stack`](Self::stack) for memory that does not need to be initialized.
+    pub fn stack_uninit(&mut self, stack: &'a mut [MaybeUninit<u8>]) -> &mut Self {
+        self.stack = Some(RawStack::from(stack));
         self
     }
 
-    #[cfg(feature = "linux_5-5")]
-    pub fn set_tid(&mut self, set_tid: &'a [pid_t]) -> &mut Self {
+    /// Like [`stack`](Self::stack) for memory that cannot be borrowed as a slice, for example
+    /// memory mapped by foreign code or shared with a `VM` child that is still running.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes of `len` bytes until the child no longer runs on the stack,
+    /// which is after it exits or calls execve. Nothing else may use the memory during that time.
+    pub unsafe fn stack_raw(&mut self, ptr: NonNull<u8>, len: usize) -> &mut Self {
+        self.stack = Some(RawStack { ptr, len });
+        self
+    }
+
+    /// Sets the pids of the child in its pid namespace and the ones above it. See [`TidSpec`].
+    pub fn set_tid(&mut self, set_tid: &'a TidSpec) -> &mut Self {
         self.set_tid = Some(set_tid);
         self
     }
 
+    /// Passes `size` to the kernel as the size of [`CloneArgs`](crate::CloneArgs) instead of its
+    /// full size.
+    ///
+    /// Use one of the `CLONE_ARGS_SIZE_VER*` constants or
+    /// [`max_clone_args_size`](crate::max_clone_args_size) to run on kernels older than the one
+    /// this crate was built for.
+    ///
+    /// Without an explicit size a call that fails with `E2BIG` is retried with the size returned
+    /// by [`max_clone_args_size`](crate::max_clone_args_size) if the fields past it are unset.
+    /// Otherwise it fails with
+    /// [`Clone3Error::UnsupportedByKernel`](crate::Clone3Error::UnsupportedByKernel).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is smaller than
+    /// [`CLONE_ARGS_SIZE_VER0`](crate::CLONE_ARGS_SIZE_VER0) or larger than `CloneArgs`.
+    pub fn clone_args_size(&mut self, size: usize) -> &mut Self {
+        assert!(
+            (crate::CLONE_ARGS_SIZE_VER0..=mem::size_of::<CloneArgs>()).contains(&size),
+            "invalid clone_args size {}",
+            size
+        );
+        self.clone_args_size = Some(size);
+        self
+    }
+
+    /// Sets up the stdin of the child.
+    pub fn stdin(&mut self, stdin: Stdio) -> &mut Self {
+        self.setup.stdio[0] = stdin;
+        self
+    }
+
+    /// Sets up the stdout of the child.
+    pub fn stdout(&mut self, stdout: Stdio) -> &mut Self {
+        self.setup.stdio[1] = stdout;
+        self
+    }
+
+    /// Sets up the stderr of the child.
+    pub fn stderr(&mut self, stderr: Stdio) -> &mut Self {
+        self.setup.stdio[2] = stderr;
+        self
+    }
+
+    /// Takes the parent end of the stdin pipe of the last successful call if stdin is
+    /// [`Stdio::Piped`](crate::Stdio::Piped).
+    pub fn take_stdin(&mut self) -> Option<File> {
+        self.setup.parent_stdio[0].take().map(File::from)
+    }
+
+    /// Takes the parent end of the stdout pipe of the last successful call if stdout is
+    /// [`Stdio::Piped`](crate::Stdio::Piped).
+    pub fn take_stdout(&mut self) -> Option<File> {
+        self.setup.parent_stdio[1].take().map(File::from)
+    }
+
+    /// Takes the parent end of the stderr pipe of the last successful call if stderr is
+    /// [`Stdio::Piped`](crate::Stdio::Piped).
+    pub fn take_stderr(&mut self) -> Option<File> {
+        self.setup.parent_stdio[2].take().map(File::from)
+    }
+
+    /// Registers a closure that runs in the child before [`call`](Self::call) returns 0 there.
+    ///
+    /// Closures run in the order they were registered after the other child setup except for
+    /// [`seccomp_filter`](Self::seccomp_filter). If one fails
+    /// the remaining ones are skipped and the error is returned in the parent as
+    /// [`Clone3Error::ChildSetup`](crate::Clone3Error::ChildSetup).
+    ///
+    /// # Safety
+    ///
+    /// The closure runs in the child right after the system call. If the parent is multi threaded
+    /// only async-signal-safe functions may be called which rules out allocating and taking locks.
+    /// With `VM` the closure runs in the memory of the parent.
+    pub unsafe fn child_setup<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut() -> Result<(), Errno> + Send + 'static,
+    {
+        self.setup.hooks.push(Hook(Box::new(f)));
+        self
+    }
+
+    /// Sets the name of the child shown by tools like ps and top. Names are truncated to 15
+    /// bytes.
+    pub fn name<S: AsRef<OsStr>>(&mut self, name: S) -> &mut Self {
+        let name = to_cstring(name.as_ref(), &mut self.setup.nul_byte);
+        self.setup.name = Some(name);
+        self
+    }
+
+    /// Sets the soft and hard limit of the resource `resource` like `RLIMIT_NOFILE` in the child.
+    ///
+    /// Can be called multiple times to set different resources. Raising a hard limit requires
+    /// `CAP_SYS_RESOURCE` so the limits are set before the credentials are changed.
+    pub fn rlimit(&mut self, resource: c_int, soft: rlim_t, hard: rlim_t) -> &mut Self {
+        let limit = rlimit {
+            rlim_cur: soft,
+            rlim_max: hard,
+        };
+        self.setup.rlimits.push((resource, limit));
+        self
+    }
+
+    /// Sets the OOM score adjustment of the child in the range -1000 to 1000. Higher values make
+    /// the child a preferred victim of the OOM killer.
+    ///
+    /// Written to `/proc/self/oom_score_adj` in the child before the credentials are changed
+    /// because lowering the value requires `CAP_SYS_RESOURCE`. Requires procfs to be mounted.
+    pub fn oom_score_adj(&mut self, value: c_int) -> &mut Self {
+        self.setup.oom_score_adj = Some(value.to_string().into_bytes());
+        self
+    }
+
+    /// Sets the nice value of the child to `level` in the range -20 to 19. Lower values give the
+    /// child a higher scheduling priority.
+    ///
+    /// Set before the credentials are changed because lowering the value requires
+    /// `CAP_SYS_NICE`.
+    pub fn nice(&mut self, level: c_int) -> &mut Self {
+        self.setup.nice = Some(level);
+        self
+    }
+
+    /// Restricts the child to run on the CPUs in `cpus`.
+    pub fn cpu_affinity(&mut self, cpus: &CpuSet) -> &mut Self {
+        self.setup.cpu_affinity = Some(*cpus);
+        self
+    }
+
+    /// Sets the scheduling policy and its parameters like the priority of `SCHED_FIFO` or the
+    /// runtime of `SCHED_DEADLINE` in the child with `sched_setattr`. The `size` field is filled
+    /// in.
+    ///
+    /// Set before the credentials are changed because real-time policies require
+    /// `CAP_SYS_NICE`.
+    pub fn sched_attr(&mut self, attr: &sched_attr) -> &mut Self {
+        let mut attr = *attr;
+        attr.size = mem::size_of::<sched_attr>() as u32;
+        self.setup.sched_attr = Some(SchedAttr(attr));
+        self
+    }
+
+    /// Sets the NUMA memory policy of the child with `set_mempolicy`.
+    ///
+    /// `mode` is a policy like `MPOL_BIND` optionally combined with mode flags. Bit n of
+    /// `nodemask` stands for node n with each element holding `c_ulong::BITS` nodes.
+    pub fn mempolicy(&mut self, mode: c_int, nodemask: &[c_ulong]) -> &mut Self {
+        self.setup.mempolicy = Some((mode, nodemask.to_vec()));
+        self
+    }
+
+    /// Sets the execution domain of the child with `personality`.
+    ///
+    /// For example `ADDR_NO_RANDOMIZE` disables address space layout randomization for the
+    /// programs the child executes.
+    pub fn personality(&mut self, persona: c_ulong) -> &mut Self {
+        self.setup.personality = Some(persona);
+        self
+    }
+
+    /// Sets the hostname of the child with `sethostname`.
+    ///
+    /// Requires `NEWUTS` because the child would otherwise change the hostname of the system.
+    pub fn hostname<S: AsRef<OsStr>>(&mut self, hostname: S) -> &mut Self {
+        let hostname = to_cstring(hostname.as_ref(), &mut self.setup.nul_byte);
+        self.setup.hostname = Some(hostname);
+        self
+    }
+
+    /// Sets the NIS domain name of the child with `setdomainname`.
+    ///
+    /// Requires `NEWUTS` like [`hostname`](Self::hostname).
+    pub fn domainname<S: AsRef<OsStr>>(&mut self, domainname: S) -> &mut Self {
+        let domainname = to_cstring(domainname.as_ref(), &mut self.setup.nul_byte);
+        self.setup.domainname = Some(domainname);
+        self
+    }
+
+    /// Sets the loopback interface of the new network namespace of the child up. Requires
+    /// `NEWNET`.
+    pub fn loopback_up(&mut self) -> &mut Self {
+        self.setup.loopback_up = true;
+        self
+    }
+
+    /// Creates a veth pair with the end `host` in the network namespace of the parent and the end
+    /// `child` in the new one of the child and sets both up.
+    ///
+    /// The parent creates the pair through rtnetlink after the system call while the child waits
+    /// like for [`userns_map`](Self::userns_map). Requires `NEWNET` and `CAP_NET_ADMIN` in the
+    /// network namespace of the parent. Names are limited to 15 bytes. Addresses and routes are
+    /// not configured.
+    pub fn veth<H: AsRef<OsStr>, C: AsRef<OsStr>>(&mut self, host: H, child: C) -> &mut Self {
+        let host = to_cstring(host.as_ref(), &mut self.setup.nul_byte);
+        let child = to_cstring(child.as_ref(), &mut self.setup.nul_byte);
+        self.setup.veth = Some((host, child));
+        self
+    }
+
+    /// Performs the mounts of `plan` in the child in order.
+    ///
+    /// Requires `NEWNS` because the child would otherwise change the mounts of the parent. Set
+    /// before the credentials are changed because mounting requires `CAP_SYS_ADMIN`.
+    pub fn mounts(&mut self, plan: &MountPlan) -> &mut Self {
+        self.setup.mounts = plan.mounts.clone();
+        self.setup.nul_byte |= plan.nul_byte;
+        self
+    }
+
+    /// Makes `new_root` the root directory of the child with `pivot_root` and detaches the old
+    /// root so that no path outside of `new_root` remains reachable.
+    ///
+    /// Requires `NEWNS`. Performed after [`mounts`](Self::mounts) which can prepare the new root
+    /// through paths of the old one. Changes the propagation of all mounts to private first
+    /// because `pivot_root` fails for shared mounts. [`current_dir`](Self::current_dir) is
+    /// resolved in the new root and defaults to `/`.
+    pub fn pivot_root<P: AsRef<Path>>(&mut self, new_root: P) -> &mut Self {
+        let new_root = to_cstring(new_root.as_ref().as_os_str(), &mut self.setup.nul_byte);
+        self.setup.root = Some(Root::PivotRoot(new_root));
+        self
+    }
+
+    /// Makes `new_root` the root directory of the child with `chroot`.
+    ///
+    /// Unlike [`pivot_root`](Self::pivot_root) this does not require `NEWNS` but the old root
+    /// stays reachable through fds opened before and for processes with `CAP_SYS_CHROOT`.
+    /// Performed at the same step. Conflicts with `FS`.
+    pub fn chroot<P: AsRef<Path>>(&mut self, new_root: P) -> &mut Self {
+        let new_root = to_cstring(new_root.as_ref().as_os_str(), &mut self.setup.nul_byte);
+        self.setup.root = Some(Root::Chroot(new_root));
+        self
+    }
+
+    /// Makes the child the init process of the new pid namespace and runs the user's code in a
+    /// further child, the payload.
+    ///
+    /// The init process forwards all signals it receives to the payload and reaps orphaned
+    /// processes of the namespace. It exits with the exit code of the payload or 128 plus the
+    /// signal that killed it. The call only returns 0 in the payload which has pid 2 in the
+    /// namespace. The pid returned in the parent is the one of the init process. If `NEWNS` is
+    /// set a new procfs is mounted at `/proc` after [`pivot_root`](Self::pivot_root).
+    ///
+    /// Requires `NEWPID`. All other steps are performed by the init process before the payload is
+    /// created so a [`seccomp_filter`](Self::seccomp_filter) must allow `clone3`, `wait4`,
+    /// `rt_sigtimedwait` and `kill`.
+    pub fn pid_init(&mut self) -> &mut Self {
+        self.setup.pid_init = true;
+        self
+    }
+
+    /// Sets the I/O scheduling class and the priority `level` within the class from 0 (highest)
+    /// to 7 (lowest) of the child. The level is ignored for
+    /// [`IoPriorityClass::Idle`](crate::IoPriorityClass::Idle).
+    ///
+    /// Applies to the I/O context shared with the parent if `IO` is set.
+    pub fn io_priority(&mut self, class: IoPriorityClass, level: u8) -> &mut Self {
+        self.setup.io_priority = Some(class.value(level));
+        self
+    }
+
+    /// Makes the child join the existing namespace of type `namespace` that `fd` refers to with
+    /// `setns`, for example one opened from `/proc/<pid>/ns` or pinned with
+    /// [`pin_namespace`](crate::pin_namespace).
+    ///
+    /// Can be called for different types and replaces an earlier fd of the same type. Joined
+    /// after the `NEW*` namespaces are created so it takes precedence. Joining a pid namespace
+    /// only places the children of the child in it. Joining a mount or user namespace conflicts
+    /// with `FS`.
+    pub fn join_namespace(&mut self, namespace: Namespace, fd: OwnedFd) -> &mut Self {
+        let namespaces = &mut self.setup.join_namespaces;
+        namespaces.retain(|(_, other)| *other != namespace);
+        namespaces.push((fd, namespace));
+        self
+    }
+
+    /// Writes the user and group id maps of the user namespace created with `NEWUSER`.
+    ///
+    /// The parent writes `uid_map` and `gid_map` of the child after the system call. The child
+    /// waits for this before its other steps. Unless [`groups`](Self::groups) is set, `deny` is
+    /// written to `setgroups` before `gid_map` which is required if the parent lacks `CAP_SETGID`.
+    /// An empty slice leaves the corresponding map unwritten. Errors from writing the maps are
+    /// returned as [`Clone3Error::ChildSetup`](crate::Clone3Error::ChildSetup).
+    pub fn userns_map(&mut self, uid_maps: &[IdMap], gid_maps: &[IdMap]) -> &mut Self {
+        self.setup.userns_map = Some((IdMap::format(uid_maps), IdMap::format(gid_maps)));
+        self
+    }
+
+    /// Sets the supplementary groups of the child.
+    pub fn groups(&mut self, groups: &[gid_t]) -> &mut Self {
+        self.setup.groups = Some(groups.to_vec());
+        self
+    }
+
+    /// Sets the real, effective and saved group id of the child. Applied after
+    /// [`groups`](Self::groups).
+    pub fn gid(&mut self, gid: gid_t) -> &mut Self {
+        self.setup.gid = Some(gid);
+        self
+    }
+
+    /// Sets the real, effective and saved user id of the child. Applied after
+    /// [`groups`](Self::groups) and [`gid`](Self::gid) so that dropping privileges with the user
+    /// id does not prevent changing them.
+    pub fn uid(&mut self, uid: uid_t) -> &mut Self {
+        self.setup.uid = Some(uid);
+        self
+    }
+
+    /// Limits the capabilities of the child to `keep` in which bit n stands for capability n.
+    ///
+    /// The other capabilities are removed from the bounding and ambient sets before the
+    /// credentials are changed and from the effective, permitted and inheritable sets afterwards.
+    /// The bounding set can only be changed with `CAP_SETPCAP`.
+    pub fn capabilities(&mut self, keep: u64) -> &mut Self {
+        self.setup.capabilities = Some(keep);
+        self
+    }
+
+    /// Sends `signal` to the child when the thread that created it exits.
+    ///
+    /// If the parent already exited before the signal was set up the child sends the signal to
+    /// itself and exits. This check is not possible in a new pid namespace. The signal is set
+    /// after changing the credentials because that clears it. Executing a set-user-ID program
+    /// also clears it.
+    pub fn parent_death_signal(&mut self, signal: c_int) -> &mut Self {
+        self.setup.parent_death_signal = Some(signal);
+        self
+    }
+
+    /// Changes the working directory of the child.
+    ///
+    /// Relative paths are resolved against the working directory of the parent. Conflicts with
+    /// `FS`.
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        let dir = to_cstring(dir.as_ref().as_os_str(), &mut self.setup.nul_byte);
+        self.setup.current_dir = Some(dir);
+        self
+    }
+
+    /// Sets the file mode creation mask of the child. Conflicts with `FS`.
+    pub fn umask(&mut self, mask: mode_t) -> &mut Self {
+        self.setup.umask = Some(mask);
+        self
+    }
+
+    /// Makes the child the leader of a new session and process group without a controlling
+    /// terminal by calling `setsid`.
+    pub fn setsid(&mut self) -> &mut Self {
+        self.setup.setsid = true;
+        self
+    }
+
+    /// Moves the child into the process group `pgid` with `setpgid`. A `pgid` of 0 creates a new
+    /// process group with the pid of the child as its id.
+    ///
+    /// The parent also calls `setpgid` so that the group is set before the child can be signaled
+    /// through it. Cannot be combined with [`setsid`](Self::setsid) which already creates a new
+    /// process group.
+    pub fn process_group(&mut self, pgid: pid_t) -> &mut Self {
+        self.setup.process_group = Some(pgid);
+        self
+    }
+
+    /// Allocates a pseudo terminal for the child.
+    ///
+    /// The child becomes the leader of a new session like with [`setsid`](Self::setsid) with the
+    /// pty as its controlling terminal.
+    /// Streams left at [`Stdio::Inherit`](crate::Stdio::Inherit) are connected to the pty. The
+    /// master is available through [`take_pty_master`](Self::take_pty_master) or the
+    /// [`Child`](crate::Child) handle. Its window size can be set with the `TIOCSWINSZ` ioctl.
+    pub fn pty(&mut self) -> &mut Self {
+        self.setup.pty = true;
+        self
+    }
+
+    /// Makes the terminal `tty` the controlling terminal of the child.
+    ///
+    /// The child becomes the leader of a new session like with [`setsid`](Self::setsid) and then
+    /// acquires the terminal with the `TIOCSCTTY` ioctl. Takes precedence over the pty from
+    /// [`pty`](Self::pty). Conflicts with `FILES`.
+    pub fn controlling_terminal(&mut self, tty: OwnedFd) -> &mut Self {
+        self.setup.controlling_terminal = Some(tty);
+        self
+    }
+
+    /// Takes the master of the pty of the last successful call if [`pty`](Self::pty) is set.
+    pub fn take_pty_master(&mut self) -> Option<File> {
+        self.setup.pty_master.take().map(File::from)
+    }
+
+    /// Creates a pair of pipes before the system call through which parent and child can wait
+    /// for each other, for example until the parent has configured the namespaces of the child.
+    ///
+    /// The child takes its side with [`take_child_barrier`](Self::take_child_barrier) after the
+    /// call returns 0. The parent side is moved into the [`Child`](crate::Child) handle by
+    /// [`spawn`](Self::spawn) and can otherwise be taken with
+    /// [`take_parent_barrier`](Self::take_parent_barrier). Both sides are close-on-exec.
+    pub fn barrier(&mut self) -> &mut Self {
+        self.setup.barrier = true;
+        self
+    }
+
+    /// Takes the parent side of the barrier of the last successful call if
+    /// [`barrier`](Self::barrier) is set.
+    pub fn take_parent_barrier(&mut self) -> Option<ParentBarrier> {
+        self.setup.parent_barrier.take()
+    }
+
+    /// Takes the child side of the barrier in the child if [`barrier`](Self::barrier) is set.
+    pub fn take_child_barrier(&mut self) -> Option<ChildBarrier> {
+        self.setup.child_barrier.take()
+    }
+
+    /// Sets the SELinux security context the child transitions to when it executes a program
+    /// like `setexeccon`.
+    pub fn selinux_exec_context<S: AsRef<OsStr>>(&mut self, context: S) -> &mut Self {
+        let context = to_cstring(context.as_ref(), &mut self.setup.nul_byte);
+        self.setup.exec_label = Some((Lsm::SELinux, context.into_bytes()));
+        self
+    }
+
+    /// Sets the AppArmor profile the child transitions to when it executes a program like
+    /// `aa_change_onexec`.
+    pub fn apparmor_exec_profile<S: AsRef<OsStr>>(&mut self, profile: S) -> &mut Self {
+        let mut command = OsString::from("exec ");
+        command.push(profile);
+        let command = to_cstring(&command, &mut self.setup.nul_byte);
+        self.setup.exec_label = Some((Lsm::AppArmor, command.into_bytes()));
+        self
+    }
+
+    /// Disables core dumps of the child and prevents other processes of the same user from
+    /// attaching to it with ptrace.
+    ///
+    /// Sets the dumpable attribute to 0 and the core dump size limit to 0. Executing a program
+    /// resets the dumpable attribute but the limit stays so that the program does not dump core
+    /// either. Applied after the other steps that write to `/proc/self` because it becomes owned
+    /// by root.
+    pub fn non_dumpable(&mut self) -> &mut Self {
+        self.setup.non_dumpable = true;
+        self
+    }
+
+    /// Sets no_new_privs in the child so that executing a program cannot grant privileges through
+    /// set-user-ID bits or file capabilities. Allows installing a
+    /// [`seccomp_filter`](Self::seccomp_filter) and [`landlock_ruleset`](Self::landlock_ruleset)
+    /// without `CAP_SYS_ADMIN`.
+    pub fn no_new_privs(&mut self) -> &mut Self {
+        self.setup.no_new_privs = true;
+        self
+    }
+
+    /// Restricts the child with the Landlock ruleset `ruleset` created with
+    /// `landlock_create_ruleset`.
+    ///
+    /// Applied after [`child_setup`](Self::child_setup) closures. Requires `CAP_SYS_ADMIN` or
+    /// [`no_new_privs`](Self::no_new_privs).
+    pub fn landlock_ruleset(&mut self, ruleset: OwnedFd) -> &mut Self {
+        self.setup.landlock_ruleset = Some(ruleset);
+        self
+    }
+
+    /// Installs the seccomp BPF program `filter` in the child.
+    ///
+    /// The filter is installed after all other child setup including
+    /// [`child_setup`](Self::child_setup) closures so that it only has to allow the system calls of
+    /// the code that runs after [`call`](Self::call) returns 0 in the child or the program it
+    /// executes. Requires `CAP_SYS_ADMIN` or [`no_new_privs`](Self::no_new_privs).
+    pub fn seccomp_filter(&mut self, filter: &[sock_filter]) -> &mut Self {
+        self.setup.seccomp_filter = Some(filter.to_vec());
+        self
+    }
+
+    /// Closes all fds from `first` upwards when the child executes a program.
+    ///
+    /// The fds are marked close-on-exec instead of being closed right away so that fds owned by
+    /// values in the memory of the child like an [`OwnedFd`] stay valid. A child that does not
+    /// execute a program keeps them open. Uses `close_range` and falls back to the entries of
+    /// `/proc/self/fd` before Linux 5.11. Conflicts with `FILES`.
+    pub fn close_fds(&mut self, first: RawFd) -> &mut Self {
+        self.setup.close_fds = Some(first);
+        self
+    }
+
+    /// Installs the fds of the parent in `fds` as the paired fd numbers in the child and closes
+    /// all other fds except stdio when the child executes a program.
+    ///
+    /// The passed fds are installed after stdin, stdout and stderr and are not close-on-exec.
+    /// Targets may overlap with the passed fds, for example to swap two fds. The fds of the parent
+    /// must stay open until the call returns. All other fds from 3 upwards are handled like with
+    /// [`close_fds`](Self::close_fds). Conflicts with `FILES`.
+    pub fn pass_fds<I: IntoIterator<Item = (RawFd, RawFd)>>(&mut self, fds: I) -> &mut Self {
+        self.setup.pass_fds.extend(fds);
+        self
+    }
+
+    /// Falls back to the legacy clone system call if clone3 fails with `ENOSYS`.
+    ///
+    /// This happens on kernels older than 5.3 and with seccomp policies that block clone3 like
+    /// older versions of Docker's default profile. The legacy system call supports only a subset
+    /// of the arguments. If the arguments cannot be represented the call fails with
+    /// [`Clone3Error::LegacyFallbackUnsupported`](crate::Clone3Error::LegacyFallbackUnsupported):
+    /// * `CLEAR_SIGHAND` and `INTO_CGROUP` are not supported
+    /// * `set_tid` is not supported
+    /// * `PIDFD` and `PARENT_SETTID` must not be set together
+    pub fn legacy_fallback(&mut self) -> &mut Self {
+        self.legacy_fallback = true;
+        self
+    }
+
+    /// Retries the system call according to `policy` while it fails with `EAGAIN`.
+    ///
+    /// Without this a single `EAGAIN` is returned to the caller. The retries sleep on the calling
+    /// thread but do not allocate.
+    pub fn retry_eagain(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.eagain_retry = Some(policy);
+        self
+    }
+
     /// Performs the system call.
     ///
+    /// Returns the pid of the child in the parent and 0 in the child. A [`Pid`](crate::Pid)
+    /// cannot be 0 so this is a `pid_t`. [`spawn`](Self::spawn) returns a [`Child`] whose
+    /// [`pid`](crate::Child::pid) is a `Pid`.
+    ///
     /// # Errors
     ///
     /// Errors if the system call returns -1.
     ///
+    /// Errors with `EINVAL` without performing the system call if the arguments are invalid, for
+    /// example if fields are set that are not included in the size set through
+    /// [`clone_args_size`](Self::clone_args_size) or child setup contains a nul byte or conflicts
+    /// with the flags. Errors with `ENOSYS` if clone3 is unavailable and the
+    /// [`legacy_fallback`](Self::legacy_fallback) cannot be used. Use
+    /// [`try_call`](Self::try_call) to tell these apart.
+    ///
     /// # Panics
     ///
     /// Panics if the set flags are incompatible:
@@ -206,24 +887,249 @@ impl<'a> Clone3<'a> {
     // For the last two conditions we could automatically set the other required but I prefer the
     // explicitness of forcing the user to set them.
     //
-    /// Panics if the system call returns a value that neither indicates failure nor is convertible
-    /// to [`pid_t`](pid_t) which  could happen on overflow due to different type sizes. This is a
-    /// bug in the Linux kernel or the libc bindings used by this crate.
+    /// Panics if the system call returns a value that neither indicates failure nor is a valid
+    /// [`pid_t`](pid_t). This is a bug in the Linux kernel or the libc bindings used by this crate.
+    ///
+    /// Without child setup like [`stdin`](Self::stdin) nothing is allocated before the system
+    /// call so this can be used in contexts where allocating is not allowed, like in a forked child
+    /// of a multi threaded process right before exec. Child setup is prepared by allocating in the
+    /// parent. Panicking also allocates.
     pub unsafe fn call(&mut self) -> Result<pid_t, Errno> {
+        match self.try_call() {
+            Ok(pid) => Ok(pid),
+            Err(Clone3Error::System(errno)) => Err(errno),
+            Err(Clone3Error::CloneArgsSizeTooSmall { .. }) => Err(Errno(EINVAL)),
+            Err(Clone3Error::InvalidExitSignal { .. }) => Err(Errno(EINVAL)),
+            Err(Clone3Error::InvalidSetTid { .. }) => Err(Errno(EINVAL)),
+            Err(Clone3Error::SetTidTooLong { .. }) => Err(Errno(EINVAL)),
+            Err(Clone3Error::InvalidCloneArgs(_)) => Err(Errno(EINVAL)),
+            Err(Clone3Error::StackTooSmall { .. }) => Err(Errno(EINVAL)),
+            Err(Clone3Error::UnsupportedByKernel { errno, .. }) => Err(errno),
+            Err(Clone3Error::Unavailable { errno, .. }) => Err(errno),
+            Err(Clone3Error::LegacyFallbackUnsupported(_)) => Err(Errno(ENOSYS)),
+            Err(Clone3Error::ChildSetup { errno, .. }) => Err(errno),
+            Err(Clone3Error::NulByte) => Err(Errno(EINVAL)),
+            Err(Clone3Error::SetupConflictsWithFlag { .. }) => Err(Errno(EINVAL)),
+            Err(Clone3Error::SetupRequiresFlag { .. }) => Err(Errno(EINVAL)),
+            Err(err @ Clone3Error::IncompatibleFlags { .. }) => panic!("{}", err),
+        }
+    }
+
+    /// Performs the system call.
+    ///
+    /// Like [`try_call`](Self::try_call) but returns an `io::Error`. See the conversion from
+    /// [`Clone3Error`](crate::Clone3Error).
+    pub unsafe fn call_io(&mut self) -> io::Result<pid_t> {
+        self.try_call().map_err(io::Error::from)
+    }
+
+    /// Performs the system call.
+    ///
+    /// Like [`call`](Self::call) but returns incompatible flags as
+    /// [`Clone3Error::IncompatibleFlags`](crate::Clone3Error::IncompatibleFlags) instead of
+    /// panicking. Use this when the flags come from user input.
+    ///
+    /// If child setup like [`stdin`](Self::stdin) is configured the child performs it before this
+    /// returns 0 there. The parent waits until the child has finished. If a step fails the child
+    /// reports the error through a close-on-exec pipe and exits with code 127, the parent reaps
+    /// it and returns [`Clone3Error::ChildSetup`](crate::Clone3Error::ChildSetup). With `FILES`
+    /// the pipe would be shared so the child only exits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the system call returns a value that is not a valid [`pid_t`](pid_t). See
+    /// [`call`](Self::call).
+    pub unsafe fn try_call(&mut self) -> Result<pid_t, Clone3Error> {
         if let Some(reason) = find_incompatible_flags(self.flags) {
-            panic!("flags {:?} are inconsistent: {}", self.flags, reason);
+            return Err(Clone3Error::IncompatibleFlags {
+                flags: self.flags,
+                reason,
+            });
+        }
+        if let Some(reason) = find_invalid_exit_signal(self.flags, self.exit_signal) {
+            return Err(Clone3Error::InvalidExitSignal {
+                exit_signal: self.exit_signal,
+                reason,
+            });
+        }
+        if let Some(set_tid) = self.set_tid {
+            set_tid.validate(TidSpec::max_levels(self.flags))?;
+        }
+        let cl_args = self.as_clone_args();
+        let size = self.effective_clone_args_size();
+        let required = cl_args.min_size();
+        if size < required {
+            return Err(Clone3Error::CloneArgsSizeTooSmall { size, required });
+        }
+        if self.stack.is_some() && (cl_args.stack_size as usize) < MIN_STACK_SIZE {
+            return Err(Clone3Error::StackTooSmall {
+                size: cl_args.stack_size as usize,
+                required: MIN_STACK_SIZE,
+            });
         }
-        let return_value = self.call_unchecked();
-        handle_return_value(return_value)
+        if let Some(reason) = cl_args.find_invalid() {
+            return Err(Clone3Error::InvalidCloneArgs(reason));
+        }
+        if self.setup.nul_byte {
+            return Err(Clone3Error::NulByte);
+        }
+        if let Some(Err(errno)) = self.setup.cgroup_path {
+            return Err(Clone3Error::System(errno));
+        }
+        if let Some((setup, flag)) = self.setup.find_conflict(self.flags) {
+            let setup = setup.name();
+            return Err(Clone3Error::SetupConflictsWithFlag { setup, flag });
+        }
+        if let Some((setup, flag)) = self.setup.find_missing_flag(self.flags) {
+            let setup = setup.name();
+            return Err(Clone3Error::SetupRequiresFlag { setup, flag });
+        }
+        let setup = if self.setup.is_empty() {
+            None
+        } else {
+            let prepared = self
+                .setup
+                .prepare(self.flags)
+                .map_err(Clone3Error::System)?;
+            let error_pipe = if self.flags.contains(Flags::FILES) {
+                None
+            } else {
+                Some(ErrorPipe::new(self.setup.min_fd()).map_err(Clone3Error::System)?)
+            };
+            Some((prepared, error_pipe))
+        };
+        // errno is read right after each system call so that nothing in between can change it.
+        let call = |size| clone3_result((&cl_args as *const CloneArgs).cast(), size);
+        let mut result = retry(
+            self.eagain_retry,
+            || call(size),
+            |&result| result == Err(Errno(EAGAIN)),
+        );
+        if result == Err(Errno(E2BIG)) && self.clone_args_size.is_none() {
+            if let Some(smaller) = crate::max_clone_args_size()
+                .ok()
+                .and_then(|supported| find_smaller_size(&cl_args, size, supported))
+            {
+                result = retry(
+                    self.eagain_retry,
+                    || call(smaller),
+                    |&result| result == Err(Errno(EAGAIN)),
+                );
+            }
+        }
+        if result == Err(Errno(ENOSYS)) && self.legacy_fallback {
+            if let Some(reason) = find_legacy_unsupported(&cl_args) {
+                return Err(Clone3Error::LegacyFallbackUnsupported(reason));
+            }
+            result = pid_from_libc_result(crate::legacy_clone_system_call(&cl_args));
+        }
+        self.store_owned_pidfd(matches!(result, Ok(pid) if pid > 0));
+        let pid = result.map_err(|errno| classify_error(&cl_args, errno))?;
+        if let Some((prepared, error_pipe)) = setup {
+            if pid == 0 {
+                self.run_setup(prepared, error_pipe);
+            } else {
+                self.finish_setup(pid, prepared, error_pipe)?;
+            }
+        }
+        Ok(pid)
+    }
+
+    /// Applies the child setup in the child and exits if it fails.
+    ///
+    /// With [`pid_init`](Self::pid_init) only the created payload returns.
+    unsafe fn run_setup(&mut self, prepared: Prepared, error_pipe: Option<ErrorPipe>) {
+        let result = self.setup.apply(prepared).and_then(|()| {
+            if self.setup.pid_init {
+                init::fork_payload().map_err(|errno| (Step::PidInit, errno))
+            } else {
+                Ok(None)
+            }
+        });
+        match result {
+            Ok(payload) => {
+                if let Some(error_pipe) = error_pipe {
+                    error_pipe.close_in_child();
+                }
+                if let Some(payload) = payload {
+                    init::run(payload);
+                }
+            }
+            Err(err) => {
+                if let Some(error_pipe) = error_pipe {
+                    error_pipe.report(err);
+                }
+                c::_exit(127);
+            }
+        }
+    }
+
+    /// Waits for the child to finish its setup and reaps it if the setup failed.
+    fn finish_setup(
+        &mut self,
+        pid: pid_t,
+        mut prepared: Prepared,
+        error_pipe: Option<ErrorPipe>,
+    ) -> Result<(), Clone3Error> {
+        let parent_error = self.setup.apply_in_parent(pid, &mut prepared).err();
+        let parent_error = parent_error.map(|(step, errno)| (Some(step), errno));
+        let child_error = error_pipe.and_then(ErrorPipe::receive);
+        if let Some((step, errno)) = parent_error.or(child_error) {
+            let _ = crate::wait_pid(pid, WaitOptions::empty());
+            // The child reported a step this version does not know.
+            let step = step.map_or("unknown", Step::name);
+            return Err(Clone3Error::ChildSetup { step, errno });
+        }
+        self.setup.finish(prepared);
+        Ok(())
+    }
+
+    /// Performs the system call and returns a [`Child`](crate::Child) handle in the parent.
+    ///
+    /// Returns `None` in the child. If `PIDFD` is set through [`flag_pidfd`](Self::flag_pidfd) the
+    /// pidfd is moved into the handle instead of being stored in the referenced variable. The same
+    /// applies to the parent ends of piped stdio and the pty master.
+    ///
+    /// Errors like [`try_call`](Self::try_call).
+    pub unsafe fn spawn(&mut self) -> Result<Option<Child>, Clone3Error> {
+        let pid = self.try_call()?;
+        if pid == 0 {
+            return Ok(None);
+        }
+        let pidfd = self.pidfd_owned.as_mut().and_then(|pidfd| pidfd.take());
+        let mut child = Child::new(pid, pidfd);
+        child.stdio = mem::take(&mut self.setup.parent_stdio).map(|fd| fd.map(File::from));
+        child.pty_master = self.take_pty_master();
+        child.barrier = self.take_parent_barrier();
+        child.cgroup = self.setup.scoped_cgroup.take();
+        Ok(Some(child))
     }
 
     /// Performs the system call.
     ///
-    /// Like [`call`](Self::call) but never errors or panics. Forwards the return value of the system
-    /// call.
+    /// Like [`call`](Self::call) but never errors or panics. Forwards the return value of the
+    /// system call. Child setup is not performed.
     pub unsafe fn call_unchecked(&mut self) -> c_long {
         let cl_args = self.as_clone_args();
-        crate::clone3_system_call(&cl_args)
+        let return_value =
+            crate::clone3_system_call_with_size(&cl_args, self.effective_clone_args_size());
+        self.store_owned_pidfd(return_value > 0);
+        return_value
+    }
+
+    /// Takes ownership of the pidfd written by the kernel if `parent` is true which it only is in
+    /// the parent after a successful call.
+    pub(crate) unsafe fn store_owned_pidfd(&mut self, parent: bool) {
+        if parent {
+            if let Some(pidfd) = &mut self.pidfd_owned {
+                **pidfd = Some(OwnedFd::from_raw_fd(self.pidfd_slot));
+            }
+        }
+    }
+
+    pub(crate) fn effective_clone_args_size(&self) -> usize {
+        self.clone_args_size
+            .unwrap_or_else(mem::size_of::<CloneArgs>)
     }
 
     /// Returns the underlying [`CloneArgs`](crate::CloneArgs).
@@ -232,31 +1138,72 @@ impl<'a> Clone3<'a> {
     /// ensure that the referenced variables stay alive and the referenced mutable variables are not
     /// aliased.
     pub fn as_clone_args(&mut self) -> CloneArgs {
+        let scoped_cgroup = self.setup.scoped_cgroup.as_ref().map(AsFd::as_fd);
+        let mut flags = self.flags;
+        flags.set(
+            Flags::INTO_CGROUP,
+            flags.contains(Flags::INTO_CGROUP) || scoped_cgroup.is_some(),
+        );
+        let cgroup_path = match &self.setup.cgroup_path {
+            Some(Ok(fd)) => Some(fd.as_fd()),
+            _ => None,
+        };
+        let cgroup = scoped_cgroup.or(self.cgroup).or(cgroup_path);
+        let (stack, stack_size) = match self.stack {
+            Some(RawStack { ptr, len }) => {
+                let stack = ptr.as_ptr() as usize;
+                (
+                    stack,
+                    ((stack + len) & !(STACK_ALIGN - 1)).saturating_sub(stack),
+                )
+            }
+            None => (0, 0),
+        };
         CloneArgs {
-            flags: self.flags.bits(),
-            pidfd: option_as_mut_ptr(&mut self.pidfd) as u64,
-            child_tid: option_as_mut_ptr(&mut self.child_tid) as u64,
-            parent_tid: option_as_mut_ptr(&mut self.parent_tid) as u64,
+            flags: flags.bits(),
+            pidfd: match (&mut self.pidfd, &self.pidfd_owned) {
+                (Some(pidfd), _) => *pidfd as *mut RawFd as u64,
+                (None, Some(_)) => &mut self.pidfd_slot as *mut RawFd as u64,
+                (None, None) => 0,
+            },
+            child_tid: match self.cleartid {
+                Some(handle) => handle.as_ptr() as u64,
+                None => option_as_mut_ptr(&mut self.child_tid) as u64,
+            },
+            parent_tid: match self.cleartid {
+                Some(handle) => handle.as_ptr() as u64,
+                None => option_as_mut_ptr(&mut self.parent_tid) as u64,
+            },
             exit_signal: self.exit_signal,
-            stack: option_slice_as_mut_ptr(&mut self.stack) as u64,
-            stack_size: self.stack.as_ref().map(|stack| stack.len()).unwrap_or(0) as u64,
+            stack: stack as u64,
+            stack_size: stack_size as u64,
             tls: self.tls.unwrap_or(0),
-            #[cfg(feature = "linux_5-5")]
-            set_tid: option_slice_as_ptr(&self.set_tid) as u64,
-            #[cfg(feature = "linux_5-5")]
-            set_tid_size: self.set_tid.map(|set_tid| set_tid.len()).unwrap_or(0) as u64,
-            #[cfg(feature = "linux_5-7")]
-            cgroup: self.cgroup.map(AsRawFd::as_raw_fd).unwrap_or(0) as u64,
+            set_tid: option_slice_as_ptr(&self.set_tid.map(TidSpec::as_slice)) as u64,
+            set_tid_size: self.set_tid.map(TidSpec::len).unwrap_or(0) as u64,
+            cgroup: cgroup.map(|cgroup| cgroup.as_raw_fd()).unwrap_or(0) as u64,
         }
     }
 }
 
-fn find_incompatible_flags(flags: Flags) -> Option<String> {
+/// The flags of [`Clone3::sandboxed`].
+pub(crate) fn sandbox_flags() -> Flags {
+    let mut flags = Flags::NEWUSER
+        | Flags::NEWNS
+        | Flags::NEWPID
+        | Flags::NEWNET
+        | Flags::NEWIPC
+        | Flags::NEWUTS
+        | Flags::NEWCGROUP;
+    let size = crate::max_clone_args_size().unwrap_or(0);
+    flags.set(Flags::CLEAR_SIGHAND, size >= crate::CLONE_ARGS_SIZE_VER1);
+    flags
+}
+
+pub(crate) fn find_incompatible_flags(flags: Flags) -> Option<IncompatibleFlags> {
     use Flags as F;
 
     let mutually_exclusive = [
         (F::CHILD_CLEARTID, F::CHILD_SETTID),
-        #[cfg(feature = "linux_5-5")]
         (F::CLEAR_SIGHAND, F::SIGHAND),
         (F::NEWIPC, F::SYSVSEM),
         (F::FS, F::NEWNS),
@@ -266,30 +1213,86 @@ fn find_incompatible_flags(flags: Flags) -> Option<String> {
     ];
     for (left, right) in mutually_exclusive.as_ref() {
         if flags.contains(*left) && flags.intersects(*right) {
-            return Some(format!("{:?} and any of {:?} is set", left, right));
+            return Some(IncompatibleFlags::MutuallyExclusive {
+                flag: *left,
+                conflicting: *right,
+            });
         }
     }
 
     let implies = [(F::SIGHAND, F::VM), (F::THREAD, F::SIGHAND)];
     for (left, right) in implies.as_ref() {
         if flags.contains(*left) && !flags.contains(*right) {
-            return Some(format!("{:?} is set without {:?}", left, right));
+            return Some(IncompatibleFlags::MissingRequired {
+                flag: *left,
+                required: *right,
+            });
         }
     }
 
     None
 }
 
-fn handle_return_value(return_value: c_long) -> Result<pid_t, Errno> {
-    if return_value == -1 {
-        return Err(Errno::default());
+/// Returns why the kernel rejects `exit_signal` together with `flags`.
+pub(crate) fn find_invalid_exit_signal(flags: Flags, exit_signal: u64) -> Option<&'static str> {
+    // Only the low byte is a signal. The kernel rejects the other bits instead of ignoring them.
+    if exit_signal > crate::signal::MAX_SIGNAL as u64 {
+        return Some("not a signal number");
+    }
+    if exit_signal != 0 && flags.intersects(Flags::THREAD | Flags::PARENT) {
+        return Some("THREAD and PARENT require no exit signal");
+    }
+    None
+}
+
+/// Returns the size `supported` by the kernel if it is smaller than `size` and includes all set
+/// fields of `cl_args`.
+pub(crate) fn find_smaller_size(
+    cl_args: &CloneArgs,
+    size: usize,
+    supported: usize,
+) -> Option<usize> {
+    (supported < size && cl_args.min_size() <= supported).then_some(supported)
+}
+
+/// Returns why `cl_args` cannot be passed to the legacy clone system call.
+fn find_legacy_unsupported(cl_args: &CloneArgs) -> Option<&'static str> {
+    let flags = Flags::from_bits_truncate(cl_args.flags);
+    if cl_args.flags >> 32 != 0 {
+        Some("flags above the lower 32 bits like CLEAR_SIGHAND and INTO_CGROUP are not supported")
+    } else if cl_args.set_tid_size != 0 {
+        Some("set_tid is not supported")
+    } else if flags.contains(Flags::PIDFD | Flags::PARENT_SETTID) {
+        Some("PIDFD and PARENT_SETTID cannot be set together")
+    } else {
+        None
     }
-    Ok(return_value.try_into().unwrap_or_else(|err| {
-        panic!(
-            "could not convert successful clone3 system call result {} to pid_t: {:?}",
-            return_value, err
-        )
-    }))
+}
+
+/// Turns the failure of a system call that uses fields or flags the running kernel does not know
+/// into [`Clone3Error::UnsupportedByKernel`].
+pub(crate) fn classify_error(cl_args: &CloneArgs, errno: Errno) -> Clone3Error {
+    let required = cl_args.required_kernel_size();
+    // Old kernels fail with E2BIG for unknown fields and with EINVAL for unknown flags.
+    if required > crate::CLONE_ARGS_SIZE_VER0 && (errno.0 == E2BIG || errno.0 == EINVAL) {
+        if let Ok(supported) = crate::max_clone_args_size() {
+            if supported < required {
+                return Clone3Error::UnsupportedByKernel {
+                    required,
+                    supported,
+                    errno,
+                };
+            }
+        }
+    }
+    // A kernel that supports clone3 never fails with ENOSYS and only fails with EPERM for
+    // reasons unrelated to the probe.
+    if errno.0 == ENOSYS || errno.0 == EPERM {
+        if let Err(reason) = crate::check_clone3_available() {
+            return Clone3Error::Unavailable { reason, errno };
+        }
+    }
+    Clone3Error::System(errno)
 }
 
 fn option_as_mut_ptr<T>(o: &mut Option<&mut T>) -> *mut T {
@@ -299,7 +1302,6 @@ fn option_as_mut_ptr<T>(o: &mut Option<&mut T>) -> *mut T {
     }
 }
 
-#[cfg(feature = "linux_5-5")]
 fn option_slice_as_ptr<T>(o: &Option<&[T]>) -> *const T {
     match o {
         Some(inner) => inner.as_ptr(),
@@ -307,18 +1309,34 @@ fn option_slice_as_ptr<T>(o: &Option<&[T]>) -> *const T {
     }
 }
 
-fn option_slice_as_mut_ptr<T>(o: &mut Option<&mut [T]>) -> *mut T {
-    match o {
-        Some(inner) => inner.as_mut_ptr(),
-        None => std::ptr::null_mut(),
+/// The memory of the stack of the child. The borrow of a slice is tracked by the lifetime of
+/// [`Clone3`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RawStack {
+    pub(crate) ptr: NonNull<u8>,
+    pub(crate) len: usize,
+}
+
+// The memory is only used by the child.
+unsafe impl Send for RawStack {}
+unsafe impl Sync for RawStack {}
+
+impl From<&mut [MaybeUninit<u8>]> for RawStack {
+    fn from(stack: &mut [MaybeUninit<u8>]) -> Self {
+        let len = stack.len();
+        Self {
+            ptr: NonNull::from(stack).cast(),
+            len,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{mem, time::Duration};
-    use uapi::c::{siginfo_t, waitid, P_PIDFD, WEXITED, __WCLONE};
+    use crate::test_util::assert_child;
+    use crate::{ChildStatus, WaitOptions};
+    use std::{os::unix::io::AsFd, time::Duration};
 
     #[test]
     #[should_panic]
@@ -331,9 +1349,222 @@ mod tests {
     }
 
     #[test]
-    fn wait_for_child() {
+    fn call_maps_setup_errors() {
+        let mut clone3 = Clone3::default();
+        clone3.flag_files().pass_fds([(0, 10)]);
+        assert_eq!(unsafe { clone3.call() }, Err(Errno(EINVAL)));
+    }
+
+    #[test]
+    fn sandboxed() {
+        let (uid, gid) = unsafe { (c::getuid(), c::getgid()) };
+        let mut clone3 = Clone3::sandboxed();
+        clone3.userns_map(
+            &[IdMap {
+                inside: 0,
+                outside: uid,
+                count: 1,
+            }],
+            &[IdMap {
+                inside: 0,
+                outside: gid,
+                count: 1,
+            }],
+        );
+        assert_child(&mut clone3, || unsafe {
+            c::getpid() == 1 && c::getuid() == 0
+        });
+    }
+
+    #[test]
+    fn try_call_returns_incompatible() {
+        let mut clone3 = Clone3::default();
+        clone3.flag_thread();
+        let result = unsafe { clone3.try_call() };
+        assert!(matches!(
+            result,
+            Err(Clone3Error::IncompatibleFlags {
+                flags: Flags::THREAD,
+                reason: IncompatibleFlags::MissingRequired {
+                    flag: Flags::THREAD,
+                    required: Flags::SIGHAND,
+                },
+            })
+        ));
+    }
+
+    #[test]
+    fn fork_like() {
+        let child = match unsafe { Clone3::fork_like().spawn() }.unwrap() {
+            None => unsafe { c::_exit(5) },
+            Some(child) => child,
+        };
+        let mut status = 0;
+        assert_eq!(
+            unsafe { c::waitpid(child.pid().as_raw(), &mut status, 0) },
+            child.pid().as_raw()
+        );
+        assert!(c::WIFEXITED(status) && c::WEXITSTATUS(status) == 5);
+    }
+
+    #[test]
+    fn smaller_size_without_newer_fields() {
+        use crate::{CLONE_ARGS_SIZE_VER0, CLONE_ARGS_SIZE_VER1, CLONE_ARGS_SIZE_VER2};
+
+        let mut clone3 = Clone3::default();
+        let cl_args = clone3.as_clone_args();
+        let full = CLONE_ARGS_SIZE_VER2;
+        assert_eq!(
+            find_smaller_size(&cl_args, full, CLONE_ARGS_SIZE_VER0),
+            Some(CLONE_ARGS_SIZE_VER0)
+        );
+        assert_eq!(find_smaller_size(&cl_args, full, full), None);
+        let set_tid = TidSpec::from(&[1][..]);
+        clone3.set_tid(&set_tid);
+        let cl_args = clone3.as_clone_args();
+        assert_eq!(
+            find_smaller_size(&cl_args, full, CLONE_ARGS_SIZE_VER0),
+            None
+        );
+        assert_eq!(
+            find_smaller_size(&cl_args, full, CLONE_ARGS_SIZE_VER1),
+            Some(CLONE_ARGS_SIZE_VER1)
+        );
+    }
+
+    #[test]
+    fn io_errors() {
+        let mut clone3 = Clone3::default();
+        clone3.exit_signal(0x100);
+        let err = unsafe { clone3.call_io() }.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref()),
+            Some(Clone3Error::InvalidExitSignal { .. })
+        ));
+        let err = io::Error::from(Clone3Error::System(Errno(c::EAGAIN)));
+        assert_eq!(err.raw_os_error(), Some(c::EAGAIN));
+        clone3
+            .exit_signal(SIGCHLD as u64)
+            .flag_into_cgroup_path("/clone3-does-not-exist");
+        let err = unsafe { clone3.call_io() }.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(c::ENOENT));
+    }
+
+    #[test]
+    fn invalid_exit_signal() {
+        let mut clone3 = Clone3::default();
+        clone3.exit_signal(0x100 | SIGCHLD as u64);
+        assert!(matches!(
+            unsafe { clone3.try_call() },
+            Err(Clone3Error::InvalidExitSignal {
+                exit_signal: 0x111,
+                ..
+            })
+        ));
+        clone3.exit_signal(SIGCHLD as u64).flag_parent();
+        assert!(matches!(
+            unsafe { clone3.try_call() },
+            Err(Clone3Error::InvalidExitSignal { .. })
+        ));
+    }
+
+    #[test]
+    fn stack_alignment_and_size() {
+        let mut memory = vec![0u128; MIN_STACK_SIZE / 16 + 1];
+        let memory = unsafe {
+            std::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, memory.len() * 16)
+        };
+        let len = memory.len();
+        let mut clone3 = Clone3::default();
+        clone3.stack(&mut memory[1..len - 1]);
+        let cl_args = clone3.as_clone_args();
+        assert_eq!((cl_args.stack + cl_args.stack_size) % STACK_ALIGN as u64, 0);
+        assert_eq!(cl_args.stack_size as usize, len - 1 - STACK_ALIGN);
+        let mut small = [0u8; 64];
+        clone3.stack(&mut small);
+        assert!(matches!(
+            unsafe { clone3.try_call() },
+            Err(Clone3Error::StackTooSmall {
+                required: MIN_STACK_SIZE,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn uninit_stack() {
+        let mut memory = Box::<[u8]>::new_uninit_slice(MIN_STACK_SIZE);
+        let mut clone3 = Clone3::default();
+        clone3.stack_uninit(&mut memory);
+        let cl_args = clone3.as_clone_args();
+        assert_eq!(cl_args.stack, memory.as_ptr() as u64);
+        let mut owned = crate::OwnedClone3::default();
+        owned.stack(vec![0; MIN_STACK_SIZE]);
+        assert_ne!(owned.as_clone3().as_clone_args().stack, 0);
+    }
+
+    #[test]
+    fn raw_stack() {
+        let stack = crate::CloneStack::new(MIN_STACK_SIZE).unwrap();
+        let ptr = NonNull::new(stack.as_ptr() as *mut u8).unwrap();
+        let mut clone3 = Clone3::default();
+        unsafe { clone3.stack_raw(ptr, stack.len()) };
+        let cl_args = clone3.as_clone_args();
+        assert_eq!(cl_args.stack, ptr.as_ptr() as u64);
+        assert_eq!(cl_args.stack_size, stack.len() as u64);
+        let mut owned = crate::OwnedClone3::default();
+        unsafe { owned.stack_raw(ptr, stack.len()) };
+        assert_eq!(owned.as_clone3().as_clone_args().stack, cl_args.stack);
+    }
+
+    #[test]
+    fn check_consistency() {
+        assert_eq!(
+            (Flags::VM | Flags::SIGHAND | Flags::THREAD).check_consistency(),
+            Ok(())
+        );
+        assert_eq!(
+            (Flags::FS | Flags::NEWNS).check_consistency(),
+            Err(IncompatibleFlags::MutuallyExclusive {
+                flag: Flags::FS,
+                conflicting: Flags::NEWNS,
+            })
+        );
+        assert_eq!(
+            Flags::SIGHAND.check_consistency(),
+            Err(IncompatibleFlags::MissingRequired {
+                flag: Flags::SIGHAND,
+                required: Flags::VM,
+            })
+        );
+    }
+
+    #[test]
+    fn legacy_clone_writes_pidfd() {
         let mut pidfd = -1;
         let mut clone3 = Clone3::default();
+        clone3.flag_pidfd_raw(&mut pidfd);
+        let cl_args = clone3.as_clone_args();
+        assert_eq!(find_legacy_unsupported(&cl_args), None);
+        let child = match unsafe { crate::legacy_clone_system_call(&cl_args) } {
+            0 => unsafe { crate::sys::c::_exit(0) },
+            child => child as pid_t,
+        };
+        assert!(child > 0);
+        assert!(pidfd >= 0);
+        let _pidfd = unsafe { OwnedFd::from_raw_fd(pidfd) };
+        // Without an exit signal the child is only reaped with __WALL.
+        let mut status = 0;
+        let waited = unsafe { crate::sys::c::waitpid(child, &mut status, crate::sys::c::__WALL) };
+        assert_eq!(waited, child);
+        assert_eq!(status, 0);
+    }
+
+    #[test]
+    fn wait_for_child() {
+        let mut pidfd = None;
+        let mut clone3 = Clone3::default();
         clone3.flag_pidfd(&mut pidfd);
         let child_pid = match unsafe { clone3.call() }.unwrap() {
             0 => {
@@ -345,24 +1576,14 @@ mod tests {
             }
             child_pid => child_pid,
         };
-        assert!(pidfd >= 0);
+        let pidfd = pidfd.unwrap();
 
         println!(
-            "parent: waiting for child pid {} to exit on pidfd {}",
+            "parent: waiting for child pid {} to exit on pidfd {:?}",
             child_pid, pidfd
         );
-        // vk: Not sure how else to construct a siginfo_t.
-        let mut siginfo = [0u8; mem::size_of::<siginfo_t>()];
-        let return_value = unsafe {
-            // We need __WCLONE because we have not set exit_signal to SIGCHLD.
-            waitid(
-                P_PIDFD,
-                pidfd as _,
-                siginfo.as_mut_ptr() as *mut _,
-                WEXITED | __WCLONE,
-            )
-        };
-        assert!(return_value != -1, "waitid failed: {:?}", Errno::default());
+        let status = crate::wait_pidfd(pidfd.as_fd(), WaitOptions::empty());
+        assert_eq!(status, Ok(Some(ChildStatus::Exited(0))));
         println!("parent: child has exited");
     }
 }